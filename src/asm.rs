@@ -0,0 +1,554 @@
+use crate::{
+    emulator::{
+        instruction::{encode, Instruction},
+        State,
+    },
+    error::{AsmError, ErrorKind},
+    utils::parse_number,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Address the ROM is loaded at, which is where the first assembled byte ends up.
+const START: usize = 0x200;
+/// How deeply constants may be defined in terms of other constants, which catches cycles.
+const MAX_DEPTH: usize = 32;
+/// Operand names which can't be used as labels or constants.
+const RESERVED: [&str; 10] = ["I", "[I]", "DT", "ST", "K", "F", "HF", "B", "R", "LONG"];
+
+/// A value which can only be worked out once every label is known. It is a sum of numbers and
+/// symbols, with the flag on each term saying whether it is subtracted instead.
+#[derive(Clone, Debug)]
+struct Expr(Vec<(bool, Term)>);
+
+#[derive(Clone, Debug)]
+enum Term {
+    Number(i64),
+    Symbol(String),
+}
+
+/// A single operand of an instruction.
+#[derive(Clone, Debug)]
+enum Operand {
+    /// One of the registers V0 to VF.
+    Reg(u8),
+    /// The address register, `I`.
+    I,
+    /// The memory pointed to by I, `[I]`.
+    IndirectI,
+    /// The delay timer, `DT`.
+    Dt,
+    /// The sound timer, `ST`.
+    St,
+    /// A key press, `K`.
+    K,
+    /// A small font sprite, `F`.
+    F,
+    /// A big font sprite, `HF`.
+    Hf,
+    /// A BCD representation, `B`.
+    B,
+    /// The RPL user flags, `R`.
+    R,
+    /// A 16-bit address, written `LONG nnnn`.
+    Long(Expr),
+    /// A number, label or constant.
+    Value(Expr),
+}
+
+/// One line of source which produces bytes.
+#[derive(Debug)]
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    /// A `db` or `dw` directive, with the size in bytes of each value.
+    Data { size: usize, values: Vec<Expr> },
+}
+
+/// Assemble `source` into a ROM, which should be loaded at 0x200.
+///
+/// Each line holds an optional `label:`, then either an instruction or a `db`/`dw` directive,
+/// and anything after a `;` is a comment. Mnemonics are the same ones the disassembler prints.
+/// Lines of the form `NAME = value` define constants. Numbers may be decimal, hex (`#2A`, `$2A` or
+/// `0x2A`) or binary (`%1010` or `0b1010`), and may be added to and subtracted from labels and
+/// constants. Within `db` and `dw`, a row of `#` and `.` characters is a sprite bitmap, with `#`
+/// for each set pixel.
+///
+/// Every problem found is returned, rather than just the first.
+pub fn assemble(source: &str) -> Result<Vec<u8>, Vec<AsmError>> {
+    let mut errors = Vec::new();
+    let mut symbols = HashMap::new();
+    let mut statements = Vec::new();
+    let mut addr = START;
+
+    // First pass, which finds the address of every label
+    for (idx, line) in source.lines().enumerate() {
+        let line_no = idx + 1;
+        let mut define = |name: &str, value: Expr| {
+            if RESERVED.contains(&name.to_ascii_uppercase().as_str()) || register(name).is_some() {
+                Err(format!("`{}` is a reserved name", name))
+            } else if symbols.insert(name.to_string(), value).is_some() {
+                Err(format!("`{}` is defined more than once", name))
+            } else {
+                Ok(())
+            }
+        };
+        let mut text = line.split(';').next().unwrap_or("").trim();
+
+        if let Some((name, value)) = split_once(text, '=') {
+            if is_identifier(name) {
+                if let Err(message) = parse_expr(value).and_then(|value| define(name, value)) {
+                    errors.push(AsmError {
+                        line: line_no,
+                        message,
+                    });
+                }
+                continue;
+            }
+        }
+        if let Some((name, rest)) = split_once(text, ':') {
+            if is_identifier(name) {
+                let label = Expr(vec![(false, Term::Number(addr as i64))]);
+                if let Err(message) = define(name, label) {
+                    errors.push(AsmError {
+                        line: line_no,
+                        message,
+                    });
+                }
+                text = rest;
+            }
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        match parse_statement(text) {
+            Ok(statement) => {
+                let size = match &statement {
+                    Statement::Instruction { mnemonic, operands } => match operands.as_slice() {
+                        [Operand::I, Operand::Long(_)] if mnemonic == "LD" => 4,
+                        _ => 2,
+                    },
+                    Statement::Data { size, values } => size * values.len(),
+                };
+                if addr <= State::MEM_SIZE && addr + size > State::MEM_SIZE {
+                    errors.push(AsmError {
+                        line: line_no,
+                        message: "program is too large to fit in memory".to_string(),
+                    });
+                }
+                addr += size;
+                statements.push((line_no, statement));
+            }
+            Err(message) => errors.push(AsmError {
+                line: line_no,
+                message,
+            }),
+        }
+    }
+
+    // Second pass, which encodes everything now that labels are known
+    let mut rom = Vec::new();
+    for (line_no, statement) in statements {
+        let bytes = match statement {
+            Statement::Instruction { mnemonic, operands } => {
+                encode_instruction(&mnemonic, &operands, &symbols)
+            }
+            Statement::Data { size, values } => values
+                .iter()
+                .map(|value| match size {
+                    1 => ranged(value, &symbols, -0x80, 0xFF, "byte").map(|v| vec![v as u8]),
+                    _ => ranged(value, &symbols, -0x8000, 0xFFFF, "word")
+                        .map(|v| (v as u16).to_be_bytes().to_vec()),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(|words| words.concat()),
+        };
+        match bytes {
+            Ok(bytes) => rom.extend(bytes),
+            Err(message) => errors.push(AsmError {
+                line: line_no,
+                message,
+            }),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(rom)
+    } else {
+        errors.sort_by_key(|e| e.line);
+        Err(errors)
+    }
+}
+
+/// Split `text` around the first `pat`, trimming both halves.
+fn split_once(text: &str, pat: char) -> Option<(&str, &str)> {
+    let idx = text.find(pat)?;
+    Some((text[..idx].trim(), text[idx + 1..].trim()))
+}
+
+/// Whether `text` is a valid label or constant name.
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// The index of the register named by `text`, such as 0xA for `VA`.
+fn register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(digit), None) | (Some('v'), Some(digit), None) => {
+            digit.to_digit(16).map(|d| d as u8)
+        }
+        _ => None,
+    }
+}
+
+/// Parse an instruction or data directive, without its label.
+fn parse_statement(text: &str) -> Result<Statement, String> {
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(idx) => (&text[..idx], text[idx..].trim()),
+        None => (text, ""),
+    };
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let operands: Vec<&str> = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(str::trim).collect()
+    };
+    if operands.iter().any(|operand| operand.is_empty()) {
+        return Err(format!("missing operand in `{}`", text));
+    }
+
+    match mnemonic.as_str() {
+        "DB" | "DW" => {
+            let size = if mnemonic == "DB" { 1 } else { 2 };
+            if operands.is_empty() {
+                return Err(format!("{} needs at least one value", mnemonic));
+            }
+            let values = operands
+                .iter()
+                .map(|operand| match parse_bitmap(operand, size * 8) {
+                    Some(bitmap) => bitmap.map(|v| Expr(vec![(false, Term::Number(v))])),
+                    None => parse_expr(operand),
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Statement::Data { size, values })
+        }
+        _ => Ok(Statement::Instruction {
+            operands: operands
+                .into_iter()
+                .map(parse_operand)
+                .collect::<Result<_, _>>()?,
+            mnemonic,
+        }),
+    }
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    let upper = text.to_ascii_uppercase();
+    Ok(match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "HF" => Operand::Hf,
+        "B" => Operand::B,
+        "R" => Operand::R,
+        _ => match register(text) {
+            Some(x) => Operand::Reg(x),
+            None if upper.starts_with("LONG ") => Operand::Long(parse_expr(&text[5..])?),
+            None => Operand::Value(parse_expr(text)?),
+        },
+    })
+}
+
+/// Parse a sprite row such as `##..##..` into a value `width` bits wide, or return `None` if
+/// `text` isn't a sprite row.
+fn parse_bitmap(text: &str, width: usize) -> Option<Result<i64, String>> {
+    if !text.chars().all(|c| c == '#' || c == '.') {
+        return None;
+    }
+    if text.len() > width {
+        return Some(Err(format!(
+            "sprite row `{}` is wider than {} pixels",
+            text, width
+        )));
+    }
+    let bits = text.chars().fold(0, |acc, c| acc << 1 | (c == '#') as i64);
+    Some(Ok(bits << (width - text.len())))
+}
+
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    let text = text.trim();
+    let (mut negate, mut rest) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let mut terms = Vec::new();
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        terms.push((negate, parse_term(rest[..end].trim())?));
+        match rest[end..].chars().next() {
+            Some(op) => {
+                negate = op == '-';
+                rest = &rest[end + 1..];
+            }
+            None => return Ok(Expr(terms)),
+        }
+    }
+}
+
+fn parse_term(text: &str) -> Result<Term, String> {
+    let lower = text.to_ascii_lowercase();
+    let value = if let Some(hex) = lower.strip_prefix('#').or_else(|| lower.strip_prefix('$')) {
+        parse_number(&format!("0x{}", hex))
+    } else if let Some(bin) = lower.strip_prefix('%') {
+        parse_number(&format!("0b{}", bin))
+    } else if is_identifier(text) {
+        return Ok(Term::Symbol(text.to_string()));
+    } else if text.is_empty() {
+        return Err("expected a value".to_string());
+    } else {
+        parse_number(text)
+    };
+    value
+        .map(Term::Number)
+        .ok_or_else(|| format!("`{}` is not a number or name", text))
+}
+
+/// Work out the value of `expr`, failing on undefined symbols.
+fn eval(expr: &Expr, symbols: &HashMap<String, Expr>, depth: usize) -> Result<i64, String> {
+    if depth > MAX_DEPTH {
+        return Err("constant is defined in terms of itself".to_string());
+    }
+    expr.0.iter().try_fold(0, |acc: i64, (negate, term)| {
+        let value = match term {
+            Term::Number(n) => *n,
+            Term::Symbol(name) => match symbols.get(name) {
+                Some(expr) => eval(expr, symbols, depth + 1)?,
+                None => return Err(format!("`{}` is not defined", name)),
+            },
+        };
+        Ok(if *negate {
+            acc.wrapping_sub(value)
+        } else {
+            acc.wrapping_add(value)
+        })
+    })
+}
+
+/// Work out the value of `expr`, failing unless it's between `min` and `max` inclusive.
+fn ranged(
+    expr: &Expr,
+    symbols: &HashMap<String, Expr>,
+    min: i64,
+    max: i64,
+    what: &str,
+) -> Result<i64, String> {
+    let value = eval(expr, symbols, 0)?;
+    let hex = |n: i64| format!("{}#{:X}", if n < 0 { "-" } else { "" }, n.abs());
+    if value < min || value > max {
+        Err(format!(
+            "{} {} is out of range, it must be between {} and {}",
+            what,
+            hex(value),
+            hex(min),
+            hex(max)
+        ))
+    } else {
+        Ok(value)
+    }
+}
+
+/// Encode a single instruction into its bytes.
+fn encode_instruction(
+    mnemonic: &str,
+    operands: &[Operand],
+    symbols: &HashMap<String, Expr>,
+) -> Result<Vec<u8>, String> {
+    use Instruction::*;
+    use Operand::*;
+
+    let nibble = |expr| ranged(expr, symbols, 0, 0xF, "nibble").map(|v| v as u8);
+    let byte = |expr| ranged(expr, symbols, -0x80, 0xFF, "byte").map(|v| v as u8);
+    let addr = |expr| ranged(expr, symbols, 0, 0xFFF, "address").map(|v| v as u16);
+
+    let instruction = match (mnemonic, operands) {
+        ("SCD", [Value(n)]) => ScrollDown(nibble(n)?),
+        ("SCU", [Value(n)]) => ScrollUp(nibble(n)?),
+        ("CLS", []) => Clear,
+        ("RET", []) => Return,
+        ("SCR", []) => ScrollRight,
+        ("SCL", []) => ScrollLeft,
+        ("EXIT", []) => Exit,
+        ("LOW", []) => LowRes,
+        ("HIGH", []) => HighRes,
+        ("JP", [Value(nnn)]) => Jump(addr(nnn)?),
+        ("JP", [Reg(0), Value(nnn)]) => JumpOffset(addr(nnn)?),
+        ("CALL", [Value(nnn)]) => Call(addr(nnn)?),
+        ("SE", [Reg(x), Value(kk)]) => SkipEqual(*x, byte(kk)?),
+        ("SE", [Reg(x), Reg(y)]) => SkipRegEqual(*x, *y),
+        ("SNE", [Reg(x), Value(kk)]) => SkipNotEqual(*x, byte(kk)?),
+        ("SNE", [Reg(x), Reg(y)]) => SkipRegNotEqual(*x, *y),
+        ("SAVE", [Reg(x), Reg(y)]) => SaveRange(*x, *y),
+        ("LOAD", [Reg(x), Reg(y)]) => LoadRange(*x, *y),
+        ("LD", [Reg(x), Value(kk)]) => Load(*x, byte(kk)?),
+        ("LD", [Reg(x), Reg(y)]) => Move(*x, *y),
+        ("LD", [I, Value(nnn)]) => LoadI(addr(nnn)?),
+        ("LD", [I, Long(nnnn)]) => {
+            let nnnn = ranged(nnnn, symbols, 0, 0xFFFF, "address")? as u16;
+            let mut bytes = encode(LoadLongI).to_be_bytes().to_vec();
+            bytes.extend_from_slice(&nnnn.to_be_bytes());
+            return Ok(bytes);
+        }
+        ("LD", [Reg(x), Dt]) => GetDelay(*x),
+        ("LD", [Reg(x), K]) => WaitKey(*x),
+        ("LD", [Dt, Reg(x)]) => SetDelay(*x),
+        ("LD", [St, Reg(x)]) => SetSound(*x),
+        ("LD", [F, Reg(x)]) => Font(*x),
+        ("LD", [Hf, Reg(x)]) => BigFont(*x),
+        ("LD", [B, Reg(x)]) => Bcd(*x),
+        ("LD", [IndirectI, Reg(x)]) => Store(*x),
+        ("LD", [Reg(x), IndirectI]) => Restore(*x),
+        ("LD", [R, Reg(x)]) => SaveFlags(*x),
+        ("LD", [Reg(x), R]) => LoadFlags(*x),
+        ("ADD", [Reg(x), Value(kk)]) => AddByte(*x, byte(kk)?),
+        ("ADD", [Reg(x), Reg(y)]) => Add(*x, *y),
+        ("ADD", [I, Reg(x)]) => AddI(*x),
+        ("OR", [Reg(x), Reg(y)]) => Or(*x, *y),
+        ("AND", [Reg(x), Reg(y)]) => And(*x, *y),
+        ("XOR", [Reg(x), Reg(y)]) => Xor(*x, *y),
+        ("SUB", [Reg(x), Reg(y)]) => Sub(*x, *y),
+        ("SHR", [Reg(x)]) => ShiftRight(*x, *x),
+        ("SHR", [Reg(x), Reg(y)]) => ShiftRight(*x, *y),
+        ("SUBN", [Reg(x), Reg(y)]) => SubN(*x, *y),
+        ("SHL", [Reg(x)]) => ShiftLeft(*x, *x),
+        ("SHL", [Reg(x), Reg(y)]) => ShiftLeft(*x, *y),
+        ("RND", [Reg(x), Value(kk)]) => Random(*x, byte(kk)?),
+        ("DRW", [Reg(x), Reg(y), Value(n)]) => Draw(*x, *y, nibble(n)?),
+        ("SKP", [Reg(x)]) => SkipPressed(*x),
+        ("SKNP", [Reg(x)]) => SkipNotPressed(*x),
+        ("PLANE", [Value(n)]) => Plane(nibble(n)?),
+        ("AUDIO", []) => LoadAudio,
+        ("PITCH", [Reg(x)]) => Pitch(*x),
+        _ => return Err(format!("`{}` is not a valid instruction", mnemonic)),
+    };
+    Ok(encode(instruction).to_be_bytes().to_vec())
+}
+
+/// Assemble the source at `source_path`, writing the ROM to `output`, or beside the source with
+/// a `.ch8` extension. If the source has errors, nothing is written, and every error is returned
+/// in `ErrorKind::AsmErr` with its line number, for the caller to report.
+pub fn run(source_path: &Path, output: Option<&Path>) -> Result<(), ErrorKind> {
+    let source = fs::read_to_string(source_path)?;
+    match assemble(&source) {
+        Ok(rom) => {
+            let output = output
+                .map(PathBuf::from)
+                .unwrap_or_else(|| source_path.with_extension("ch8"));
+            fs::write(output, rom)?;
+            Ok(())
+        }
+        Err(errors) => Err(ErrorKind::AsmErr(errors)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{disasm::disassemble, emulator::Quirks};
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            ; Draw a sprite, then loop forever
+            X = 10
+            start:  LD V0, X + 2
+                    LD V1, #04
+                    LD I, sprite
+                    DRW V0, V1, sprite_end - sprite
+                    LD I, LONG big
+            loop:   JP loop
+            sprite: db ##..##.., $FF
+            sprite_end:
+            big:    dw ################, 0b1
+        ";
+        let rom = assemble(source).unwrap();
+        assert_eq!(
+            vec![
+                0x60, 0x0C, 0x61, 0x04, 0xA2, 0x0E, 0xD0, 0x12, 0xF0, 0x00, 0x02, 0x10, 0x12, 0x0C,
+                0xCC, 0xFF, 0xFF, 0xFF, 0x00, 0x01
+            ],
+            rom
+        );
+
+        let mut state = State::new(&rom, Quirks::default());
+        for _ in 0..4 {
+            state.step_forward().unwrap();
+        }
+        assert_eq!(
+            (12, 4, 0x20E),
+            (state.reg_v[0], state.reg_v[1], state.reg_i)
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // Everything the disassembler prints, minus the address and raw bytes, should assemble
+        let res = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res");
+        for entry in std::fs::read_dir(res).unwrap() {
+            let path = entry.unwrap().path();
+            let rom = std::fs::read(&path).unwrap();
+            let mut listing = Vec::new();
+            disassemble(&rom, &mut listing).unwrap();
+            let source: String = String::from_utf8(listing)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    if line.ends_with(':') {
+                        line
+                    } else {
+                        &line[21..]
+                    }
+                })
+                .map(|line| format!("{}\n", line))
+                .collect();
+            match assemble(&source) {
+                Ok(assembled) => assert_eq!(rom, assembled, "{}", path.display()),
+                Err(errors) => panic!("{} didn't assemble: {:?}", path.display(), errors),
+            }
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let source = "
+            LD V0, #100
+            JP nowhere
+            CLS V0
+            LD I, #1000
+            dup: CLS
+            dup: CLS
+            DRW V0, V1
+        ";
+        let lines: Vec<_> = assemble(source)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.line)
+            .collect();
+        assert_eq!(vec![2, 3, 4, 5, 7, 8], lines);
+    }
+}
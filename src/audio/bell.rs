@@ -0,0 +1,36 @@
+use crate::{
+    audio::{Audio, Sound},
+    error::ErrorKind,
+};
+use std::{
+    io::{stdout, Write},
+    time::Duration,
+};
+
+/// Rings the terminal bell each time the tone starts. A bell can't be held for as long as the
+/// sound timer runs, and its pitch is up to the terminal, so the `Tone` options and XO-CHIP audio
+/// patterns do nothing here.
+#[derive(Default)]
+pub struct BellAudio {
+    /// Whether the tone was on in the last frame.
+    playing: bool,
+}
+
+impl BellAudio {
+    pub fn new() -> Self {
+        BellAudio { playing: false }
+    }
+}
+
+impl Audio for BellAudio {
+    fn update(&mut self, sound: Sound, _duration: Duration) -> Result<(), ErrorKind> {
+        let playing = sound.playing();
+        if playing && !self.playing {
+            let mut stdout = stdout();
+            stdout.write_all(b"\x07")?;
+            stdout.flush()?;
+        }
+        self.playing = playing;
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{
+    audio::{Audio, Oscillator, Sound, Tone},
+    error::ErrorKind,
+};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Sample, SampleFormat, StreamConfig,
+};
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Plays the sound through the default audio output device. Samples are generated on the audio
+/// thread, which only needs to know what is currently playing.
+pub struct DeviceAudio {
+    sound: Arc<Mutex<Sound>>,
+    /// Playback stops once the stream is dropped.
+    _stream: cpal::Stream,
+}
+
+impl DeviceAudio {
+    /// Open the default output device and start a silent stream on it.
+    pub fn new(tone: Tone) -> Result<Self, ErrorKind> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| ErrorKind::AudioErr(String::from("No audio output device")))?;
+        let supported = device.default_output_config().map_err(audio_err)?;
+        let config = supported.config();
+        let sound = Arc::new(Mutex::new(Sound::Silent));
+
+        let shared = Arc::clone(&sound);
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, tone, shared),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, tone, shared),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, tone, shared),
+        }?;
+        stream.play().map_err(audio_err)?;
+
+        Ok(DeviceAudio {
+            sound,
+            _stream: stream,
+        })
+    }
+}
+
+impl Audio for DeviceAudio {
+    fn update(&mut self, sound: Sound, _duration: Duration) -> Result<(), ErrorKind> {
+        // The audio thread only holds the lock to copy the sound, so it can't be poisoned
+        *self.sound.lock().expect("audio lock poisoned") = sound;
+        Ok(())
+    }
+}
+
+/// Build an output stream for samples of type `T`, which plays `sound` on every channel, using
+/// `tone` for the plain CHIP-8 tone.
+fn build_stream<T: Sample>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    tone: Tone,
+    sound: Arc<Mutex<Sound>>,
+) -> Result<cpal::Stream, ErrorKind> {
+    let channels = config.channels as usize;
+    let mut osc = Oscillator::new(tone, config.sample_rate.0);
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let current = *sound.lock().expect("audio lock poisoned");
+                for frame in data.chunks_mut(channels) {
+                    let value = osc.next(current);
+                    for sample in frame {
+                        *sample = T::from(&value);
+                    }
+                }
+            },
+            |e| log::error!("Audio stream failed: {}", e),
+        )
+        .map_err(audio_err)
+}
+
+fn audio_err(e: impl Display) -> ErrorKind {
+    ErrorKind::AudioErr(e.to_string())
+}
@@ -0,0 +1,286 @@
+use crate::{
+    emulator::State,
+    error::{AudioKindError, ErrorKind, WaveformError},
+};
+use std::{f32::consts::PI, path::Path, str::FromStr, time::Duration};
+
+/// Rings the terminal bell whenever the tone starts.
+pub mod bell;
+/// Plays the tone through the default audio device, when built with the `device-audio` feature.
+#[cfg(feature = "device-audio")]
+pub mod device;
+/// A backend which plays nothing.
+pub mod silent;
+/// Records the tone to a WAV file.
+pub mod wav;
+
+/// Rings the terminal bell whenever the tone starts.
+pub use bell::BellAudio;
+/// Plays the tone through the default audio device, when built with the `device-audio` feature.
+#[cfg(feature = "device-audio")]
+pub use device::DeviceAudio;
+/// A backend which plays nothing.
+pub use silent::SilentAudio;
+/// Records the tone to a WAV file.
+pub use wav::WavAudio;
+
+/// The `Audio` trait describes types which can play the sound of the CHIP-8, which sounds
+/// whenever the sound timer is above zero. `Audio::update` is called once per frame, with what
+/// is playing and how long the frame lasts.
+pub trait Audio {
+    fn update(&mut self, sound: Sound, duration: Duration) -> Result<(), ErrorKind>;
+}
+
+/// What the CHIP-8 plays during a frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Sound {
+    /// Nothing, since the sound timer isn't running.
+    Silent,
+    /// The `Tone` chosen by the user.
+    Tone,
+    /// An XO-CHIP audio pattern of 128 1-bit samples, most significant bit first, repeated at the
+    /// rate given by `pitch`.
+    Pattern { pattern: [u8; 16], pitch: u8 },
+}
+
+impl Sound {
+    /// What `state` is playing. While the sound timer runs, the audio pattern buffer is played
+    /// once a program has loaded anything into it with `F002`, and the `Tone` otherwise.
+    pub fn of(state: &State) -> Sound {
+        if state.sound == 0 {
+            Sound::Silent
+        } else if state.audio.iter().any(|&byte| byte != 0) {
+            Sound::Pattern {
+                pattern: state.audio,
+                pitch: state.pitch,
+            }
+        } else {
+            Sound::Tone
+        }
+    }
+
+    /// Whether anything is playing.
+    pub fn playing(self) -> bool {
+        self != Sound::Silent
+    }
+}
+
+/// The rate in samples per second at which an XO-CHIP audio pattern plays at `pitch`, which is
+/// 4000 at the default pitch of 64, and doubles for every 48 above it.
+pub fn pattern_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+/// The shape of the wave played as the tone.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    /// The value of the wave, from -1 to 1, at `phase` through a cycle, where `phase` is from 0
+    /// to 1.
+    pub fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square if phase < 0.5 => 1.0,
+            Waveform::Square => -1.0,
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+        }
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = WaveformError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use Waveform::*;
+        Ok(match s.trim().to_lowercase().as_str() {
+            "square" => Square,
+            "triangle" => Triangle,
+            "sawtooth" | "saw" => Sawtooth,
+            "sine" => Sine,
+            _ => return Err(WaveformError(s.to_owned())),
+        })
+    }
+}
+
+/// The tone played while the sound timer is above zero.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tone {
+    /// Pitch of the tone, in hertz.
+    pub frequency: f32,
+    pub waveform: Waveform,
+    /// Loudness of the tone, from 0 for silence to 1 for full volume.
+    pub volume: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+        }
+    }
+}
+
+/// Generates the samples of a `Tone`, or of an XO-CHIP audio pattern, at a fixed sample rate.
+/// The phase is kept between samples, so the wave stays continuous however the samples are split
+/// into buffers.
+pub struct Oscillator {
+    tone: Tone,
+    sample_rate: u32,
+    /// How far through a cycle the next sample is, from 0 to 1.
+    phase: f32,
+    /// How far through the audio pattern the next sample is, from 0 to 128 pattern samples.
+    position: f64,
+}
+
+impl Oscillator {
+    pub fn new(tone: Tone, sample_rate: u32) -> Self {
+        Oscillator {
+            tone,
+            sample_rate,
+            phase: 0.0,
+            position: 0.0,
+        }
+    }
+
+    /// The next sample of `sound`, from -1 to 1, scaled by the volume of the tone.
+    pub fn next(&mut self, sound: Sound) -> f32 {
+        match sound {
+            Sound::Silent => 0.0,
+            Sound::Tone => self.next_sample(),
+            Sound::Pattern { pattern, pitch } => self.next_pattern_sample(&pattern, pitch),
+        }
+    }
+
+    /// The next sample of the tone, from -1 to 1, scaled by its volume.
+    pub fn next_sample(&mut self) -> f32 {
+        let sample = self.tone.waveform.sample(self.phase) * self.tone.volume.clamp(0.0, 1.0);
+        self.phase = (self.phase + self.tone.frequency / self.sample_rate as f32).fract();
+        sample
+    }
+
+    /// The next sample of the audio pattern `pattern` played at `pitch`, which is the volume of
+    /// the tone for bits which are set, and its negative for those which aren't.
+    pub fn next_pattern_sample(&mut self, pattern: &[u8; 16], pitch: u8) -> f32 {
+        let bit = self.position as usize;
+        let set = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+        let step = pattern_rate(pitch) / self.sample_rate as f64;
+        self.position = (self.position + step) % 128.0;
+        let volume = self.tone.volume.clamp(0.0, 1.0);
+        if set {
+            volume
+        } else {
+            -volume
+        }
+    }
+}
+
+/// Represents the avaliable choices of audio backend.
+#[derive(Copy, Clone, Debug)]
+pub enum AudioKind {
+    Silent,
+    Bell,
+    Device,
+    Wav,
+}
+
+impl AudioKind {
+    /// Create the backend, playing `tone`. The `Wav` backend writes to `wav_path`.
+    pub fn to_backend(&self, tone: Tone, wav_path: &Path) -> Result<Box<dyn Audio>, ErrorKind> {
+        Ok(match *self {
+            Self::Silent => Box::new(SilentAudio),
+            Self::Bell => Box::new(BellAudio::new()),
+            #[cfg(feature = "device-audio")]
+            Self::Device => Box::new(DeviceAudio::new(tone)?),
+            #[cfg(not(feature = "device-audio"))]
+            Self::Device => {
+                return Err(ErrorKind::AudioErr(String::from(
+                    "Built without the device-audio feature",
+                )))
+            }
+            Self::Wav => Box::new(WavAudio::create(wav_path, tone)?),
+        })
+    }
+}
+
+impl FromStr for AudioKind {
+    type Err = AudioKindError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use AudioKind::*;
+        Ok(match s.trim().to_lowercase().as_str() {
+            "none" | "silent" => Silent,
+            "bell" => Bell,
+            "device" => Device,
+            "wav" => Wav,
+            _ => return Err(AudioKindError(s.to_owned())),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_waveform() {
+        let samples = |waveform: Waveform| {
+            let samples: Vec<_> = [0.0, 0.25, 0.5, 0.75]
+                .iter()
+                .map(|&phase| waveform.sample(phase))
+                .collect();
+            samples
+        };
+        assert_eq!(vec![1.0, 1.0, -1.0, -1.0], samples(Waveform::Square));
+        assert_eq!(vec![-1.0, 0.0, 1.0, 0.0], samples(Waveform::Triangle));
+        assert_eq!(vec![-1.0, -0.5, 0.0, 0.5], samples(Waveform::Sawtooth));
+        assert!(samples(Waveform::Sine)
+            .iter()
+            .zip(&[0.0, 1.0, 0.0, -1.0])
+            .all(|(a, b)| (a - b).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_oscillator() {
+        let tone = Tone {
+            frequency: 1000.0,
+            waveform: Waveform::Square,
+            volume: 0.5,
+        };
+        // Four samples per cycle, which carry on across calls
+        let mut osc = Oscillator::new(tone, 4000);
+        let samples: Vec<_> = (0..6).map(|_| osc.next_sample()).collect();
+        assert_eq!(vec![0.5, 0.5, -0.5, -0.5, 0.5, 0.5], samples);
+    }
+
+    #[test]
+    fn test_pattern() {
+        assert_eq!(4000.0, pattern_rate(64));
+        assert_eq!(8000.0, pattern_rate(112));
+        assert_eq!(2000.0, pattern_rate(16));
+
+        let mut pattern = [0; 16];
+        pattern[0] = 0b1010_0000;
+        let sound = Sound::Pattern { pattern, pitch: 64 };
+        // One sample of output for each bit of the pattern at the default pitch
+        let mut osc = Oscillator::new(Tone::default(), 4000);
+        let samples: Vec<_> = (0..4).map(|_| osc.next(sound)).collect();
+        assert_eq!(vec![0.25, -0.25, 0.25, -0.25], samples);
+        assert_eq!(0.0, osc.next(Sound::Silent));
+
+        let mut state = State::new(&[], Default::default());
+        assert_eq!(Sound::Silent, Sound::of(&state));
+        state.sound = 10;
+        assert_eq!(Sound::Tone, Sound::of(&state));
+        state.audio = pattern;
+        assert_eq!(sound, Sound::of(&state));
+    }
+}
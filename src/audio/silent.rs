@@ -0,0 +1,14 @@
+use crate::{
+    audio::{Audio, Sound},
+    error::ErrorKind,
+};
+use std::time::Duration;
+
+/// A backend which plays nothing, for when sound isn't wanted.
+pub struct SilentAudio;
+
+impl Audio for SilentAudio {
+    fn update(&mut self, _sound: Sound, _duration: Duration) -> Result<(), ErrorKind> {
+        Ok(())
+    }
+}
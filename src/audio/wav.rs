@@ -0,0 +1,164 @@
+use crate::{
+    audio::{Audio, Oscillator, Sound, Tone},
+    error::ErrorKind,
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+
+/// Sample rate of the WAV files written.
+pub const SAMPLE_RATE: u32 = 44100;
+/// Size of the header before the samples.
+const HEADER_SIZE: u32 = 44;
+/// The most samples a WAV file can hold, as its header gives the size of the file after the first
+/// 8 bytes as 32 bits. This is a little over 13.5 hours at `SAMPLE_RATE`.
+const MAX_SAMPLES: u32 = (u32::MAX - (HEADER_SIZE - 8)) / 2;
+
+/// Records the sound to a 16-bit mono WAV file, with silence while it is off, so the recording
+/// lasts as long as the session. This needs no audio hardware, so it also suits tests.
+///
+/// The sizes in the header are kept up to date after every frame, so the file can be played even
+/// if the emulator doesn't exit cleanly. WAV files can't be larger than 4 GiB, so recording stops
+/// once the file is full, after about 13.5 hours, and the session carries on without it.
+pub struct WavAudio<W: Write + Seek> {
+    out: W,
+    osc: Oscillator,
+    /// The fraction of a sample left over from previous frames.
+    owed: f64,
+    /// Number of samples written so far.
+    samples: u32,
+}
+
+impl WavAudio<BufWriter<File>> {
+    /// Start recording to a new file at `path`.
+    pub fn create(path: impl AsRef<Path>, tone: Tone) -> Result<Self, ErrorKind> {
+        WavAudio::new(BufWriter::new(File::create(path)?), tone)
+    }
+}
+
+impl<W: Write + Seek> WavAudio<W> {
+    /// Start recording to `out`, writing the header straight away.
+    pub fn new(out: W, tone: Tone) -> Result<Self, ErrorKind> {
+        let mut res = WavAudio {
+            out,
+            osc: Oscillator::new(tone, SAMPLE_RATE),
+            owed: 0.0,
+            samples: 0,
+        };
+        res.write_header()?;
+        Ok(res)
+    }
+
+    /// Stop recording, giving back the writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Write the header at the start of the file, then go back to the end.
+    fn write_header(&mut self) -> Result<(), ErrorKind> {
+        let data_size = self.samples * 2;
+        let w = &mut self.out;
+        w.seek(SeekFrom::Start(0))?;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        w.write_all(b"WAVEfmt ")?;
+        // Format chunk: 16 bytes of 1 channel of PCM, with 2 bytes per sample
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        w.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        w.write_all(&2u16.to_le_bytes())?;
+        w.write_all(&16u16.to_le_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&data_size.to_le_bytes())?;
+        w.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Audio for WavAudio<W> {
+    fn update(&mut self, sound: Sound, duration: Duration) -> Result<(), ErrorKind> {
+        if self.samples == MAX_SAMPLES {
+            return Ok(());
+        }
+        let total = self.owed + duration.as_secs_f64() * SAMPLE_RATE as f64;
+        let count = total.floor();
+        self.owed = total - count;
+
+        let count = (count as u32).min(MAX_SAMPLES - self.samples);
+        for _ in 0..count {
+            let sample = self.osc.next(sound);
+            let sample = (sample * i16::MAX as f32) as i16;
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples += count;
+        if self.samples == MAX_SAMPLES {
+            log::warn!("Stopped recording audio, as the WAV file has reached its 4 GiB limit");
+        }
+        self.write_header()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::Waveform;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wav() {
+        let tone = Tone {
+            frequency: 441.0,
+            waveform: Waveform::Square,
+            volume: 1.0,
+        };
+        let mut wav = WavAudio::new(Cursor::new(Vec::new()), tone).unwrap();
+        // A tenth of a second of tone, then of silence, in frames of 551.25 samples
+        let frame = Duration::from_micros(12_500);
+        for _ in 0..8 {
+            wav.update(Sound::Tone, frame).unwrap();
+        }
+        for _ in 0..8 {
+            wav.update(Sound::Silent, frame).unwrap();
+        }
+        let bytes = wav.into_inner().into_inner();
+
+        let samples = SAMPLE_RATE as usize / 5;
+        assert_eq!(HEADER_SIZE as usize + samples * 2, bytes.len());
+        assert_eq!(b"RIFF", &bytes[0..4]);
+        assert_eq!((bytes.len() as u32 - 8).to_le_bytes(), bytes[4..8]);
+        assert_eq!((samples as u32 * 2).to_le_bytes(), bytes[40..44]);
+
+        let data: Vec<i16> = bytes[44..]
+            .chunks(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        // 100 samples per cycle of the tone, high for the first half
+        assert_eq!(i16::MAX, data[0]);
+        assert_eq!(i16::MAX, data[48]);
+        assert_eq!(-i16::MAX, data[52]);
+        assert!(data[samples / 2..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn test_full() {
+        let tone = Tone {
+            frequency: 441.0,
+            waveform: Waveform::Square,
+            volume: 1.0,
+        };
+        let mut wav = WavAudio::new(Cursor::new(Vec::new()), tone).unwrap();
+        // Pretend the file is 10 samples short of full, rather than recording for hours
+        wav.samples = MAX_SAMPLES - 10;
+        wav.update(Sound::Tone, Duration::from_secs(1)).unwrap();
+        wav.update(Sound::Tone, Duration::from_secs(1)).unwrap();
+        assert_eq!(MAX_SAMPLES, wav.samples);
+        let bytes = wav.into_inner().into_inner();
+        assert_eq!(HEADER_SIZE as usize + 10 * 2, bytes.len());
+        assert_eq!((u32::MAX - 1).to_le_bytes(), bytes[4..8]);
+    }
+}
@@ -0,0 +1,491 @@
+use crate::{
+    emulator::{
+        fault::EmulationFault,
+        input::Input,
+        instruction::{decode, Instruction},
+        State,
+    },
+    error::ErrorKind,
+};
+use crossterm::{
+    cursor,
+    event::{KeyCode, KeyEvent},
+    style,
+    terminal::{self, ClearType},
+    QueueableCommand,
+};
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{stdout, Write},
+    ops::Range,
+};
+
+/// Number of instructions shown either side of the program counter in the disassembly window.
+const DISASM_CONTEXT: u16 = 6;
+
+/// Number of columns at the right of the terminal taken up by the debugger's panel.
+pub const PANEL_WIDTH: u16 = 64;
+
+/// What the debugger is currently doing with the program.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Mode {
+    /// Execution is stopped until the user asks for it to continue.
+    Paused,
+    /// Execution continues until a breakpoint is hit.
+    Running,
+    /// Execution continues until a subroutine call returns to a stack depth of `sp`.
+    StepOver { sp: usize },
+    /// Execution continues until the current subroutine returns below a stack depth of `sp`.
+    StepOut { sp: usize },
+}
+
+/// A condition which pauses execution when it becomes true.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Breakpoint {
+    /// The program counter reaches an address.
+    Pc(u16),
+    /// The program writes to a memory address, even if the value there stays the same.
+    Write(u16),
+    /// Register Vx takes on a value.
+    RegV(u8, u8),
+    /// Register I takes on a value.
+    RegI(u16),
+}
+
+impl Breakpoint {
+    /// Whether this breakpoint was triggered by the step which took `before` to `after`.
+    fn hit(&self, before: &State, after: &State) -> bool {
+        match *self {
+            Breakpoint::Pc(addr) => after.pc == addr,
+            Breakpoint::Write(addr) => written(before).contains(&(addr as usize)),
+            Breakpoint::RegV(x, val) => {
+                before.reg_v[x as usize] != val && after.reg_v[x as usize] == val
+            }
+            Breakpoint::RegI(val) => before.reg_i != val && after.reg_i == val,
+        }
+    }
+}
+
+/// The memory written by the instruction at the program counter of `state`, which is empty for
+/// instructions that don't write to memory.
+fn written(state: &State) -> Range<usize> {
+    let len = match decode(state.opcode_at(state.pc)) {
+        Some(Instruction::Store(x)) => x as usize + 1,
+        Some(Instruction::SaveRange(x, y)) => x.abs_diff(y) as usize + 1,
+        Some(Instruction::Bcd(_)) => 3,
+        _ => 0,
+    };
+    let start = state.reg_i as usize;
+    start..start + len
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Pc(addr) => write!(f, "pc {:#06X}", addr),
+            Breakpoint::Write(addr) => write!(f, "write {:#06X}", addr),
+            Breakpoint::RegV(x, val) => write!(f, "V{:X} == {:#04X}", x, val),
+            Breakpoint::RegI(val) => write!(f, "I == {:#06X}", val),
+        }
+    }
+}
+
+/// An interactive debugger, which controls when the program runs and draws a panel showing the
+/// state of the interpreter beside the display.
+///
+/// While running, the only key the debugger handles is `p`, which pauses execution. While paused,
+/// `s` steps one instruction, `n` steps over subroutine calls, `o` steps out of the current
+/// subroutine, `c` continues, `b` toggles a breakpoint at the program counter, `q` quits, and `:`
+/// opens a prompt for one of these commands:
+///
+/// - `break <addr>` toggles a breakpoint when the program counter reaches `addr`.
+/// - `watch <addr>` toggles a breakpoint when the program writes to `addr` in memory.
+/// - `reg <Vx|I> <value>` toggles a breakpoint when a register takes on `value`.
+/// - `clear` removes every breakpoint.
+///
+/// All numbers are in hexadecimal.
+pub struct Debugger {
+    mode: Mode,
+    /// Set when a single instruction should run, even though execution is paused.
+    pending_step: bool,
+    breakpoints: BTreeSet<Breakpoint>,
+    /// Text typed into the command prompt, if it is open.
+    prompt: Option<String>,
+    /// A message describing the last thing that happened, shown at the bottom of the panel.
+    status: String,
+    quit: bool,
+}
+
+impl Debugger {
+    /// Create a new debugger, with execution paused before the first instruction.
+    pub fn new() -> Self {
+        Debugger {
+            mode: Mode::Paused,
+            pending_step: false,
+            breakpoints: BTreeSet::new(),
+            prompt: None,
+            status: String::from("Paused"),
+            quit: false,
+        }
+    }
+
+    /// Whether execution is paused, in which case keyboard input belongs to the debugger.
+    pub fn paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+
+    /// Whether the user has asked to quit the emulator.
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    /// Pause execution before the next instruction.
+    pub fn pause(&mut self) {
+        self.mode = Mode::Paused;
+        self.status = String::from("Paused");
+    }
+
+    /// Pause execution to show a fault in the program, with the program counter just past it.
+    pub fn fault(&mut self, fault: EmulationFault) {
+        self.mode = Mode::Paused;
+        self.status = fault.to_string();
+    }
+
+    /// Run the next instruction, then pause again.
+    pub fn step_into(&mut self) {
+        self.mode = Mode::Paused;
+        self.pending_step = true;
+    }
+
+    /// Run the next instruction, and if it calls a subroutine, keep running until it returns.
+    pub fn step_over(&mut self, cpu: &State) {
+        self.step_into();
+        if let Some(Instruction::Call(_)) = decode(cpu.opcode_at(cpu.pc)) {
+            self.mode = Mode::StepOver { sp: cpu.sp };
+        }
+    }
+
+    /// Keep running until the current subroutine returns.
+    pub fn step_out(&mut self, cpu: &State) {
+        if cpu.sp == 0 {
+            self.status = String::from("Not inside a subroutine");
+            return;
+        }
+        self.step_into();
+        self.mode = Mode::StepOut { sp: cpu.sp };
+    }
+
+    /// Keep running until a breakpoint is hit.
+    pub fn resume(&mut self) {
+        self.mode = Mode::Running;
+        self.status = String::from("Running");
+    }
+
+    /// Add the breakpoint if it doesn't already exist, otherwise remove it.
+    pub fn toggle(&mut self, breakpoint: Breakpoint) {
+        if self.breakpoints.remove(&breakpoint) {
+            self.status = format!("Removed breakpoint {}", breakpoint);
+        } else {
+            self.breakpoints.insert(breakpoint);
+            self.status = format!("Added breakpoint {}", breakpoint);
+        }
+    }
+
+    /// Run one instruction of the program if the debugger allows it, then pause if a breakpoint
+    /// was hit or a step has finished. Returns whether an instruction was run, or the fault if it
+    /// couldn't be.
+    pub fn step(&mut self, cpu: &mut State, input: Input) -> Result<bool, EmulationFault> {
+        if self.mode == Mode::Paused && !self.pending_step {
+            return Ok(false);
+        }
+        self.pending_step = false;
+
+        let before = *cpu;
+        cpu.step(input)?;
+
+        if let Some(bp) = self.breakpoints.iter().find(|bp| bp.hit(&before, cpu)) {
+            self.status = format!("Hit breakpoint {}", bp);
+            self.mode = Mode::Paused;
+            return Ok(true);
+        }
+        let done = match self.mode {
+            Mode::Paused => true,
+            Mode::Running => false,
+            Mode::StepOver { sp } => cpu.sp <= sp,
+            Mode::StepOut { sp } => cpu.sp < sp,
+        };
+        if done {
+            self.status = String::from("Paused");
+            self.mode = Mode::Paused;
+        }
+        Ok(true)
+    }
+
+    /// Handle a key press, returning whether the debugger used it. Keys it does not use should be
+    /// passed on to the program.
+    pub fn handle_key(&mut self, key: KeyEvent, cpu: &State) -> bool {
+        if let Some(prompt) = self.prompt.as_mut() {
+            match key.code {
+                KeyCode::Char(c) => prompt.push(c),
+                KeyCode::Backspace => {
+                    prompt.pop();
+                }
+                KeyCode::Esc => self.prompt = None,
+                KeyCode::Enter => {
+                    let command = self.prompt.take().unwrap_or_default();
+                    if let Err(e) = self.execute(&command) {
+                        self.status = e;
+                    }
+                }
+                _ => {}
+            }
+            return true;
+        }
+
+        if !self.paused() {
+            if key.code == KeyCode::Char('p') {
+                self.pause();
+                return true;
+            }
+            return false;
+        }
+
+        match key.code {
+            KeyCode::Char('s') => self.step_into(),
+            KeyCode::Char('n') => self.step_over(cpu),
+            KeyCode::Char('o') => self.step_out(cpu),
+            KeyCode::Char('c') => self.resume(),
+            KeyCode::Char('b') => self.toggle(Breakpoint::Pc(cpu.pc)),
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Char(':') => self.prompt = Some(String::new()),
+            _ => {}
+        }
+        true
+    }
+
+    /// Run a command typed into the prompt.
+    pub fn execute(&mut self, command: &str) -> Result<(), String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            ["break", addr] | ["b", addr] => self.toggle(Breakpoint::Pc(parse_hex(addr)?)),
+            ["watch", addr] | ["w", addr] => self.toggle(Breakpoint::Write(parse_hex(addr)?)),
+            ["reg", reg, val] | ["r", reg, val] => {
+                let reg = reg.to_lowercase();
+                let val = parse_hex(val)?;
+                let bp = if reg == "i" {
+                    Breakpoint::RegI(val)
+                } else {
+                    let x = reg
+                        .strip_prefix('v')
+                        .and_then(|x| u8::from_str_radix(x, 16).ok())
+                        .filter(|&x| x <= 0xF)
+                        .ok_or_else(|| format!("Unknown register: {}", reg))?;
+                    if val > 0xFF {
+                        return Err(format!("V{:X} can't hold {:#X}", x, val));
+                    }
+                    Breakpoint::RegV(x, val as u8)
+                };
+                self.toggle(bp);
+            }
+            ["clear"] => {
+                self.breakpoints.clear();
+                self.status = String::from("Removed all breakpoints");
+            }
+            _ => return Err(format!("Unknown command: {}", command)),
+        }
+        Ok(())
+    }
+
+    /// Draw the debugger panel at the right of the terminal, and never over a display drawn at
+    /// one column per pixel.
+    pub fn draw(&self, cpu: &State) -> Result<(), ErrorKind> {
+        let (columns, _) = terminal::size()?;
+        let left = columns
+            .saturating_sub(PANEL_WIDTH)
+            .max(cpu.display.width() as u16 + 2);
+        let mut lines = Vec::new();
+
+        lines.push(format!(
+            "PC {:#06X}  I {:#06X}  SP {:X}",
+            cpu.pc, cpu.reg_i, cpu.sp
+        ));
+        lines.push(format!("DT {:#04X}  ST {:#04X}", cpu.delay, cpu.sound));
+        for row in 0..4 {
+            let regs = (0..4)
+                .map(|col| row * 4 + col)
+                .map(|x| format!("V{:X} {:02X}", x, cpu.reg_v[x]))
+                .collect::<Vec<_>>();
+            lines.push(regs.join("  "));
+        }
+        let stack = cpu.stack[..cpu.sp]
+            .iter()
+            .map(|addr| format!("{:03X}", addr))
+            .collect::<Vec<_>>();
+        lines.push(format!("Stack [{}]", stack.join(" ")));
+        lines.push(String::new());
+
+        // Disassemble around the PC, keeping instructions aligned with it
+        let start = cpu.pc.saturating_sub(DISASM_CONTEXT * 2);
+        let end = cpu.pc.saturating_add(DISASM_CONTEXT * 2);
+        for addr in (start..end).step_by(2) {
+            let opcode = cpu.opcode_at(addr);
+            let mnemonic = match decode(opcode) {
+                Some(Instruction::LoadLongI) => {
+                    format!("LD I, LONG #{:04X}", cpu.opcode_at(addr.wrapping_add(2)))
+                }
+                Some(instruction) => instruction.to_string(),
+                None => String::from("???"),
+            };
+            let marker = match (
+                addr == cpu.pc,
+                self.breakpoints.contains(&Breakpoint::Pc(addr)),
+            ) {
+                (true, _) => '>',
+                (false, true) => '*',
+                (false, false) => ' ',
+            };
+            lines.push(format!(
+                "{} {:03X}  {:04X}  {}",
+                marker, addr, opcode, mnemonic
+            ));
+        }
+        lines.push(String::new());
+
+        let breakpoints = self
+            .breakpoints
+            .iter()
+            .map(|bp| bp.to_string())
+            .collect::<Vec<_>>();
+        lines.push(format!("Breakpoints: {}", breakpoints.join(", ")));
+        lines.push(self.status.clone());
+        lines.push(if self.paused() {
+            String::from("s step  n over  o out  c continue  b break  : command  q quit")
+        } else {
+            String::from("p pause")
+        });
+        if let Some(prompt) = &self.prompt {
+            lines.push(format!(":{}", prompt));
+        }
+
+        let mut stdout = stdout();
+        stdout.queue(style::ResetColor)?;
+        for (y, line) in lines.iter().enumerate() {
+            stdout
+                .queue(cursor::MoveTo(left, y as u16))?
+                .queue(terminal::Clear(ClearType::UntilNewLine))?;
+            write!(stdout, "{}", line)?;
+        }
+        // Blank out anything left behind by a longer panel, such as a closed prompt
+        for y in lines.len()..lines.len() + 2 {
+            stdout
+                .queue(cursor::MoveTo(left, y as u16))?
+                .queue(terminal::Clear(ClearType::UntilNewLine))?;
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parse a hexadecimal number, with or without a leading `0x`.
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Not a hexadecimal number: {}", s))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Quirks;
+
+    /// 200: CALL 206, 202: LD V1, 01, 204: JP 204, 206: LD V0, 05, 208: RET
+    const ROM: [u8; 10] = [0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x60, 0x05, 0x00, 0xEE];
+
+    #[test]
+    fn test_step_over() {
+        let mut cpu = State::new(&ROM, Quirks::default());
+        let mut debugger = Debugger::new();
+        debugger.step(&mut cpu, [false; 16]).unwrap();
+        assert_eq!(0x200, cpu.pc);
+
+        debugger.step_over(&cpu);
+        for _ in 0..10 {
+            debugger.step(&mut cpu, [false; 16]).unwrap();
+        }
+        assert!(debugger.paused());
+        assert_eq!(0x202, cpu.pc);
+        assert_eq!(5, cpu.reg_v[0x0]);
+    }
+
+    #[test]
+    fn test_step_out() {
+        let mut cpu = State::new(&ROM, Quirks::default());
+        let mut debugger = Debugger::new();
+        debugger.step_into();
+        debugger.step(&mut cpu, [false; 16]).unwrap();
+        assert_eq!(0x206, cpu.pc);
+
+        debugger.step_out(&cpu);
+        for _ in 0..10 {
+            debugger.step(&mut cpu, [false; 16]).unwrap();
+        }
+        assert!(debugger.paused());
+        assert_eq!(0x202, cpu.pc);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut cpu = State::new(&ROM, Quirks::default());
+        let mut debugger = Debugger::new();
+        debugger.execute("reg v1 1").unwrap();
+        debugger.execute("break 208").unwrap();
+        assert!(debugger.execute("reg vg 1").is_err());
+
+        debugger.resume();
+        for _ in 0..10 {
+            debugger.step(&mut cpu, [false; 16]).unwrap();
+        }
+        assert_eq!(0x208, cpu.pc);
+
+        debugger.resume();
+        for _ in 0..10 {
+            debugger.step(&mut cpu, [false; 16]).unwrap();
+        }
+        assert_eq!(0x204, cpu.pc);
+        assert_eq!(1, cpu.reg_v[0x1]);
+    }
+
+    #[test]
+    fn test_watch() {
+        // Store V0, which is 0, to 0x300 where memory is already 0, then V0 as BCD over 0x2FF-0x301
+        let rom = [0xA3, 0x00, 0xF0, 0x55, 0xA2, 0xFF, 0xF0, 0x33, 0x12, 0x08];
+        let mut cpu = State::new(&rom, Quirks::default());
+        let mut debugger = Debugger::new();
+        debugger.execute("watch 300").unwrap();
+
+        debugger.resume();
+        for _ in 0..10 {
+            debugger.step(&mut cpu, [false; 16]).unwrap();
+        }
+        assert_eq!(0x204, cpu.pc);
+
+        debugger.resume();
+        for _ in 0..10 {
+            debugger.step(&mut cpu, [false; 16]).unwrap();
+        }
+        assert_eq!(0x208, cpu.pc);
+
+        // Jumping doesn't write to memory, so the loop at the end runs on
+        debugger.resume();
+        for _ in 0..10 {
+            debugger.step(&mut cpu, [false; 16]).unwrap();
+        }
+        assert!(!debugger.paused());
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{
+    emulator::{
+        instruction::{decode, Instruction},
+        rom,
+    },
+    error::ErrorKind,
+    utils::u8_to_bits,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, stdout, Write},
+    path::Path,
+};
+
+/// Address the ROM is loaded at, and where execution starts.
+const START: u16 = 0x200;
+
+/// What a labelled address was found to be used for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Label {
+    /// The target of a jump or call.
+    Code,
+    /// The target of an I load, usually sprite data.
+    Data,
+}
+
+/// The result of tracing the flow of a ROM from its entry point.
+struct Trace {
+    /// For each byte of the ROM, whether it is part of a reachable instruction.
+    code: Vec<bool>,
+    /// Every address referred to by a jump, call or I load.
+    labels: BTreeMap<u16, Label>,
+}
+
+/// Follow every jump, call and skip from the entry point, to separate instructions from data.
+///
+/// Targets of `Bnnn` depend on V0, so they are labelled but not followed, and decoding stops at
+/// anything that isn't a valid instruction.
+fn trace(rom: &[u8]) -> Trace {
+    let mut code = vec![false; rom.len()];
+    let mut labels = BTreeMap::new();
+    let mut work = vec![START];
+    labels.insert(START, Label::Code);
+
+    while let Some(mut addr) = work.pop() {
+        while let Some((instruction, operand)) = instruction_at(rom, addr) {
+            let idx = (addr - START) as usize;
+            if code[idx] {
+                break;
+            }
+            let len = instruction.size();
+            for flag in code.iter_mut().skip(idx).take(len as usize) {
+                *flag = true;
+            }
+
+            let mut add_label = |target: u16, kind: Label| {
+                let label = labels.entry(target).or_insert(kind);
+                if kind == Label::Code {
+                    *label = Label::Code;
+                }
+            };
+            match instruction {
+                Instruction::Jump(target) => {
+                    add_label(target, Label::Code);
+                    work.push(target);
+                    break;
+                }
+                Instruction::Call(target) => {
+                    add_label(target, Label::Code);
+                    work.push(target);
+                }
+                Instruction::JumpOffset(target) => {
+                    add_label(target, Label::Code);
+                    break;
+                }
+                Instruction::Return | Instruction::Exit => break,
+                Instruction::LoadI(target) => add_label(target, Label::Data),
+                Instruction::LoadLongI => add_label(operand, Label::Data),
+                i if i.is_skip() => {
+                    // Carry on from the instruction after next, as well as the next one
+                    let next = addr.wrapping_add(len);
+                    if let Some((skipped, _)) = instruction_at(rom, next) {
+                        work.push(next.wrapping_add(skipped.size()));
+                    }
+                }
+                _ => {}
+            }
+            addr = addr.wrapping_add(len);
+        }
+    }
+
+    Trace { code, labels }
+}
+
+/// Decode the instruction at `addr`, along with the two bytes after it, which are the operand of
+/// `F000 nnnn`. Returns `None` for anything that isn't a valid instruction within the ROM.
+fn instruction_at(rom: &[u8], addr: u16) -> Option<(Instruction, u16)> {
+    let idx = addr.checked_sub(START)? as usize;
+    let word = |idx: usize| Some(u16::from_be_bytes([*rom.get(idx)?, *rom.get(idx + 1)?]));
+
+    let instruction = decode(word(idx)?)?;
+    let operand = match instruction {
+        Instruction::LoadLongI => word(idx + 2)?,
+        _ => 0,
+    };
+    Some((instruction, operand))
+}
+
+/// The address of every line in the listing of `rom`, which is either an instruction traced as
+/// code or a single byte of data.
+fn line_starts(rom: &[u8], code: &[bool]) -> BTreeSet<u16> {
+    let mut res = BTreeSet::new();
+    let mut idx = 0;
+    while idx < rom.len() {
+        let addr = START + idx as u16;
+        res.insert(addr);
+        idx += match instruction_at(rom, addr).filter(|_| code[idx]) {
+            Some((instruction, _)) => instruction.size() as usize,
+            None => 1,
+        };
+    }
+    res
+}
+
+/// The name given to a labelled address.
+fn label_name(addr: u16, kind: Label) -> String {
+    match kind {
+        Label::Code => format!("L{:03X}", addr),
+        Label::Data => format!("D{:03X}", addr),
+    }
+}
+
+/// Write a listing of `rom` to `w`, giving the address, raw bytes and mnemonic of every
+/// instruction. Bytes which are never reached as code are written as `DB` directives, with a
+/// picture of their bits, since they are usually sprites. Every jump, call and I load target is
+/// given a label, which is used in place of the address in operands, as long as the address starts
+/// a line of the listing. Targets outside the ROM, or in the middle of an instruction, are left as
+/// plain addresses, so the listing can be assembled again.
+pub fn disassemble(rom: &[u8], w: &mut impl Write) -> io::Result<()> {
+    let Trace { code, mut labels } = trace(rom);
+    let starts = line_starts(rom, &code);
+    labels.retain(|addr, _| starts.contains(addr));
+    let target = |addr: u16| match labels.get(&addr) {
+        Some(&kind) => label_name(addr, kind),
+        None => format!("#{:03X}", addr),
+    };
+
+    let mut idx = 0;
+    while idx < rom.len() {
+        let addr = START + idx as u16;
+        if let Some(&kind) = labels.get(&addr) {
+            writeln!(w, "{}:", label_name(addr, kind))?;
+        }
+
+        let instruction = instruction_at(rom, addr).filter(|_| code[idx]);
+        if let Some((instruction, operand)) = instruction {
+            let len = instruction.size() as usize;
+            let raw = rom[idx..idx + len]
+                .chunks(2)
+                .map(|pair| format!("{:02X}{:02X}", pair[0], pair[1]))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let mnemonic = match instruction {
+                Instruction::Jump(nnn) => format!("JP {}", target(nnn)),
+                Instruction::Call(nnn) => format!("CALL {}", target(nnn)),
+                Instruction::LoadI(nnn) => format!("LD I, {}", target(nnn)),
+                Instruction::JumpOffset(nnn) => format!("JP V0, {}", target(nnn)),
+                Instruction::LoadLongI => format!("LD I, LONG {}", target(operand)),
+                other => other.to_string(),
+            };
+            writeln!(w, "    {:04X}  {:<9}  {}", addr, raw, mnemonic)?;
+            idx += len;
+        } else {
+            let byte = rom[idx];
+            let bits: String = u8_to_bits(byte)
+                .iter()
+                .map(|&bit| if bit { '#' } else { '.' })
+                .collect();
+            let data = format!("DB #{:02X}", byte);
+            writeln!(
+                w,
+                "    {:04X}  {:<9}  {:<16}; {}",
+                addr,
+                format!("{:02X}", byte),
+                data,
+                bits
+            )?;
+            idx += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Disassemble the ROM at `rom_path`, printing the listing to stdout.
+pub fn run(rom_path: &Path) -> Result<(), ErrorKind> {
+    let rom = rom::load_file(rom_path)?;
+    let stdout = stdout();
+    let mut w = io::BufWriter::new(stdout.lock());
+    disassemble(&rom, &mut w)?;
+    w.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        // 200: LD I, 208; 202: CALL 206; 204: JP 204; 206: RET; 208: sprite data
+        let rom = [0xA2, 0x08, 0x22, 0x06, 0x12, 0x04, 0x00, 0xEE, 0xF0, 0x90];
+        let mut out = Vec::new();
+        disassemble(&rom, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let expected = "\
+L200:
+    0200  A208       LD I, D208
+    0202  2206       CALL L206
+L204:
+    0204  1204       JP L204
+L206:
+    0206  00EE       RET
+D208:
+    0208  F0         DB #F0          ; ####....
+    0209  90         DB #90          ; #..#....
+";
+        assert_eq!(expected, out);
+    }
+
+    #[test]
+    fn test_trace_skips() {
+        // 200: SE V0, 00; 202: LD I, LONG 1234; 206: EXIT; 208: unreachable
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD, 0x00, 0xE0];
+        let Trace { code, labels } = trace(&rom);
+        assert_eq!(vec![true; 8], code[..8]);
+        assert_eq!(vec![false; 2], code[8..]);
+        assert_eq!(Some(&Label::Data), labels.get(&0x1234));
+    }
+
+    #[test]
+    fn test_unlabelled_targets() {
+        // 200: LD I, 300, past the end; 202: SE V0, 00; 204: JP 207, in the middle of 206: JP 200
+        let rom = [0xA3, 0x00, 0x30, 0x00, 0x12, 0x07, 0x12, 0x00];
+        let mut out = Vec::new();
+        disassemble(&rom, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("LD I, #300"));
+        assert!(out.contains("JP #207"));
+        assert!(out.contains("JP L200"));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{
+    display::{
+        terminal::{enter_screen, leave_screen},
+        theme::{ColourDepth, Theme},
+        Render,
+    },
+    emulator::Framebuffer,
+    error::ErrorKind,
+};
+use crossterm::{
+    cursor,
+    style::{self, Color},
+    terminal::{self, ClearType},
+    QueueableCommand,
+};
+use std::io::{stdout, Write};
+
+/// The bit of a braille character's dot pattern for each pixel of a cell, indexed by `[y][x]`.
+const DOTS: [[u8; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// A renderer which draws a block of 2x4 pixels in each character cell using the braille
+/// patterns, so a whole 64x32 screen fits in 32 columns and 8 lines.
+///
+/// A cell only has one foreground colour, so when pixels of different colours share a cell, the
+/// whole cell takes the colour with the highest value.
+pub struct BrailleRenderer {
+    /// The last frame drawn, or `None` if the whole screen must be redrawn.
+    prev: Option<Framebuffer>,
+    /// The colour of each pixel value.
+    palette: Vec<Color>,
+}
+
+impl BrailleRenderer {
+    /// Create a new BrailleRenderer which draws in the colours of `theme`, clearing the screen.
+    pub fn new(theme: Theme) -> Result<BrailleRenderer, ErrorKind> {
+        enter_screen()?;
+        let mut res = BrailleRenderer {
+            prev: None,
+            palette: theme.palette(ColourDepth::detect(), 0),
+        };
+        res.render(&Framebuffer::new())?;
+        Ok(res)
+    }
+}
+
+impl Render for BrailleRenderer {
+    fn render(&mut self, screen: &Framebuffer) -> Result<(), ErrorKind> {
+        // Skip rendering if nothing has changed
+        if self.prev.as_ref() == Some(screen) {
+            return Ok(());
+        }
+
+        let mut stdout = stdout();
+        // A change in resolution leaves the old image behind, so start from a blank terminal
+        let prev = self.prev.filter(|prev| prev.hires() == screen.hires());
+        if prev.is_none() {
+            stdout.queue(terminal::Clear(ClearType::All))?;
+        }
+
+        // Each line of cells covers four rows of pixels
+        let width = screen.width();
+        let old_lines = prev.as_ref().map(|prev| prev.pixels().chunks(width * 4));
+        let mut old_lines = old_lines.into_iter().flatten();
+        for (y, line) in screen.pixels().chunks(width * 4).enumerate() {
+            // Skip per-line rendering if nothing has changed
+            if old_lines.next() == Some(line) {
+                continue;
+            }
+
+            stdout
+                .queue(cursor::MoveTo(0, y as u16))?
+                .queue(style::SetBackgroundColor(self.palette[0]))?;
+            let mut colour = None;
+            for x in (0..width).step_by(2) {
+                let (glyph, fg) = cell(screen, x, y * 4);
+                // Blank cells look the same whatever the foreground colour is
+                if fg != 0 && colour != Some(fg) {
+                    stdout.queue(style::SetForegroundColor(self.palette[fg as usize]))?;
+                    colour = Some(fg);
+                }
+                write!(stdout, "{}", glyph)?;
+            }
+        }
+
+        stdout.flush()?;
+        self.prev = Some(*screen);
+        Ok(())
+    }
+}
+
+impl Drop for BrailleRenderer {
+    fn drop(&mut self) {
+        leave_screen();
+    }
+}
+
+/// The braille character for the cell whose top left pixel is at `(x, y)`, and the colour it
+/// should be drawn in. A cell with no pixels lit has colour 0.
+fn cell(screen: &Framebuffer, x: usize, y: usize) -> (char, u8) {
+    let mut pattern = 0;
+    let mut colour = 0;
+    for (dy, row) in DOTS.iter().enumerate() {
+        for (dx, &bit) in row.iter().enumerate() {
+            let pixel = screen.get(x + dx, y + dy);
+            if pixel != 0 {
+                pattern |= bit;
+                colour = colour.max(pixel);
+            }
+        }
+    }
+    let glyph = std::char::from_u32(0x2800 + pattern as u32).expect("braille is a valid char");
+    (glyph, colour)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cell() {
+        let mut screen = Framebuffer::new();
+        assert_eq!(('⠀', 0), cell(&screen, 0, 0));
+
+        // The left column, then the bottom right dot
+        for y in 0..4 {
+            screen.flip(0, y, 1);
+        }
+        screen.flip(1, 3, 2);
+        assert_eq!(('⣇', 2), cell(&screen, 0, 0));
+        screen.flip(2, 0, 1);
+        assert_eq!(('⠁', 1), cell(&screen, 2, 0));
+    }
+}
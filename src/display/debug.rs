@@ -1,27 +1,33 @@
-use crate::{display::Render, emulator::State, error::ErrorKind};
-use std::cmp::Ordering;
+use crate::{display::Render, emulator::Framebuffer, error::ErrorKind};
 
 /// A simple renderer that repeatedly prints the output to stdout. Included for debugging.
-pub struct DebugRenderer(pub [bool; 2048]);
+/// Each of the four XO-CHIP colours is printed with a different shade of block.
+pub struct DebugRenderer(pub Framebuffer);
 
 impl DebugRenderer {
     pub fn new() -> Self {
-        DebugRenderer([false; 2048])
+        DebugRenderer(Framebuffer::new())
+    }
+}
+
+impl Default for DebugRenderer {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl Render for DebugRenderer {
-    fn render(&mut self, display: [bool; 2048]) -> Result<(), ErrorKind> {
+    fn render(&mut self, display: &Framebuffer) -> Result<(), ErrorKind> {
         // Do not render repeated frames
-        if display.cmp(&self.0) == Ordering::Equal {
+        if *display == self.0 {
             return Ok(());
         } else {
-            self.0 = display;
+            self.0 = *display;
         }
 
-        for line in display.chunks_exact(State::WIDTH) {
+        for line in display.rows() {
             for b in line {
-                print!("{}", if *b { '█' } else { '░' });
+                print!("{}", ['░', '█', '▒', '▓'][*b as usize]);
             }
             println!();
         }
@@ -0,0 +1,126 @@
+use crate::{
+    display::{
+        terminal::{enter_screen, leave_screen},
+        theme::{ColourDepth, Theme},
+        Render,
+    },
+    emulator::Framebuffer,
+    error::ErrorKind,
+};
+use crossterm::{
+    cursor,
+    style::{self, Color},
+    terminal::{self, ClearType},
+    QueueableCommand,
+};
+use std::io::{stdout, Write};
+
+/// A renderer which packs two pixels, one above the other, into each character cell using the
+/// half block characters. Since terminal cells are about twice as tall as they are wide, this
+/// makes pixels roughly square, and needs half as many lines as `TerminalRenderer`.
+pub struct HalfBlockRenderer {
+    /// The last frame drawn, or `None` if the whole screen must be redrawn.
+    prev: Option<Framebuffer>,
+    /// The colour of each pixel value.
+    palette: Vec<Color>,
+}
+
+impl HalfBlockRenderer {
+    /// Create a new HalfBlockRenderer which draws in the colours of `theme`, clearing the screen.
+    pub fn new(theme: Theme) -> Result<HalfBlockRenderer, ErrorKind> {
+        enter_screen()?;
+        let mut res = HalfBlockRenderer {
+            prev: None,
+            palette: theme.palette(ColourDepth::detect(), 0),
+        };
+        res.render(&Framebuffer::new())?;
+        Ok(res)
+    }
+}
+
+impl Render for HalfBlockRenderer {
+    fn render(&mut self, screen: &Framebuffer) -> Result<(), ErrorKind> {
+        // Skip rendering if nothing has changed
+        if self.prev.as_ref() == Some(screen) {
+            return Ok(());
+        }
+
+        let mut stdout = stdout();
+        // A change in resolution leaves the old image behind, so start from a blank terminal
+        let prev = self.prev.filter(|prev| prev.hires() == screen.hires());
+        if prev.is_none() {
+            stdout.queue(terminal::Clear(ClearType::All))?;
+        }
+
+        // Each line of cells covers two rows of pixels
+        let width = screen.width();
+        let old_lines = prev.as_ref().map(|prev| prev.pixels().chunks(width * 2));
+        let mut old_lines = old_lines.into_iter().flatten();
+        for (y, line) in screen.pixels().chunks(width * 2).enumerate() {
+            // Skip per-line rendering if nothing has changed
+            if old_lines.next() == Some(line) {
+                continue;
+            }
+
+            stdout.queue(cursor::MoveTo(0, y as u16))?;
+            let (top, bottom) = line.split_at(width);
+            let mut colours = None;
+            for (&top, &bottom) in top.iter().zip(bottom) {
+                let (glyph, fg, bg) = cell(top, bottom, colours);
+                if colours != Some((fg, bg)) {
+                    stdout
+                        .queue(style::SetForegroundColor(self.palette[fg as usize]))?
+                        .queue(style::SetBackgroundColor(self.palette[bg as usize]))?;
+                    colours = Some((fg, bg));
+                }
+                write!(stdout, "{}", glyph)?;
+            }
+        }
+
+        stdout.flush()?;
+        self.prev = Some(*screen);
+        Ok(())
+    }
+}
+
+impl Drop for HalfBlockRenderer {
+    fn drop(&mut self) {
+        leave_screen();
+    }
+}
+
+/// Choose the character, foreground and background colour for a cell whose top pixel has the
+/// colour `top` and bottom pixel `bottom`. Where there's a choice, the colours `current` already
+/// set are kept, so fewer colour changes need to be sent to the terminal.
+fn cell(top: u8, bottom: u8, current: Option<(u8, u8)>) -> (char, u8, u8) {
+    if top == bottom {
+        match current {
+            Some((fg, bg)) if fg == top => ('█', fg, bg),
+            Some((fg, bg)) if bg == top => (' ', fg, bg),
+            Some((_, bg)) => ('█', top, bg),
+            None => ('█', top, top),
+        }
+    } else if current == Some((bottom, top)) {
+        ('▄', bottom, top)
+    } else {
+        ('▀', top, bottom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cell() {
+        assert_eq!(('▀', 1, 0), cell(1, 0, None));
+        assert_eq!(('▀', 1, 0), cell(1, 0, Some((1, 0))));
+        // Flipping the glyph saves changing both colours
+        assert_eq!(('▄', 0, 1), cell(1, 0, Some((0, 1))));
+        // Solid cells use whichever colour is already set
+        assert_eq!(('█', 1, 0), cell(1, 1, Some((1, 0))));
+        assert_eq!((' ', 1, 0), cell(0, 0, Some((1, 0))));
+        assert_eq!(('█', 3, 0), cell(3, 3, Some((1, 0))));
+        assert_eq!(('█', 2, 2), cell(2, 2, None));
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{display::theme::Rgb, emulator::Framebuffer};
+
+/// A picture of the display, scaled up so that each of its pixels is a square block of pixels.
+/// Renderers which draw images, rather than characters, encode one of these.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// The value of each pixel, row by row, which is an index into a palette such as the one
+    /// from `Theme::rgb`.
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// A picture of `screen`, with each of its pixels drawn as a `scale` by `scale` block.
+    pub fn new(screen: &Framebuffer, scale: usize) -> Self {
+        let (width, height) = (screen.width() * scale, screen.height() * scale);
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in screen.rows() {
+            let line: Vec<u8> = row
+                .iter()
+                .flat_map(|&pixel| std::iter::repeat_n(pixel, scale))
+                .collect();
+            for _ in 0..scale {
+                pixels.extend_from_slice(&line);
+            }
+        }
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// The image as red, green and blue bytes for each pixel, row by row, in the given colours.
+    pub fn to_rgb(&self, colours: &[Rgb]) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&pixel| colours[pixel as usize].iter().copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_image() {
+        let mut screen = Framebuffer::new();
+        screen.flip(1, 0, 1);
+        let image = Image::new(&screen, 2);
+        assert_eq!((128, 64), (image.width, image.height));
+        assert_eq!(&[0, 0, 1, 1, 0], &image.pixels[..5]);
+        assert_eq!(&[0, 0, 1, 1, 0], &image.pixels[128..133]);
+        assert_eq!(0, image.pixels[256 + 2]);
+
+        let rgb = image.to_rgb(&[[0, 0, 0], [1, 2, 3]]);
+        assert_eq!(&[0, 0, 0, 0, 0, 0, 1, 2, 3, 1, 2, 3], &rgb[..12]);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{
+    display::{
+        image::Image,
+        terminal::{enter_screen, leave_screen},
+        theme::{Rgb, Theme},
+        Render,
+    },
+    emulator::Framebuffer,
+    error::ErrorKind,
+};
+use crossterm::{cursor, QueueableCommand};
+use std::{
+    env,
+    fmt::Write as _,
+    io::{stdout, Write},
+};
+
+/// The largest amount of base64 data sent in one escape sequence, as the protocol requires.
+const CHUNK_SIZE: usize = 4096;
+
+/// A renderer which draws the display as an image, using the graphics protocol of the Kitty
+/// terminal, which WezTerm and Ghostty also understand.
+pub struct KittyRenderer {
+    /// The last frame drawn, or `None` if nothing has been drawn yet.
+    prev: Option<Framebuffer>,
+    /// Width and height in image pixels of each pixel of the display.
+    scale: usize,
+    /// The colour of each pixel value.
+    colours: [Rgb; 4],
+}
+
+impl KittyRenderer {
+    /// Create a new KittyRenderer, which draws each pixel as a `scale` by `scale` block in the
+    /// colours of `theme`, clearing the screen.
+    pub fn new(scale: usize, theme: Theme) -> Result<KittyRenderer, ErrorKind> {
+        enter_screen()?;
+        let mut res = KittyRenderer {
+            prev: None,
+            scale,
+            colours: theme.rgb(),
+        };
+        res.render(&Framebuffer::new())?;
+        Ok(res)
+    }
+}
+
+impl Render for KittyRenderer {
+    fn render(&mut self, screen: &Framebuffer) -> Result<(), ErrorKind> {
+        // Skip rendering if nothing has changed
+        if self.prev.as_ref() == Some(screen) {
+            return Ok(());
+        }
+
+        let image = Image::new(screen, self.scale);
+        let mut stdout = stdout();
+        stdout.queue(cursor::MoveTo(0, 0))?;
+        write!(stdout, "{}", encode(&image, &self.colours))?;
+        stdout.flush()?;
+        self.prev = Some(*screen);
+        Ok(())
+    }
+}
+
+impl Drop for KittyRenderer {
+    fn drop(&mut self) {
+        // Images aren't cleared along with the text, so delete them first
+        let mut stdout = stdout();
+        let _ = write!(stdout, "\x1b_Ga=d,d=A,q=2\x1b\\");
+        leave_screen();
+    }
+}
+
+/// Whether the terminal seems to understand the Kitty graphics protocol, going by its
+/// environment variables.
+pub fn supported() -> bool {
+    detect(|name| env::var(name).ok())
+}
+
+/// Whether the terminal described by the environment variables from `var` understands the Kitty
+/// graphics protocol.
+fn detect(var: impl Fn(&str) -> Option<String>) -> bool {
+    let term = var("TERM").unwrap_or_default();
+    let program = var("TERM_PROGRAM").unwrap_or_default();
+    var("KITTY_WINDOW_ID").is_some()
+        || term.contains("kitty")
+        || term.contains("ghostty")
+        || ["WezTerm", "ghostty"].contains(&program.as_str())
+}
+
+/// Encode `image` as escape sequences of the Kitty graphics protocol, drawing each pixel value
+/// in the matching colour.
+///
+/// The image is sent as raw RGB data, in base64 split into chunks, and always with the same image
+/// and placement ids, so each frame replaces the last rather than piling up in the terminal. The
+/// cursor is left where it was.
+pub fn encode(image: &Image, colours: &[Rgb]) -> String {
+    let data = base64(&image.to_rgb(colours));
+    let mut out = String::new();
+    let chunks: Vec<_> = data.as_bytes().chunks(CHUNK_SIZE).collect();
+    for (idx, chunk) in chunks.iter().enumerate() {
+        let more = (idx + 1 < chunks.len()) as u8;
+        if idx == 0 {
+            let _ = write!(
+                out,
+                "\x1b_Ga=T,f=24,s={},v={},i=1,p=1,C=1,q=2,m={};",
+                image.width, image.height, more
+            );
+        } else {
+            let _ = write!(out, "\x1b_Gm={};", more);
+        }
+        out.push_str(std::str::from_utf8(chunk).expect("base64 is ASCII"));
+        out.push_str("\x1b\\");
+    }
+    out
+}
+
+/// Encode `data` in standard base64, with padding.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let bytes = [
+            group[0],
+            *group.get(1).unwrap_or(&0),
+            *group.get(2).unwrap_or(&0),
+        ];
+        let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for idx in 0..4 {
+            if idx <= group.len() {
+                out.push(ALPHABET[(bits >> (18 - idx * 6) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![0, 1],
+        };
+        assert_eq!(
+            "\x1b_Ga=T,f=24,s=2,v=1,i=1,p=1,C=1,q=2,m=0;AAAA////\x1b\\",
+            encode(&image, &[[0, 0, 0], [255, 255, 255]])
+        );
+
+        // The full display is too large to send in one go
+        let encoded = encode(&Image::new(&Framebuffer::new(), 1), &[[0, 0, 0]]);
+        let chunks: Vec<_> = encoded.split_terminator("\x1b\\").collect();
+        assert_eq!(2, chunks.len());
+        assert!(chunks[0].starts_with("\x1b_Ga=T,f=24,s=64,v=32,i=1,p=1,C=1,q=2,m=1;AAAA"));
+        assert!(chunks[1].starts_with("\x1b_Gm=0;AAAA"));
+        let data = chunks
+            .iter()
+            .map(|chunk| chunk.split(';').nth(1).unwrap().len());
+        assert_eq!(64 * 32 * 4, data.sum::<usize>());
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9vYmFy", base64(b"foobar"));
+    }
+
+    #[test]
+    fn test_detect() {
+        assert!(detect(|name| match name {
+            "KITTY_WINDOW_ID" => Some(String::from("1")),
+            _ => None,
+        }));
+        assert!(detect(|name| match name {
+            "TERM" => Some(String::from("xterm-kitty")),
+            _ => None,
+        }));
+        assert!(!detect(|name| match name {
+            "TERM" => Some(String::from("xterm-256color")),
+            _ => None,
+        }));
+    }
+}
@@ -1,57 +1,66 @@
-use crate::error::{DisplayKindError, ErrorKind};
-use std::str::FromStr;
+use crate::emulator::Framebuffer;
 
+/// Draws blocks of 2x4 pixels as braille characters, for small terminals.
+pub mod braille;
 /// A simple renderer that repeatedly prints the output to stdout. Included for debugging.
 pub mod debug;
+/// Packs two pixels into each character cell, for square pixels.
+pub mod halfblock;
+/// Pictures of the display, for renderers which draw images.
+pub mod image;
+/// Draws the display as an image, with the Kitty graphics protocol.
+pub mod kitty;
 /// A simple no-operation renderer.
 pub mod noop;
+/// Records what another renderer draws as a GIF or Y4M video.
+pub mod record;
+/// Lets renderers be chosen by name, and lists them with their options.
+pub mod registry;
+/// Saves pictures of the display as PNG or PPM files.
+pub mod screenshot;
+/// Draws the display as a Sixel image.
+pub mod sixel;
 /// The default, most fully-featured renderer.
 pub mod terminal;
+/// Colour themes, and fading pixels out.
+pub mod theme;
 
+/// Draws blocks of 2x4 pixels as braille characters, for small terminals.
+pub use braille::BrailleRenderer;
 /// A simple renderer that repeatedly prints the output to stdout. Included for debugging.
 pub use debug::DebugRenderer;
+/// Packs two pixels into each character cell, for square pixels.
+pub use halfblock::HalfBlockRenderer;
+/// Pictures of the display, for renderers which draw images.
+pub use image::Image;
+/// Draws the display as an image, with the Kitty graphics protocol.
+pub use kitty::KittyRenderer;
 /// A simple no-operation renderer.
 pub use noop::DummyRenderer;
+/// Records what another renderer draws as a GIF or Y4M video.
+pub use record::Recorder;
+/// Lets renderers be chosen by name, and lists them with their options.
+pub use registry::{DisplayOption, DisplayOptions, Registry, RendererInfo};
+/// Draws the display as a Sixel image.
+pub use sixel::SixelRenderer;
 /// The default, most fully-featured renderer.
 pub use terminal::TerminalRenderer;
+/// Colour themes, and fading pixels out.
+pub use theme::Theme;
 
 /// The `Render` trait describes types which implement some kind of rendering protocol. It
 /// exposes the method `Render::render`, which takes the current state of the games display and
 /// attempts to render it to the given output. It also has an associated type `Render::Err`,
 /// and a value of `Result::<Render::Err>::Err` will be returned when the renderer has failed
-/// to render.
+/// to render. The resolution of the display may change between calls, when a program switches
+/// in or out of high resolution mode.
 pub trait Render {
-    fn render(&mut self, display: [bool; 2048]) -> Result<(), crate::error::ErrorKind>;
-}
-
-/// Represents the avaliable choices of display mode.
-#[derive(Copy, Clone, Debug)]
-pub enum DisplayKind {
-    Noop,
-    Debug,
-    Terminal,
-}
-
-impl DisplayKind {
-    pub fn to_renderer(&self) -> Result<Box<dyn Render>, ErrorKind> {
-        Ok(match *self {
-            Self::Noop => Box::new(DummyRenderer),
-            Self::Debug => Box::new(DebugRenderer::new()),
-            Self::Terminal => Box::new(TerminalRenderer::new()?),
-        })
-    }
-}
-
-impl FromStr for DisplayKind {
-    type Err = DisplayKindError;
+    fn render(&mut self, display: &Framebuffer) -> Result<(), crate::error::ErrorKind>;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        use DisplayKind::*;
-        Ok(match s.trim().to_lowercase().as_str() {
-            "noop" => Noop,
-            "debug" => Debug,
-            "terminal" => Terminal,
-            _ => return Err(DisplayKindError(s.to_owned())),
-        })
+    /// Called when the terminal is resized, with the number of columns and rows, from the top
+    /// left, which the renderer may now draw to. Renderers which don't draw to the terminal can
+    /// ignore this.
+    fn resize(&mut self, _columns: u16, _rows: u16) -> Result<(), crate::error::ErrorKind> {
+        Ok(())
     }
 }
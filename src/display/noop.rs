@@ -1,11 +1,11 @@
-use crate::{display::Render, error::ErrorKind};
+use crate::{display::Render, emulator::Framebuffer, error::ErrorKind};
 
 /// A simple no-operation renderer. This is useful for testing game logic, without needing to draw
 /// output to the screen.
 pub struct DummyRenderer;
 
 impl Render for DummyRenderer {
-    fn render(&mut self, _display: [bool; 2048]) -> Result<(), ErrorKind> {
+    fn render(&mut self, _display: &Framebuffer) -> Result<(), ErrorKind> {
         Ok(())
     }
 }
@@ -0,0 +1,306 @@
+use crate::{
+    display::{
+        image::Image,
+        theme::{Rgb, Theme},
+        Render,
+    },
+    emulator::Framebuffer,
+    error::ErrorKind,
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// The file formats recordings can be written in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// An animated GIF, which plays anywhere, but can only time frames to the hundredth of a
+    /// second.
+    Gif,
+    /// A raw YUV4MPEG2 video stream, which tools such as ffmpeg can convert to anything else.
+    Y4m,
+}
+
+impl Format {
+    /// The format to write to `path` in, which is Y4M for a `.y4m` extension and GIF otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("y4m") => Format::Y4m,
+            _ => Format::Gif,
+        }
+    }
+}
+
+/// The encoder for each format.
+enum Encoder<W: Write> {
+    Gif {
+        encoder: gif::Encoder<W>,
+        /// The image last recorded, which isn't written until it changes, along with the frame
+        /// it was first recorded on. This way, a still image is one long GIF frame.
+        pending: Option<(Image, u64)>,
+    },
+    Y4m {
+        w: W,
+        /// The Y, U and V planes, each holding that component of the colour of each pixel
+        /// value.
+        planes: [[u8; 4]; 3],
+    },
+}
+
+/// A video being recorded, one frame at a time, to a writer.
+///
+/// Every frame is the same size, which is that of the display in high resolution mode, so low
+/// resolution frames are drawn at twice the scale.
+pub struct Recording<W: Write> {
+    encoder: Encoder<W>,
+    /// Frames per second the video plays at.
+    fps: u64,
+    /// Width and height in image pixels of each pixel of the display in high resolution mode.
+    scale: usize,
+    /// Number of frames recorded so far.
+    frames: u64,
+}
+
+impl<W: Write> Recording<W> {
+    /// Start recording to `w` in the given format, at `fps` frames per second. Each pixel is
+    /// drawn as a `scale` by `scale` block in the colours of `theme`.
+    pub fn new(
+        mut w: W,
+        format: Format,
+        fps: u64,
+        scale: usize,
+        theme: Theme,
+    ) -> Result<Self, ErrorKind> {
+        let width = Framebuffer::HIRES_WIDTH * scale;
+        let height = Framebuffer::HIRES_HEIGHT * scale;
+        let encoder = match format {
+            Format::Gif => {
+                let palette: Vec<u8> = theme.rgb().iter().flatten().copied().collect();
+                let mut encoder = gif::Encoder::new(w, width as u16, height as u16, &palette)
+                    .map_err(|e| ErrorKind::ImageErr(e.to_string()))?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(|e| ErrorKind::ImageErr(e.to_string()))?;
+                Encoder::Gif {
+                    encoder,
+                    pending: None,
+                }
+            }
+            Format::Y4m => {
+                writeln!(
+                    w,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                    width, height, fps
+                )?;
+                let colours = theme.rgb().map(yuv);
+                let planes = [0, 1, 2].map(|plane| colours.map(|colour| colour[plane]));
+                Encoder::Y4m { w, planes }
+            }
+        };
+        Ok(Recording {
+            encoder,
+            fps,
+            scale,
+            frames: 0,
+        })
+    }
+
+    /// Record `screen` as the next frame.
+    pub fn add(&mut self, screen: &Framebuffer) -> Result<(), ErrorKind> {
+        let scale = self.scale * Framebuffer::HIRES_WIDTH / screen.width();
+        let image = Image::new(screen, scale);
+        match &mut self.encoder {
+            Encoder::Gif { encoder, pending } => {
+                if pending.as_ref().map(|(prev, _)| prev) != Some(&image) {
+                    if let Some((prev, start)) = pending.replace((image, self.frames)) {
+                        write_gif_frame(encoder, prev, start, self.frames, self.fps)?;
+                    }
+                }
+            }
+            Encoder::Y4m { w, planes } => {
+                w.write_all(b"FRAME\n")?;
+                // Each of the Y, U and V planes is written in full, one after the other
+                for plane in planes.iter() {
+                    let bytes: Vec<u8> = image
+                        .pixels
+                        .iter()
+                        .map(|&pixel| plane[pixel as usize])
+                        .collect();
+                    w.write_all(&bytes)?;
+                }
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Finish the recording, returning the writer it was written to.
+    pub fn finish(self) -> Result<W, ErrorKind> {
+        match self.encoder {
+            Encoder::Gif {
+                mut encoder,
+                pending,
+            } => {
+                if let Some((image, start)) = pending {
+                    write_gif_frame(&mut encoder, image, start, self.frames, self.fps)?;
+                }
+                Ok(encoder.into_inner()?)
+            }
+            Encoder::Y4m { w, .. } => Ok(w),
+        }
+    }
+}
+
+/// Write `image` as a GIF frame shown from frame `start` until frame `end`. GIF frame delays are
+/// counted in hundredths of a second, so each frame is rounded to the nearest, keeping the total
+/// in time.
+fn write_gif_frame<W: Write>(
+    encoder: &mut gif::Encoder<W>,
+    image: Image,
+    start: u64,
+    end: u64,
+    fps: u64,
+) -> Result<(), ErrorKind> {
+    let centiseconds = |frame: u64| (frame * 100 + fps / 2) / fps;
+    let (width, height) = (image.width as u16, image.height as u16);
+    let mut frame = gif::Frame::from_indexed_pixels(width, height, image.pixels, None);
+    frame.delay = (centiseconds(end) - centiseconds(start)).min(u16::MAX as u64) as u16;
+    encoder
+        .write_frame(&frame)
+        .map_err(|e| ErrorKind::ImageErr(e.to_string()))
+}
+
+/// Convert a colour to its Y, U and V components, as used for standard definition video.
+fn yuv([r, g, b]: Rgb) -> Rgb {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    [y as u8, u as u8, v as u8]
+}
+
+/// The path of a recording started on frame `frame` of the ROM at `rom_path`, which sits beside
+/// the ROM. For example, a recording from frame 120 of `pong.ch8` is `pong.ch8.frame120.gif`.
+pub fn default_path(rom_path: &Path, frame: u64) -> PathBuf {
+    let mut name = rom_path.as_os_str().to_owned();
+    name.push(format!(".frame{}.gif", frame));
+    PathBuf::from(name)
+}
+
+/// A renderer which passes every frame on to another renderer, while recording them to a file
+/// whenever a recording has been started.
+pub struct Recorder {
+    inner: Box<dyn Render>,
+    /// The recording in progress, if there is one.
+    recording: Option<Recording<BufWriter<File>>>,
+    fps: u64,
+    scale: usize,
+    theme: Theme,
+}
+
+impl Recorder {
+    /// Wrap `inner`, so that recordings can be made of what it draws. Recordings play at `fps`
+    /// frames per second, which should be the rate frames are rendered at, and are drawn at
+    /// `scale` in the colours of `theme`, as for `Recording::new`.
+    pub fn new(inner: Box<dyn Render>, fps: u64, scale: usize, theme: Theme) -> Self {
+        Recorder {
+            inner,
+            recording: None,
+            fps,
+            scale,
+            theme,
+        }
+    }
+
+    /// Whether a recording is in progress.
+    pub fn recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Start recording to `path`, in the format chosen by `Format::from_path`, finishing any
+    /// recording already in progress.
+    pub fn start(&mut self, path: &Path) -> Result<(), ErrorKind> {
+        self.stop()?;
+        let w = BufWriter::new(File::create(path)?);
+        let format = Format::from_path(path);
+        self.recording = Some(Recording::new(w, format, self.fps, self.scale, self.theme)?);
+        Ok(())
+    }
+
+    /// Finish the recording in progress, if there is one.
+    pub fn stop(&mut self) -> Result<(), ErrorKind> {
+        if let Some(recording) = self.recording.take() {
+            recording.finish()?.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Render for Recorder {
+    fn render(&mut self, display: &Framebuffer) -> Result<(), ErrorKind> {
+        self.inner.render(display)?;
+        if let Some(recording) = self.recording.as_mut() {
+            recording.add(display)?;
+        }
+        Ok(())
+    }
+
+    fn resize(&mut self, columns: u16, rows: u16) -> Result<(), ErrorKind> {
+        self.inner.resize(columns, rows)
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // Implicitly ignore errors here (can't return them)
+        let _ = self.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gif() {
+        let mut screen = Framebuffer::new();
+        let mut recording = Recording::new(Vec::new(), Format::Gif, 60, 1, Theme::Classic).unwrap();
+        recording.add(&screen).unwrap();
+        recording.add(&screen).unwrap();
+        screen.flip(0, 0, 1);
+        recording.add(&screen).unwrap();
+        let gif = recording.finish().unwrap();
+
+        // The two identical frames are merged, and delays add up to 3/60 of a second
+        let mut decoder = gif::DecodeOptions::new().read_info(&gif[..]).unwrap();
+        assert_eq!((128, 64), (decoder.width(), decoder.height()));
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        assert_eq!(vec![3, 2], delays);
+    }
+
+    #[test]
+    fn test_y4m() {
+        let mut screen = Framebuffer::new();
+        screen.flip(0, 0, 1);
+        let mut recording = Recording::new(Vec::new(), Format::Y4m, 60, 1, Theme::Classic).unwrap();
+        recording.add(&screen).unwrap();
+        let y4m = recording.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\nFRAME\n";
+        assert_eq!(&header[..], &y4m[..header.len()]);
+        let planes = &y4m[header.len()..];
+        assert_eq!(128 * 64 * 3, planes.len());
+        // Low resolution pixels are drawn twice as large, so the white pixel is 2x2
+        assert_eq!(&[235, 235, 16], &planes[..3]);
+        assert_eq!(&[235, 235, 16], &planes[128..131]);
+        assert_eq!(128, planes[128 * 64]);
+
+        assert_eq!(Format::Y4m, Format::from_path(Path::new("run.y4m")));
+        assert_eq!(Format::Gif, Format::from_path(Path::new("run.gif")));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::{
+    display::{
+        kitty, sixel, BrailleRenderer, DebugRenderer, DummyRenderer, HalfBlockRenderer,
+        KittyRenderer, Render, SixelRenderer, TerminalRenderer, Theme,
+    },
+    error::{DisplayError, ErrorKind},
+};
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    str::FromStr,
+};
+
+/// A single `key=value` option for a renderer, as given to `--display-opt`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisplayOption {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for DisplayOption {
+    type Err = DisplayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if !key.trim().is_empty() => Ok(DisplayOption {
+                key: key.trim().to_lowercase(),
+                value: value.trim().to_owned(),
+            }),
+            _ => Err(DisplayError(format!("Expected key=value, found {:?}", s))),
+        }
+    }
+}
+
+/// The options a renderer is built with, looked up by key.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DisplayOptions(BTreeMap<String, String>);
+
+impl DisplayOptions {
+    /// Collect options, where later options replace earlier ones with the same key.
+    pub fn new(options: &[DisplayOption]) -> Self {
+        DisplayOptions(
+            options
+                .iter()
+                .map(|opt| (opt.key.clone(), opt.value.clone()))
+                .collect(),
+        )
+    }
+
+    /// The value of the option `key` parsed as a `T`, or `default` if it wasn't given.
+    pub fn get<T: FromStr>(&self, key: &str, default: T) -> Result<T, DisplayError> {
+        match self.0.get(key) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| DisplayError(format!("Invalid value {:?} for {}", value, key))),
+            None => Ok(default),
+        }
+    }
+
+    /// The keys of every option given.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+}
+
+/// Everything needed to offer a renderer by name.
+#[derive(Copy, Clone)]
+pub struct RendererInfo {
+    /// The name the renderer is chosen with, which should be lower case.
+    pub name: &'static str,
+    /// A short, one line description.
+    pub description: &'static str,
+    /// The options the renderer understands, each with a short description. Any other option is
+    /// rejected before the renderer is built.
+    pub options: &'static [(&'static str, &'static str)],
+    /// Create the renderer with the given options.
+    pub build: fn(&DisplayOptions) -> Result<Box<dyn Render>, ErrorKind>,
+}
+
+/// The renderers which can be chosen with the `display` argument.
+///
+/// `Registry::default` holds the renderers built into this crate, and others, including ones
+/// from other crates, can be added with `Registry::register`.
+pub struct Registry(Vec<RendererInfo>);
+
+impl Registry {
+    /// A registry with no renderers at all.
+    pub fn empty() -> Self {
+        Registry(Vec::new())
+    }
+
+    /// Add a renderer, replacing any existing renderer with the same name.
+    pub fn register(&mut self, info: RendererInfo) {
+        match self
+            .0
+            .iter_mut()
+            .find(|existing| existing.name == info.name)
+        {
+            Some(existing) => *existing = info,
+            None => self.0.push(info),
+        }
+    }
+
+    /// The renderer called `name`, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&RendererInfo> {
+        let name = name.trim().to_lowercase();
+        self.0.iter().find(|info| info.name == name)
+    }
+
+    /// Every renderer, in the order they were registered.
+    pub fn renderers(&self) -> impl Iterator<Item = &RendererInfo> {
+        self.0.iter()
+    }
+
+    /// Build the renderer called `name`, after checking that it understands every option.
+    pub fn build(
+        &self,
+        name: &str,
+        options: &DisplayOptions,
+    ) -> Result<Box<dyn Render>, ErrorKind> {
+        let info = self
+            .get(name)
+            .ok_or_else(|| DisplayError(format!("Unknown display {:?}", name)))?;
+        if let Some(key) = options
+            .keys()
+            .find(|key| !info.options.iter().any(|(known, _)| known == key))
+        {
+            let msg = format!("The {} display has no option {:?}", info.name, key);
+            return Err(DisplayError(msg).into());
+        }
+        (info.build)(options)
+    }
+
+    /// Write a list of the renderers and their options, as printed by `--list-displays`.
+    pub fn list(&self, w: &mut impl Write) -> io::Result<()> {
+        for info in self.renderers() {
+            writeln!(w, "{:<10} {}", info.name, info.description)?;
+            for (key, description) in info.options {
+                writeln!(w, "    {:<10} {}", key, description)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The option for choosing a `Theme`, which every renderer drawing in colour takes.
+const THEME_OPTION: (&str, &str) = (
+    "theme",
+    "classic, green, amber, gameboy, or colours like #000000,#ffffff.",
+);
+
+/// The options of renderers which draw the display as an image.
+const IMAGE_OPTIONS: &[(&str, &str)] = &[
+    (
+        "scale",
+        "Width and height in image pixels of each pixel, at least 1. Defaults to 8.",
+    ),
+    THEME_OPTION,
+    (
+        "force",
+        "Draw images even if the terminal doesn't seem to support them.",
+    ),
+];
+
+/// Build an image renderer with `build` if the terminal seems to support it, according to
+/// `supported`, and otherwise fall back to the terminal renderer.
+fn build_image_renderer<R: Render + 'static>(
+    options: &DisplayOptions,
+    name: &str,
+    supported: fn() -> bool,
+    build: fn(usize, Theme) -> Result<R, ErrorKind>,
+) -> Result<Box<dyn Render>, ErrorKind> {
+    let theme = options.get("theme", Theme::default())?;
+    let scale = options.get("scale", 8)?;
+    if scale == 0 {
+        let msg = format!("The {} display needs a scale of at least 1", name);
+        return Err(DisplayError(msg).into());
+    }
+    if options.get("force", false)? || supported() {
+        Ok(Box::new(build(scale, theme)?))
+    } else {
+        log::warn!("The terminal doesn't seem to support {} images", name);
+        Ok(Box::new(TerminalRenderer::with_options(0, true, theme, 0)?))
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut res = Registry::empty();
+        res.register(RendererInfo {
+            name: "terminal",
+            description: "Draws each pixel as coloured spaces. The default.",
+            options: &[
+                ("scale", "Columns per pixel, or 0 to fit the terminal."),
+                ("border", "Whether to draw a border, true or false."),
+                THEME_OPTION,
+                (
+                    "fade",
+                    "Frames for pixels to fade out over, to hide flicker.",
+                ),
+            ],
+            build: |options| {
+                let scale = options.get("scale", 0)?;
+                let border = options.get("border", true)?;
+                let theme = options.get("theme", Theme::default())?;
+                let fade = options.get("fade", 0)?;
+                let renderer = TerminalRenderer::with_options(scale, border, theme, fade)?;
+                Ok(Box::new(renderer))
+            },
+        });
+        res.register(RendererInfo {
+            name: "halfblock",
+            description: "Draws two pixels in each character, so pixels are square.",
+            options: &[THEME_OPTION],
+            build: |options| {
+                let theme = options.get("theme", Theme::default())?;
+                Ok(Box::new(HalfBlockRenderer::new(theme)?))
+            },
+        });
+        res.register(RendererInfo {
+            name: "braille",
+            description: "Draws 2x4 pixels in each character, for small terminals.",
+            options: &[THEME_OPTION],
+            build: |options| {
+                let theme = options.get("theme", Theme::default())?;
+                Ok(Box::new(BrailleRenderer::new(theme)?))
+            },
+        });
+        res.register(RendererInfo {
+            name: "sixel",
+            description: "Draws an image with Sixel graphics, if the terminal supports them.",
+            options: IMAGE_OPTIONS,
+            build: |options| {
+                build_image_renderer(options, "Sixel", sixel::supported, SixelRenderer::new)
+            },
+        });
+        res.register(RendererInfo {
+            name: "kitty",
+            description: "Draws an image with the Kitty graphics protocol, if supported.",
+            options: IMAGE_OPTIONS,
+            build: |options| {
+                build_image_renderer(options, "Kitty", kitty::supported, KittyRenderer::new)
+            },
+        });
+        res.register(RendererInfo {
+            name: "debug",
+            description: "Prints every changed frame to stdout, for debugging.",
+            options: &[],
+            build: |_| Ok(Box::new(DebugRenderer::new())),
+        });
+        res.register(RendererInfo {
+            name: "noop",
+            description: "Draws nothing, for testing game logic.",
+            options: &[],
+            build: |_| Ok(Box::new(DummyRenderer)),
+        });
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::empty();
+        registry.register(RendererInfo {
+            name: "custom",
+            description: "A renderer from somewhere else.",
+            options: &[("speed", "How fast to draw.")],
+            build: |options| {
+                assert_eq!(3, options.get("speed", 1).unwrap());
+                Ok(Box::new(DummyRenderer))
+            },
+        });
+
+        let opt = |s: &str| s.parse::<DisplayOption>().unwrap();
+        let options = DisplayOptions::new(&[opt("speed=3")]);
+        assert!(registry.build("Custom", &options).is_ok());
+        assert!(registry.build("missing", &options).is_err());
+        let options = DisplayOptions::new(&[opt("colour = red")]);
+        assert!(registry.build("custom", &options).is_err());
+        assert!("speed".parse::<DisplayOption>().is_err());
+        let options = DisplayOptions::new(&[opt("scale=0"), opt("force=true")]);
+        for name in &["sixel", "kitty"] {
+            let res = Registry::default().build(name, &options);
+            assert!(matches!(res, Err(ErrorKind::DisplayErr(e)) if e.0.contains("scale")));
+        }
+
+        let mut out = Vec::new();
+        registry.list(&mut out).unwrap();
+        assert_eq!(
+            "custom     A renderer from somewhere else.\n    speed      How fast to draw.\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{
+    display::{
+        image::Image,
+        theme::{Rgb, Theme},
+    },
+    emulator::Framebuffer,
+    error::ErrorKind,
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// The file formats screenshots can be written in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    /// The binary portable pixmap format, which is simple enough to read without a library.
+    Ppm,
+}
+
+impl Format {
+    /// The format to write to `path` in, which is PPM for a `.ppm` extension and PNG otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("ppm") => Format::Ppm,
+            _ => Format::Png,
+        }
+    }
+}
+
+/// Write `image` to `w` in the given format, drawing each pixel value in the matching colour.
+pub fn write(
+    mut w: impl Write,
+    format: Format,
+    image: &Image,
+    colours: &[Rgb],
+) -> Result<(), ErrorKind> {
+    let rgb = image.to_rgb(colours);
+    match format {
+        Format::Png => {
+            let mut encoder = png::Encoder::new(w, image.width as u32, image.height as u32);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder
+                .write_header()
+                .map_err(|e| ErrorKind::ImageErr(e.to_string()))?;
+            writer
+                .write_image_data(&rgb)
+                .map_err(|e| ErrorKind::ImageErr(e.to_string()))?;
+        }
+        Format::Ppm => {
+            write!(w, "P6\n{} {}\n255\n", image.width, image.height)?;
+            w.write_all(&rgb)?;
+        }
+    }
+    Ok(())
+}
+
+/// Save a screenshot of `screen` to `path`, with each pixel drawn as a `scale` by `scale` block
+/// in the colours of `theme`. The format is chosen by `Format::from_path`.
+pub fn save(
+    path: &Path,
+    screen: &Framebuffer,
+    scale: usize,
+    theme: Theme,
+) -> Result<(), ErrorKind> {
+    let image = Image::new(screen, scale);
+    let mut w = BufWriter::new(File::create(path)?);
+    write(&mut w, Format::from_path(path), &image, &theme.rgb())?;
+    w.flush()?;
+    Ok(())
+}
+
+/// The path of a screenshot taken on frame `frame` of the ROM at `rom_path`, which sits beside
+/// the ROM. For example, frame 120 of `pong.ch8` is `pong.ch8.frame120.png`.
+pub fn default_path(rom_path: &Path, frame: u64) -> PathBuf {
+    let mut name = rom_path.as_os_str().to_owned();
+    name.push(format!(".frame{}.png", frame));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![0, 1],
+        };
+        let colours = [[0, 0, 0], [51, 255, 51]];
+        let mut ppm = Vec::new();
+        write(&mut ppm, Format::Ppm, &image, &colours).unwrap();
+        assert_eq!(b"P6\n2 1\n255\n\x00\x00\x00\x33\xff\x33", &ppm[..]);
+
+        let mut encoded = Vec::new();
+        write(&mut encoded, Format::Png, &image, &colours).unwrap();
+        let mut reader = png::Decoder::new(&encoded[..]).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!((2, 1), (info.width, info.height));
+        assert_eq!(&ppm[11..], &decoded[..info.buffer_size()]);
+
+        assert_eq!(Format::Ppm, Format::from_path(Path::new("shot.PPM")));
+        assert_eq!(Format::Png, Format::from_path(Path::new("shot")));
+        assert_eq!(
+            Path::new("roms/pong.ch8.frame120.png"),
+            default_path(Path::new("roms/pong.ch8"), 120)
+        );
+    }
+}
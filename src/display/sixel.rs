@@ -0,0 +1,197 @@
+use crate::{
+    display::{
+        image::Image,
+        terminal::{enter_screen, leave_screen},
+        theme::{Rgb, Theme},
+        Render,
+    },
+    emulator::Framebuffer,
+    error::ErrorKind,
+};
+use crossterm::{cursor, QueueableCommand};
+use std::{
+    env,
+    fmt::Write as _,
+    io::{stdout, Write},
+};
+
+/// A renderer which draws the display as an image, using the Sixel graphics format understood
+/// by terminals such as mlterm, foot, WezTerm and recent versions of xterm.
+pub struct SixelRenderer {
+    /// The last frame drawn, or `None` if nothing has been drawn yet.
+    prev: Option<Framebuffer>,
+    /// Width and height in image pixels of each pixel of the display.
+    scale: usize,
+    /// The colour of each pixel value.
+    colours: [Rgb; 4],
+}
+
+impl SixelRenderer {
+    /// Create a new SixelRenderer, which draws each pixel as a `scale` by `scale` block in the
+    /// colours of `theme`, clearing the screen.
+    pub fn new(scale: usize, theme: Theme) -> Result<SixelRenderer, ErrorKind> {
+        enter_screen()?;
+        let mut res = SixelRenderer {
+            prev: None,
+            scale,
+            colours: theme.rgb(),
+        };
+        res.render(&Framebuffer::new())?;
+        Ok(res)
+    }
+}
+
+impl Render for SixelRenderer {
+    fn render(&mut self, screen: &Framebuffer) -> Result<(), ErrorKind> {
+        // Skip rendering if nothing has changed
+        if self.prev.as_ref() == Some(screen) {
+            return Ok(());
+        }
+
+        let image = Image::new(screen, self.scale);
+        let mut stdout = stdout();
+        stdout.queue(cursor::MoveTo(0, 0))?;
+        write!(stdout, "{}", encode(&image, &self.colours))?;
+        stdout.flush()?;
+        self.prev = Some(*screen);
+        Ok(())
+    }
+}
+
+impl Drop for SixelRenderer {
+    fn drop(&mut self) {
+        leave_screen();
+    }
+}
+
+/// Whether the terminal seems to understand Sixel graphics, going by its environment variables.
+pub fn supported() -> bool {
+    detect(|name| env::var(name).ok())
+}
+
+/// Whether the terminal described by the environment variables from `var` understands Sixel
+/// graphics. There's no way to ask every terminal directly, so this recognises the ones known
+/// to support it.
+fn detect(var: impl Fn(&str) -> Option<String>) -> bool {
+    let term = var("TERM").unwrap_or_default();
+    let program = var("TERM_PROGRAM").unwrap_or_default();
+    ["mlterm", "foot", "yaft", "contour"]
+        .iter()
+        .any(|name| term.contains(name))
+        || ["WezTerm", "iTerm.app", "mintty", "contour"].contains(&program.as_str())
+}
+
+/// Encode `image` as a Sixel escape sequence, drawing each pixel value in the matching colour.
+///
+/// The image is split into bands of 6 rows. Each band is drawn once for each colour in it, with
+/// one character per column saying which of its 6 pixels have that colour, and runs of the same
+/// character are compressed.
+pub fn encode(image: &Image, colours: &[Rgb]) -> String {
+    // Start in pixel aspect ratio 1:1, with the background left as it is
+    let mut out = String::from("\x1bP0;1;0q");
+    let _ = write!(out, "\"1;1;{};{}", image.width, image.height);
+    for (idx, colour) in colours.iter().enumerate() {
+        // Colour components are given as percentages
+        let [r, g, b] = colour.map(|c| (c as u32 * 100 + 127) / 255);
+        let _ = write!(out, "#{};2;{};{};{}", idx, r, g, b);
+    }
+
+    for top in (0..image.height).step_by(6) {
+        if top > 0 {
+            out.push('-');
+        }
+        let rows = &image.pixels[top * image.width..(top + 6).min(image.height) * image.width];
+        let mut first = true;
+        for colour in 0..colours.len() as u8 {
+            if !rows.contains(&colour) {
+                continue;
+            }
+            // Go back to the start of the band to draw the next colour over it
+            if !first {
+                out.push('$');
+            }
+            first = false;
+            let _ = write!(out, "#{}", colour);
+
+            let sixels = (0..image.width).map(|x| {
+                let bits = rows
+                    .chunks_exact(image.width)
+                    .enumerate()
+                    .filter(|(_, row)| row[x] == colour)
+                    .fold(0, |bits, (y, _)| bits | 1 << y);
+                (63 + bits) as u8 as char
+            });
+            let mut run: Option<(char, usize)> = None;
+            for sixel in sixels.map(Some).chain(std::iter::once(None)) {
+                match run {
+                    Some((c, n)) if Some(c) == sixel => run = Some((c, n + 1)),
+                    _ => {
+                        match run {
+                            Some((c, n)) if n > 3 => {
+                                let _ = write!(out, "!{}{}", n, c);
+                            }
+                            Some((c, n)) => out.extend(std::iter::repeat_n(c, n)),
+                            None => {}
+                        }
+                        run = sixel.map(|c| (c, 1));
+                    }
+                }
+            }
+        }
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        // A 5x7 image with a diagonal line, then a solid bar across the bottom
+        let mut pixels = vec![0; 5 * 7];
+        for i in 0..5 {
+            pixels[i * 5 + i] = 1;
+            pixels[6 * 5 + i] = 2;
+        }
+        let image = Image {
+            width: 5,
+            height: 7,
+            pixels,
+        };
+        let colours = [[0, 0, 0], [255, 255, 255], [255, 0, 0]];
+        assert_eq!(
+            concat!(
+                "\x1bP0;1;0q\"1;1;5;7",
+                "#0;2;0;0;0#1;2;100;100;100#2;2;100;0;0",
+                "#0}|zvn$#1@ACGO",
+                "-#2!5@",
+                "\x1b\\"
+            ),
+            encode(&image, &colours)
+        );
+
+        let mut solid = image;
+        solid.pixels = vec![0; 5 * 7];
+        assert!(encode(&solid, &colours).contains("#0!5~-#0!5@"));
+    }
+
+    #[test]
+    fn test_detect() {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert!(detect(env(&[("TERM", "foot")])));
+        assert!(detect(env(&[
+            ("TERM", "xterm-256color"),
+            ("TERM_PROGRAM", "WezTerm")
+        ])));
+        assert!(!detect(env(&[("TERM", "xterm-256color")])));
+        assert!(!detect(env(&[])));
+    }
+}
@@ -1,4 +1,11 @@
-use crate::{display::Render, emulator::State, error::ErrorKind};
+use crate::{
+    display::{
+        theme::{ColourDepth, Fade, Theme},
+        Render,
+    },
+    emulator::Framebuffer,
+    error::ErrorKind,
+};
 use crossterm::{
     cursor,
     style::{self, Color},
@@ -7,82 +14,275 @@ use crossterm::{
 };
 use itertools::Itertools;
 use std::{
-    cmp::Ordering,
     io::{stdout, Write},
+    iter,
 };
 
+/// The largest scale chosen when fitting the display to the terminal.
+const MAX_SCALE: usize = 16;
+
+/// Where the display is drawn in the terminal, and how large each pixel is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Layout {
+    /// Columns per pixel. Pixels are `scale / 2` rows tall, or 1 row at scales below 2, which
+    /// keeps them about square, since character cells are twice as tall as they are wide.
+    scale: usize,
+    /// Column of the left edge of the display, inside the border.
+    left: usize,
+    /// Row of the top edge of the display, inside the border.
+    top: usize,
+}
+
+impl Layout {
+    /// Fit a `width` by `height` display into a terminal area of `area` columns and rows. A
+    /// `scale` of 0 picks the largest scale which fits. If the display doesn't fit at all, the
+    /// size of terminal it needs is returned instead.
+    fn fit(
+        area: (usize, usize),
+        (width, height): (usize, usize),
+        scale: usize,
+        border: bool,
+    ) -> Result<Layout, (usize, usize)> {
+        let border = if border { 2 } else { 0 };
+        let needed = |scale: usize| {
+            let rows = Layout::pixel_rows(scale);
+            (width * scale + border, height * rows + border)
+        };
+        let fits = |scale: usize| {
+            let (columns, rows) = needed(scale);
+            columns <= area.0 && rows <= area.1
+        };
+
+        let scale = match scale {
+            0 => (1..=MAX_SCALE).rev().find(|&scale| fits(scale)),
+            scale => Some(scale).filter(|&scale| fits(scale)),
+        }
+        .ok_or_else(|| needed(scale.max(1)))?;
+        let (columns, rows) = needed(scale);
+        Ok(Layout {
+            scale,
+            left: (area.0 - columns + border) / 2,
+            top: (area.1 - rows + border) / 2,
+        })
+    }
+
+    /// Rows per pixel at the given scale.
+    fn pixel_rows(scale: usize) -> usize {
+        (scale / 2).max(1)
+    }
+}
+
 /// The default, most fully-featured renderer.
+///
+/// The display is centred in the terminal with a border around it, at the largest whole scale
+/// that fits, unless a fixed scale is asked for. If the terminal is too small for the display, a
+/// message saying how large it needs to be is shown instead.
+///
+/// Pixels can be made to fade out over a few frames when they're turned off, which hides the
+/// flicker of sprites being erased and redrawn.
 pub struct TerminalRenderer {
-    prev: [bool; 2048],
+    /// The width of the last frame drawn and the palette index of each of its pixels, or `None`
+    /// if the whole screen must be redrawn.
+    prev: Option<(usize, Vec<usize>)>,
+    /// The colours to draw with, indexed by the shades from `fade`.
+    palette: Vec<Color>,
+    /// Pixels which are fading out.
+    fade: Fade,
+    /// Columns per pixel, or 0 to fit the display to the terminal.
+    scale: usize,
+    /// Whether to draw a border around the display.
+    border: bool,
+    /// The columns and rows of the terminal which can be drawn to.
+    area: (u16, u16),
+    /// Whether the terminal is too small, and the message saying so has been shown.
+    too_small: bool,
 }
 
 impl TerminalRenderer {
-    /// Create a new TerminalRenderer, clearing the screen.
+    /// Create a new TerminalRenderer which fits the display to the terminal, clearing the screen.
     pub fn new() -> Result<TerminalRenderer, ErrorKind> {
-        let mut stdout = stdout();
-        terminal::enable_raw_mode()?;
-        stdout
-            .queue(cursor::Hide)?
-            .queue(terminal::Clear(ClearType::All))?;
+        TerminalRenderer::with_options(0, true, Theme::default(), 0)
+    }
 
-        // Screen starts as blank, but let's set it to be all-white to render blank screen
-        let mut res = TerminalRenderer { prev: [true; 2048] };
+    /// Create a new TerminalRenderer, clearing the screen. Each pixel is `scale` columns wide, or
+    /// as large as fits in the terminal if `scale` is 0, and takes `fade` frames to fade out.
+    pub fn with_options(
+        scale: usize,
+        border: bool,
+        theme: Theme,
+        fade: u8,
+    ) -> Result<TerminalRenderer, ErrorKind> {
+        enter_screen()?;
+
+        // Nothing has been drawn yet, so the first frame is drawn in full
+        let mut res = TerminalRenderer {
+            prev: None,
+            palette: theme.palette(ColourDepth::detect(), fade),
+            fade: Fade::new(fade),
+            scale,
+            border,
+            area: terminal::size()?,
+            too_small: false,
+        };
 
         // Let's render an all-blank screen first
-        res.render([false; 2048])?;
+        res.render(&Framebuffer::new())?;
         Ok(res)
     }
+
+    /// Draw a border just outside a `width` by `height` region with its top left at `(x, y)`.
+    fn draw_border(x: usize, y: usize, width: usize, height: usize) -> Result<(), ErrorKind> {
+        let mut stdout = stdout();
+        let horizontal = "─".repeat(width);
+        stdout.queue(cursor::MoveTo(x as u16 - 1, y as u16 - 1))?;
+        write!(stdout, "┌{}┐", horizontal)?;
+        for row in y..y + height {
+            stdout.queue(cursor::MoveTo(x as u16 - 1, row as u16))?;
+            write!(stdout, "│")?;
+            stdout.queue(cursor::MoveTo((x + width) as u16, row as u16))?;
+            write!(stdout, "│")?;
+        }
+        stdout.queue(cursor::MoveTo(x as u16 - 1, (y + height) as u16))?;
+        write!(stdout, "└{}┘", horizontal)?;
+        Ok(())
+    }
 }
 
 impl Render for TerminalRenderer {
-    fn render(&mut self, screen: [bool; 2048]) -> Result<(), ErrorKind> {
+    fn render(&mut self, screen: &Framebuffer) -> Result<(), ErrorKind> {
+        let width = screen.width();
+        let shades = self.fade.shades(screen);
         // Skip rendering if nothing has changed
-        if self.prev.cmp(&screen) == Ordering::Equal {
+        if matches!(&self.prev, Some((prev_width, prev)) if *prev_width == width && *prev == shades)
+        {
             return Ok(());
         }
 
         let mut stdout = stdout();
-        // Do line-by-line rendering
-        for ((y, line), old_line) in screen
-            .chunks_exact(State::WIDTH)
-            .enumerate()
-            .zip(self.prev.chunks_exact(State::WIDTH))
-        {
+        let area = (self.area.0 as usize, self.area.1 as usize);
+        let size = (screen.width(), screen.height());
+        let layout = match Layout::fit(area, size, self.scale, self.border) {
+            Ok(layout) => layout,
+            Err((columns, rows)) => {
+                if !self.too_small {
+                    stdout
+                        .queue(style::ResetColor)?
+                        .queue(terminal::Clear(ClearType::All))?
+                        .queue(cursor::MoveTo(0, 0))?;
+                    write!(
+                        stdout,
+                        "Terminal too small: the display needs {}x{}, but only {}x{} is available",
+                        columns, rows, area.0, area.1
+                    )?;
+                    stdout.flush()?;
+                    self.too_small = true;
+                }
+                self.prev = None;
+                return Ok(());
+            }
+        };
+
+        // A change in resolution leaves the old image behind, so start from a blank terminal
+        let prev = self
+            .prev
+            .take()
+            .filter(|(prev_width, _)| *prev_width == width);
+        if prev.is_none() {
+            stdout
+                .queue(style::ResetColor)?
+                .queue(terminal::Clear(ClearType::All))?;
+            if self.border {
+                let width = size.0 * layout.scale;
+                let height = size.1 * Layout::pixel_rows(layout.scale);
+                TerminalRenderer::draw_border(layout.left, layout.top, width, height)?;
+            }
+        }
+
+        // Do line-by-line rendering, treating every line as changed if there is no previous frame
+        let old_lines = prev
+            .as_ref()
+            .into_iter()
+            .flat_map(|(_, prev)| prev.chunks_exact(width).map(Some))
+            .chain(iter::repeat(None));
+        let pixel_rows = Layout::pixel_rows(layout.scale);
+        for ((y, line), old_line) in shades.chunks_exact(width).enumerate().zip(old_lines) {
             // Skip per-line rendering if nothing has changed
-            if line.cmp(&old_line) == Ordering::Equal {
+            if old_line == Some(line) {
                 continue;
             }
 
-            stdout.queue(cursor::MoveTo(0, y as u16))?;
-            // RLE to avoid colour switching when possible
-            // group_by(id) for booleans will group equal elements
-            for (key, group) in line.into_iter().group_by(|x| *x).into_iter() {
-                stdout.queue(style::SetBackgroundColor(if *key {
-                    Color::White
-                } else {
-                    Color::Black
-                }))?;
-                for _ in group {
-                    write!(stdout, " ")?;
+            for row in 0..pixel_rows {
+                let row = layout.top + y * pixel_rows + row;
+                stdout.queue(cursor::MoveTo(layout.left as u16, row as u16))?;
+                // RLE to avoid colour switching when possible
+                // group_by(id) will group runs of pixels of the same colour
+                for (key, group) in line.iter().group_by(|x| *x).into_iter() {
+                    stdout.queue(style::SetBackgroundColor(self.palette[*key]))?;
+                    write!(stdout, "{:1$}", "", group.count() * layout.scale)?;
                 }
             }
         }
 
         stdout.flush()?;
-        self.prev = screen;
+        self.prev = Some((width, shades));
+        self.too_small = false;
+        Ok(())
+    }
+
+    fn resize(&mut self, columns: u16, rows: u16) -> Result<(), ErrorKind> {
+        // Everything moves, so the whole display has to be drawn again
+        self.area = (columns, rows);
+        self.prev = None;
+        self.too_small = false;
         Ok(())
     }
 }
 
 impl Drop for TerminalRenderer {
     fn drop(&mut self) {
-        // Implicitly ignore errors here (can't return them)
-        let mut stdout = stdout();
-        let _ = terminal::disable_raw_mode();
-        let _ = stdout.queue(style::ResetColor);
-        let _ = stdout.queue(terminal::Clear(ClearType::All));
-        let _ = stdout.queue(cursor::MoveTo(0, 0));
-        let _ = stdout.queue(cursor::Show);
-        let _ = stdout.flush();
+        leave_screen();
+    }
+}
+
+/// Prepare the terminal for drawing, by entering raw mode, hiding the cursor and clearing the
+/// screen. Every renderer which draws to the terminal calls this when it is created.
+pub fn enter_screen() -> Result<(), ErrorKind> {
+    terminal::enable_raw_mode()?;
+    stdout()
+        .queue(cursor::Hide)?
+        .queue(terminal::Clear(ClearType::All))?;
+    Ok(())
+}
+
+/// Put the terminal back the way it was before `enter_screen`, for when a renderer is dropped.
+pub fn leave_screen() {
+    // Implicitly ignore errors here (can't return them)
+    let mut stdout = stdout();
+    let _ = terminal::disable_raw_mode();
+    let _ = stdout.queue(style::ResetColor);
+    let _ = stdout.queue(terminal::Clear(ClearType::All));
+    let _ = stdout.queue(cursor::MoveTo(0, 0));
+    let _ = stdout.queue(cursor::Show);
+    let _ = stdout.flush();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let fit = |area, size, scale, border| {
+            Layout::fit(area, size, scale, border).map(|l| (l.scale, l.left, l.top))
+        };
+        // The largest scale which fits is chosen, and the display is centred
+        assert_eq!(Ok((1, 8, 4)), fit((80, 40), (64, 32), 0, false));
+        assert_eq!(Ok((2, 16, 9)), fit((160, 50), (64, 32), 0, true));
+        assert_eq!(Ok((4, 22, 8)), fit((300, 80), (64, 32), 0, true));
+
+        // A fixed scale is used as it is, as long as it fits
+        assert_eq!(Ok((2, 86, 24)), fit((300, 80), (64, 32), 2, true));
+        assert_eq!(Err((258, 66)), fit((200, 80), (64, 32), 4, true));
+        assert_eq!(Err((130, 66)), fit((100, 40), (128, 64), 0, true));
     }
 }
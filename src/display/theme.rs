@@ -0,0 +1,304 @@
+use crate::{emulator::Framebuffer, error::DisplayError};
+use crossterm::style::Color;
+use std::{env, str::FromStr};
+
+/// A colour as red, green and blue components.
+pub type Rgb = [u8; 3];
+
+/// The colours the display is drawn in, one for each value a pixel can have. Plain CHIP-8
+/// programs only use the first two, while XO-CHIP programs drawing to both bit-planes use all
+/// four.
+///
+/// Themes are written as one of `classic`, `green`, `amber` and `gameboy`, or as a list of two
+/// or four colours separated by commas, such as `#000000,#ffffff`. With two colours, pixels on
+/// the second plane are drawn in the second colour too.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Theme {
+    /// Black and white, in the terminal's own colours.
+    #[default]
+    Classic,
+    /// A green phosphor monitor.
+    Green,
+    /// An amber phosphor monitor.
+    Amber,
+    /// The four shades of green of the original Game Boy.
+    GameBoy,
+    /// Colours chosen by the user.
+    Custom([Rgb; 4]),
+}
+
+impl FromStr for Theme {
+    type Err = DisplayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "classic" => return Ok(Theme::Classic),
+            "green" => return Ok(Theme::Green),
+            "amber" => return Ok(Theme::Amber),
+            "gameboy" => return Ok(Theme::GameBoy),
+            _ => {}
+        }
+        let colours = s
+            .split(',')
+            .map(|colour| parse_rgb(colour.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match colours[..] {
+            [off, on] => Ok(Theme::Custom([off, on, on, on])),
+            [off, first, second, both] => Ok(Theme::Custom([off, first, second, both])),
+            _ => Err(DisplayError(format!(
+                "Expected a theme name, or two or four colours, found {:?}",
+                s
+            ))),
+        }
+    }
+}
+
+impl Theme {
+    /// The colour of each pixel value.
+    pub fn rgb(self) -> [Rgb; 4] {
+        match self {
+            Theme::Classic => [[0, 0, 0], [255, 255, 255], [205, 0, 0], [205, 205, 0]],
+            Theme::Green => [[0, 20, 0], [51, 255, 51], [0, 170, 0], [170, 255, 170]],
+            Theme::Amber => [[20, 10, 0], [255, 176, 0], [170, 100, 0], [255, 220, 130]],
+            Theme::GameBoy => [[15, 56, 15], [155, 188, 15], [48, 98, 48], [139, 172, 15]],
+            Theme::Custom(colours) => colours,
+        }
+    }
+
+    /// The terminal colours to draw with, where pixels can take `fade` frames to fade out.
+    ///
+    /// The first four colours are those of each pixel value. After them come the colours of
+    /// pixels fading out, as counted by `Fade`: `fade` colours for value 1, then for value 2 and
+    /// for value 3, each further from the pixel's colour and closer to the background.
+    pub fn palette(self, depth: ColourDepth, fade: u8) -> Vec<Color> {
+        let rgb = self.rgb();
+        let mut palette = match self {
+            // Named colours follow the terminal's own colour scheme
+            Theme::Classic => vec![Color::Black, Color::White, Color::Red, Color::Yellow],
+            _ => rgb.iter().map(|&colour| depth.colour(colour)).collect(),
+        };
+        for &colour in &rgb[1..] {
+            for age in 1..=fade as u32 {
+                let faded = blend(colour, rgb[0], age, fade as u32 + 1);
+                palette.push(depth.colour(faded));
+            }
+        }
+        palette
+    }
+}
+
+/// Parse a colour written in hexadecimal as `#rrggbb`, where the `#` is optional.
+fn parse_rgb(s: &str) -> Result<Rgb, DisplayError> {
+    let digits = s.strip_prefix('#').unwrap_or(s);
+    let invalid = || DisplayError(format!("Expected a colour like #33ff33, found {:?}", s));
+    if digits.len() != 6 {
+        return Err(invalid());
+    }
+    let mut rgb = [0; 3];
+    for (idx, component) in rgb.iter_mut().enumerate() {
+        let digits = digits.get(idx * 2..idx * 2 + 2).ok_or_else(invalid)?;
+        *component = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+    }
+    Ok(rgb)
+}
+
+/// Mix `steps` parts of `to` into `from`, out of `total`.
+fn blend(from: Rgb, to: Rgb, steps: u32, total: u32) -> Rgb {
+    let mut res = [0; 3];
+    for (idx, component) in res.iter_mut().enumerate() {
+        let (from, to) = (from[idx] as u32, to[idx] as u32);
+        *component = ((from * (total - steps) + to * steps) / total) as u8;
+    }
+    res
+}
+
+/// How many colours the terminal can show.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColourDepth {
+    /// Any 24-bit colour.
+    TrueColour,
+    /// The 256 colour palette, which most terminals support.
+    Ansi256,
+}
+
+impl ColourDepth {
+    /// Find out which colours the terminal supports, from the `COLORTERM` environment variable.
+    pub fn detect() -> Self {
+        ColourDepth::from_colorterm(env::var("COLORTERM").ok().as_deref())
+    }
+
+    /// Terminals which support 24-bit colour say so by setting `COLORTERM` to `truecolor` or
+    /// `24bit`.
+    fn from_colorterm(colorterm: Option<&str>) -> Self {
+        match colorterm {
+            Some("truecolor") | Some("24bit") => ColourDepth::TrueColour,
+            _ => ColourDepth::Ansi256,
+        }
+    }
+
+    /// The closest colour to `rgb` that the terminal can show.
+    pub fn colour(self, [r, g, b]: Rgb) -> Color {
+        match self {
+            ColourDepth::TrueColour => Color::Rgb { r, g, b },
+            ColourDepth::Ansi256 => Color::AnsiValue(ansi256([r, g, b])),
+        }
+    }
+}
+
+/// The closest colour to `rgb` in the 256 colour palette, out of its 6x6x6 colour cube and its
+/// greyscale ramp.
+fn ansi256(rgb: Rgb) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let nearest = |component: u8| {
+        (0..LEVELS.len())
+            .min_by_key(|&idx| (LEVELS[idx] as i32 - component as i32).abs())
+            .unwrap()
+    };
+    let distance = |other: Rgb| {
+        (0..3)
+            .map(|idx| (rgb[idx] as i32 - other[idx] as i32).pow(2))
+            .sum::<i32>()
+    };
+
+    let cube = [nearest(rgb[0]), nearest(rgb[1]), nearest(rgb[2])];
+    let cube_rgb = [LEVELS[cube[0]], LEVELS[cube[1]], LEVELS[cube[2]]];
+    let grey = (rgb.iter().map(|&c| c as u32).sum::<u32>() / 3) as u8;
+    let step = (grey.saturating_sub(3) / 10).min(23);
+    let grey_level = 8 + step * 10;
+    if distance([grey_level; 3]) < distance(cube_rgb) {
+        232 + step
+    } else {
+        (16 + cube[0] * 36 + cube[1] * 6 + cube[2]) as u8
+    }
+}
+
+/// Keeps track of pixels which have been turned off recently, so they can fade out over a few
+/// frames instead of disappearing at once. Most CHIP-8 programs erase and redraw their sprites
+/// every frame, which flickers without this, much as the phosphor of an old monitor hid it.
+pub struct Fade {
+    /// How many frames pixels take to fade out.
+    frames: usize,
+    /// The value each pixel last had while it was lit, or 0 once it has faded out.
+    lit: Vec<u8>,
+    /// How many frames ago each pixel was turned off.
+    age: Vec<usize>,
+}
+
+impl Fade {
+    /// Fade pixels out over `frames` frames. With no frames, pixels go out at once.
+    pub fn new(frames: u8) -> Self {
+        Fade {
+            frames: frames as usize,
+            lit: Vec::new(),
+            age: Vec::new(),
+        }
+    }
+
+    /// Advance by a frame, returning the colour of every pixel of `screen` as an index into the
+    /// palette from `Theme::palette`, which has more than 256 colours for long fades.
+    pub fn shades(&mut self, screen: &Framebuffer) -> Vec<usize> {
+        let pixels = screen.pixels();
+        // A change in resolution starts everything over
+        if self.lit.len() != pixels.len() {
+            self.lit = vec![0; pixels.len()];
+            self.age = vec![0; pixels.len()];
+        }
+
+        let mut shades = Vec::with_capacity(pixels.len());
+        for ((&pixel, lit), age) in pixels.iter().zip(&mut self.lit).zip(&mut self.age) {
+            if pixel != 0 {
+                *lit = pixel;
+                *age = 0;
+                shades.push(pixel as usize);
+            } else if *lit != 0 && *age < self.frames {
+                *age += 1;
+                shades.push(4 + (*lit as usize - 1) * self.frames + *age - 1);
+            } else {
+                *lit = 0;
+                shades.push(0);
+            }
+        }
+        shades
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_theme() {
+        assert_eq!(Ok(Theme::Amber), "Amber".parse().map_err(|_| ()));
+        let theme: Theme = "#000000, 33ff33".parse().unwrap();
+        assert_eq!(
+            [[0, 0, 0], [51, 255, 51], [51, 255, 51], [51, 255, 51]],
+            theme.rgb()
+        );
+        assert!("#000000".parse::<Theme>().is_err());
+        assert!("#000000,#fffffg".parse::<Theme>().is_err());
+
+        let palette = theme.palette(ColourDepth::TrueColour, 2);
+        assert_eq!(4 + 3 * 2, palette.len());
+        assert_eq!(
+            Color::Rgb {
+                r: 34,
+                g: 170,
+                b: 34
+            },
+            palette[4]
+        );
+        assert_eq!(
+            Color::Rgb {
+                r: 17,
+                g: 85,
+                b: 17
+            },
+            palette[5]
+        );
+
+        assert_eq!(
+            ColourDepth::TrueColour,
+            ColourDepth::from_colorterm(Some("24bit"))
+        );
+        assert_eq!(ColourDepth::Ansi256, ColourDepth::from_colorterm(None));
+        assert_eq!(16, ansi256([0, 0, 0]));
+        assert_eq!(231, ansi256([255, 255, 255]));
+        assert_eq!(46, ansi256([0, 255, 0]));
+        assert_eq!(244, ansi256([128, 128, 128]));
+    }
+
+    #[test]
+    fn test_fade() {
+        let mut screen = Framebuffer::new();
+        let mut fade = Fade::new(2);
+        screen.flip(0, 0, 2);
+        assert_eq!(2, fade.shades(&screen)[0]);
+
+        // The pixel fades through the colours for value 2, then goes out
+        screen.flip(0, 0, 2);
+        assert_eq!(6, fade.shades(&screen)[0]);
+        assert_eq!(7, fade.shades(&screen)[0]);
+        assert_eq!(0, fade.shades(&screen)[0]);
+
+        // Without fading, shades are just the pixels
+        let mut fade = Fade::new(0);
+        let pixels = |screen: &Framebuffer| {
+            let pixels: Vec<_> = screen.pixels().iter().map(|&px| px as usize).collect();
+            pixels
+        };
+        screen.flip(1, 0, 1);
+        assert_eq!(pixels(&screen), fade.shades(&screen));
+        screen.flip(1, 0, 1);
+        assert_eq!(pixels(&screen), fade.shades(&screen));
+
+        // The longest fade has shades well past the range of a byte
+        let mut fade = Fade::new(255);
+        let palette = Theme::Green.palette(ColourDepth::TrueColour, 255);
+        screen.flip(2, 0, 3);
+        fade.shades(&screen);
+        screen.flip(2, 0, 3);
+        let last = (0..255).map(|_| fade.shades(&screen)[2]).last().unwrap();
+        assert_eq!(4 + 3 * 255 - 1, last);
+        assert_eq!(last + 1, palette.len());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{
+    audio::Sound,
+    emulator::{
+        fault::EmulationFault, input::Input, rom, scheduler::Scheduler, snapshot, Framebuffer,
+        Quirks, State,
+    },
+    error::ErrorKind,
+};
+use std::path::Path;
+
+/// A whole CHIP-8 machine running at a fixed speed, for embedding the emulator in other programs
+/// without any of the terminal frontend.
+///
+/// Each call to `run_frame` runs as many instructions as one frame takes at the chosen speed,
+/// with the keys set by `set_key` held down throughout, then counts the timers down. Showing the
+/// framebuffer, and playing the `sound`, is left to the caller.
+#[derive(Clone)]
+pub struct Emulator {
+    state: State,
+    scheduler: Scheduler,
+    keys: Input,
+    frames: u64,
+}
+
+impl Emulator {
+    /// Instructions run per second unless `set_speed` says otherwise.
+    pub const DEFAULT_IPS: u64 = 700;
+    /// Frames run per second unless `set_speed` says otherwise.
+    pub const DEFAULT_FPS: u64 = 60;
+
+    /// Create an emulator running `rom`, which may be in any of the formats read by the `rom`
+    /// module, and interpreting ambiguous instructions according to `quirks`.
+    pub fn new(rom: &[u8], quirks: Quirks) -> Result<Self, ErrorKind> {
+        let rom = rom::load(rom)?;
+        Ok(Emulator::from_state(State::new(&rom, quirks)))
+    }
+
+    /// Create an emulator running the ROM at `path`, or from standard input if it is `-`.
+    pub fn from_file(path: impl AsRef<Path>, quirks: Quirks) -> Result<Self, ErrorKind> {
+        let rom = rom::load_file(path)?;
+        Ok(Emulator::from_state(State::new(&rom, quirks)))
+    }
+
+    /// Create an emulator which carries on from `state`, such as one restored from a snapshot.
+    pub fn from_state(state: State) -> Self {
+        Emulator {
+            state,
+            scheduler: Scheduler::new(Emulator::DEFAULT_IPS, Emulator::DEFAULT_FPS),
+            keys: [false; 16],
+            frames: 0,
+        }
+    }
+
+    /// Restart the machine running `rom` instead, keeping the same quirks and speed.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), ErrorKind> {
+        self.state = State::new(&rom::load(rom)?, self.state.quirks);
+        self.keys = [false; 16];
+        self.frames = 0;
+        Ok(())
+    }
+
+    /// Run `ips` instructions per second, split between `fps` frames per second, which must be
+    /// at least 1. The timers always count down 60 times per second.
+    pub fn set_speed(&mut self, ips: u64, fps: u64) {
+        self.scheduler = Scheduler::new(ips, fps);
+    }
+
+    /// Restart the random number generator from `seed`, for runs which can be repeated.
+    pub fn seed(&mut self, seed: u64) {
+        self.state.seed(seed);
+    }
+
+    /// Run the instructions of the next frame, then tick the timers. If an instruction faults,
+    /// the rest of the frame is skipped, and running another frame carries on past it.
+    pub fn run_frame(&mut self) -> Result<(), EmulationFault> {
+        self.frames += 1;
+        self.state.run_frame(self.scheduler.next_frame(), self.keys)
+    }
+
+    /// Run the instructions of the next frame like `run_frame`, but stop as soon as the program
+    /// counter reaches `pc`, returning whether it did. The timers still tick.
+    pub fn run_frame_until_pc(&mut self, pc: u16) -> Result<bool, EmulationFault> {
+        self.frames += 1;
+        let frame = self.scheduler.next_frame();
+        self.state
+            .run_frame_until(frame, self.keys, |state| state.pc == pc)
+    }
+
+    /// Run a single instruction, without ticking the timers.
+    pub fn step(&mut self) -> Result<(), EmulationFault> {
+        self.state.step(self.keys)
+    }
+
+    /// Hold down the key `key`, from 0x0 to 0xF, until it is released.
+    ///
+    /// # Panics
+    ///
+    /// If `key` is above 0xF.
+    pub fn set_key(&mut self, key: u8) {
+        self.keys[key as usize] = true;
+    }
+
+    /// Release the key `key`, from 0x0 to 0xF.
+    ///
+    /// # Panics
+    ///
+    /// If `key` is above 0xF.
+    pub fn release_key(&mut self, key: u8) {
+        self.keys[key as usize] = false;
+    }
+
+    /// Hold down exactly the keys which are true in `keys`.
+    pub fn set_keys(&mut self, keys: Input) {
+        self.keys = keys;
+    }
+
+    /// The keys currently held down.
+    pub fn keys(&self) -> Input {
+        self.keys
+    }
+
+    /// The current contents of the screen.
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.state.display
+    }
+
+    /// The number of frames run so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Whether the program has exited with `00FD`, after which it runs no more instructions.
+    pub fn halted(&self) -> bool {
+        self.state.halted
+    }
+
+    /// The value of the delay timer.
+    pub fn delay_timer(&self) -> u8 {
+        self.state.delay
+    }
+
+    /// The value of the sound timer.
+    pub fn sound_timer(&self) -> u8 {
+        self.state.sound
+    }
+
+    /// Whether the tone should be playing, which it does while the sound timer is running.
+    pub fn sound_playing(&self) -> bool {
+        self.state.sound > 0
+    }
+
+    /// What should be playing, which is the XO-CHIP audio pattern once the program has loaded one,
+    /// and the tone otherwise.
+    pub fn sound(&self) -> Sound {
+        Sound::of(&self.state)
+    }
+
+    /// The address of the next instruction.
+    pub fn pc(&self) -> u16 {
+        self.state.pc
+    }
+
+    /// The value of the address register I.
+    pub fn i(&self) -> u16 {
+        self.state.reg_i
+    }
+
+    /// The values of the registers V0 to VF.
+    pub fn registers(&self) -> [u8; 16] {
+        self.state.reg_v
+    }
+
+    /// The return addresses of the subroutines currently being run, innermost last.
+    pub fn stack(&self) -> &[u16] {
+        &self.state.stack[..self.state.sp]
+    }
+
+    /// The whole of memory.
+    pub fn memory(&self) -> &[u8] {
+        &self.state.mem
+    }
+
+    /// A hash of the whole machine, which is the same for two runs exactly when they ended up in
+    /// the same state.
+    pub fn hash(&self) -> u64 {
+        snapshot::hash(&self.state)
+    }
+
+    /// The underlying state, for saving snapshots or debugging.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// The underlying state, for restoring snapshots.
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_emulator() {
+        // F00A - Wait for a key in V0, 6105 - V1 = 5, F115 - Delay = V1, 00FD - Exit
+        let rom = [0xF0, 0x0A, 0x61, 0x05, 0xF1, 0x15, 0x00, 0xFD];
+        let mut emulator = Emulator::new(&rom, Quirks::default()).unwrap();
+        emulator.run_frame().unwrap();
+        assert_eq!(0x200, emulator.pc());
+
+        emulator.set_key(0xA);
+        emulator.run_frame().unwrap();
+        emulator.release_key(0xA);
+        assert_eq!([false; 16], emulator.keys());
+        assert_eq!(0xA, emulator.registers()[0x0]);
+        assert!(emulator.halted());
+        // The timer ticks once at the end of the frame it was set in
+        assert_eq!(4, emulator.delay_timer());
+        assert_eq!(2, emulator.frames());
+
+        assert!(emulator.load_rom(&[]).is_err());
+        emulator.load_rom(&[0x12, 0x00]).unwrap();
+        assert!(!emulator.halted());
+        assert_eq!(&[0x12, 0x00], &emulator.memory()[0x200..0x202]);
+    }
+}
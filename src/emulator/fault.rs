@@ -0,0 +1,107 @@
+use crate::error::FaultPolicyError;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A problem which stops an instruction from being carried out, found at the program counter `pc`
+/// while running `opcode`.
+///
+/// When an instruction faults, the program counter is left pointing past it and nothing else is
+/// changed, so execution can carry on as if it were a no-op.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
+pub enum EmulationFault {
+    #[error("Unknown opcode {opcode:04X} at {pc:#06X}")]
+    UnknownOpcode { pc: u16, opcode: u16 },
+    #[error("Stack overflow at {pc:#06X}: {opcode:04X} called a subroutine 17 levels deep")]
+    StackOverflow { pc: u16, opcode: u16 },
+    #[error("Stack underflow at {pc:#06X}: {opcode:04X} returned from outside any subroutine")]
+    StackUnderflow { pc: u16, opcode: u16 },
+    #[error(
+        "Memory out of range at {pc:#06X}: {opcode:04X} accessed {len} bytes from {addr:#06X}"
+    )]
+    MemoryOutOfRange {
+        pc: u16,
+        opcode: u16,
+        addr: u16,
+        len: usize,
+    },
+    #[error("Program counter ran past the end of memory at {pc:#06X}, running {opcode:04X}")]
+    PcOutOfRange { pc: u16, opcode: u16 },
+}
+
+impl EmulationFault {
+    /// The address of the instruction which faulted.
+    pub fn pc(&self) -> u16 {
+        match *self {
+            EmulationFault::UnknownOpcode { pc, .. }
+            | EmulationFault::StackOverflow { pc, .. }
+            | EmulationFault::StackUnderflow { pc, .. }
+            | EmulationFault::MemoryOutOfRange { pc, .. }
+            | EmulationFault::PcOutOfRange { pc, .. } => pc,
+        }
+    }
+
+    /// The opcode of the instruction which faulted.
+    pub fn opcode(&self) -> u16 {
+        match *self {
+            EmulationFault::UnknownOpcode { opcode, .. }
+            | EmulationFault::StackOverflow { opcode, .. }
+            | EmulationFault::StackUnderflow { opcode, .. }
+            | EmulationFault::MemoryOutOfRange { opcode, .. }
+            | EmulationFault::PcOutOfRange { opcode, .. } => opcode,
+        }
+    }
+}
+
+/// The kind of an `EmulationFault`, as returned by the `opcodes` module, which doesn't know where
+/// the instruction it is running came from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    UnknownOpcode,
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfRange { addr: u16, len: usize },
+    PcOutOfRange,
+}
+
+impl Fault {
+    /// The full fault, for the instruction `opcode` at `pc`.
+    pub fn at(self, pc: u16, opcode: u16) -> EmulationFault {
+        match self {
+            Fault::UnknownOpcode => EmulationFault::UnknownOpcode { pc, opcode },
+            Fault::StackOverflow => EmulationFault::StackOverflow { pc, opcode },
+            Fault::StackUnderflow => EmulationFault::StackUnderflow { pc, opcode },
+            Fault::MemoryOutOfRange { addr, len } => EmulationFault::MemoryOutOfRange {
+                pc,
+                opcode,
+                addr,
+                len,
+            },
+            Fault::PcOutOfRange => EmulationFault::PcOutOfRange { pc, opcode },
+        }
+    }
+}
+
+/// What the emulator does when the program faults.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FaultPolicy {
+    /// Stop the emulator and report the fault.
+    #[default]
+    Halt,
+    /// Skip the faulting instruction and carry on.
+    Ignore,
+    /// Pause in the debugger, showing the fault.
+    Debug,
+}
+
+impl FromStr for FaultPolicy {
+    type Err = FaultPolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "halt" => FaultPolicy::Halt,
+            "ignore" => FaultPolicy::Ignore,
+            "debug" => FaultPolicy::Debug,
+            _ => return Err(FaultPolicyError(s.to_owned())),
+        })
+    }
+}
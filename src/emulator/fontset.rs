@@ -18,3 +18,39 @@ pub const DIG_C: [u8; BYTES] = [0xF0, 0x80, 0x80, 0x80, 0xF0];
 pub const DIG_D: [u8; BYTES] = [0xE0, 0x90, 0x90, 0x90, 0xE0];
 pub const DIG_E: [u8; BYTES] = [0xF0, 0x80, 0xF0, 0x80, 0xF0];
 pub const DIG_F: [u8; BYTES] = [0xF0, 0x80, 0xF0, 0x80, 0x80];
+
+/// The small font, indexed by the hexadecimal digit each sprite represents.
+pub const SMALL: [[u8; BYTES]; 16] = [
+    NUM_0, NUM_1, NUM_2, NUM_3, NUM_4, NUM_5, NUM_6, NUM_7, NUM_8, NUM_9, DIG_A, DIG_B, DIG_C,
+    DIG_D, DIG_E, DIG_F,
+];
+
+/// Address in memory of the small font.
+pub const SMALL_ADDR: usize = 0x0;
+
+// SUPER-CHIP 8x10 font, with the A-F digits from XO-CHIP.
+
+pub const BIG_BYTES: usize = 10;
+
+/// The big font, indexed by the hexadecimal digit each sprite represents.
+pub const BIG: [[u8; BIG_BYTES]; 16] = [
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF],
+    [0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+    [0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18],
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+    [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3],
+    [0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC],
+    [0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C],
+    [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0],
+];
+
+/// Address in memory of the big font, directly after the small font.
+pub const BIG_ADDR: usize = SMALL_ADDR + 16 * BYTES;
@@ -0,0 +1,181 @@
+use std::fmt;
+
+/// The contents of the screen, at either the original 64x32 resolution or the SUPER-CHIP 128x64
+/// high resolution mode.
+///
+/// Each pixel holds a bit for each of the two XO-CHIP bit-planes, so its value is a colour from 0
+/// to 3. Plain CHIP-8 programs only ever draw to the first plane, so their pixels are 0 or 1.
+/// Pixels are stored row by row, with a stride equal to the width of the current resolution, so
+/// only the first `width() * height()` entries are in use while in low resolution mode.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    pub(crate) hires: bool,
+    /// Bit-mask of the planes affected by drawing, clearing and scrolling.
+    pub(crate) planes: u8,
+    pub(crate) pixels: [u8; Framebuffer::HIRES_WIDTH * Framebuffer::HIRES_HEIGHT],
+}
+
+impl Framebuffer {
+    /// Width of the display in low resolution mode.
+    pub const WIDTH: usize = 64;
+
+    /// Height of the display in low resolution mode.
+    pub const HEIGHT: usize = 32;
+
+    /// Width of the display in high resolution mode.
+    pub const HIRES_WIDTH: usize = 128;
+
+    /// Height of the display in high resolution mode.
+    pub const HIRES_HEIGHT: usize = 64;
+
+    /// Number of bit-planes, and hence bits used by each pixel.
+    pub const PLANES: usize = 2;
+
+    /// Create a new, blank, low resolution screen, with only the first plane selected.
+    pub fn new() -> Self {
+        Framebuffer {
+            hires: false,
+            planes: 0b01,
+            pixels: [0; Framebuffer::HIRES_WIDTH * Framebuffer::HIRES_HEIGHT],
+        }
+    }
+
+    /// Whether the screen is in high resolution mode.
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Switch between low and high resolution, clearing every plane of the screen.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.pixels = [0; Framebuffer::HIRES_WIDTH * Framebuffer::HIRES_HEIGHT];
+    }
+
+    /// The bit-mask of planes currently selected for drawing.
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// Select which planes are affected by drawing, clearing and scrolling.
+    pub fn select_planes(&mut self, mask: u8) {
+        self.planes = mask & 0b11;
+    }
+
+    /// Width of the screen at the current resolution.
+    pub fn width(&self) -> usize {
+        if self.hires {
+            Self::HIRES_WIDTH
+        } else {
+            Self::WIDTH
+        }
+    }
+
+    /// Height of the screen at the current resolution.
+    pub fn height(&self) -> usize {
+        if self.hires {
+            Self::HIRES_HEIGHT
+        } else {
+            Self::HEIGHT
+        }
+    }
+
+    /// Turn off every pixel in the selected planes.
+    pub fn clear(&mut self) {
+        let mask = !self.planes;
+        for px in self.pixels.iter_mut() {
+            *px &= mask;
+        }
+    }
+
+    /// The colour of the pixel at (x, y), with one bit per plane. Coordinates must be within the
+    /// current resolution.
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[x + y * self.width()]
+    }
+
+    /// Whether the pixel at (x, y) is lit in any plane.
+    pub fn lit(&self, x: usize, y: usize) -> bool {
+        self.get(x, y) != 0
+    }
+
+    /// XOR a pixel onto the given plane at (x, y), returning whether a lit pixel was erased.
+    /// `plane` is a mask with a single bit set.
+    pub fn flip(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let idx = x + y * self.width();
+        let erased = self.pixels[idx] & plane != 0;
+        self.pixels[idx] ^= plane;
+        erased
+    }
+
+    /// The pixels in use at the current resolution, row by row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels[..self.width() * self.height()]
+    }
+
+    /// Iterate over each row of the screen, from top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.pixels().chunks_exact(self.width())
+    }
+
+    /// Scroll the selected planes down by `n` rows. Rows scrolled in from the top are blank.
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    /// Scroll the selected planes up by `n` rows. Rows scrolled in from the bottom are blank.
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    /// Scroll the selected planes right by `n` columns. Columns scrolled in from the left are
+    /// blank.
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    /// Scroll the selected planes left by `n` columns. Columns scrolled in from the right are
+    /// blank.
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+
+    /// Move the selected planes by (dx, dy), leaving the other planes in place.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (w, h) = (self.width() as isize, self.height() as isize);
+        let mask = self.planes;
+        let old = self.pixels;
+        for y in 0..h {
+            for x in 0..w {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let moved = if (0..w).contains(&src_x) && (0..h).contains(&src_y) {
+                    old[(src_x + src_y * w) as usize] & mask
+                } else {
+                    0
+                };
+                let idx = (x + y * w) as usize;
+                self.pixels[idx] = (old[idx] & !mask) | moved;
+            }
+        }
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shows the screen as text, one line per row, with `.` for unlit pixels and `#` for pixels lit
+/// on the first plane. XO-CHIP pixels lit on the second plane alone are `+`, and on both are `@`.
+impl fmt::Display for Framebuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.rows() {
+            let line: String = row
+                .iter()
+                .map(|&b| ['.', '#', '+', '@'][b as usize])
+                .collect();
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
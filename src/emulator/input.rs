@@ -1,12 +1,272 @@
+use crate::error::KeymapError;
+use std::{
+    env,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
 /// A set of flags representing which keys are pressed. The CHIP-8 keypad is a hexadecimal number
 /// pad with characters 0123456789ABCDEF. Hence, if the 1 and B keys are pressed, the values of the
 /// flags at indexes 0x1 (1) and 0xB (11) should be pressed
 pub type Input = [bool; 16];
 
-// Index-based keymap, the character at index k (in hexadecimal) corresponds to the letter k on the CHIP-8 keypad.
-// Note that this keypad does not start at 0, so some rearranging of letters is neccesary.
-pub const KEYMAP: [char; 16] = [
-    'x', '1', '2', '3', 'q', 'w', 'e', 'a', 's', 'd', 
-    'z', 'c', '4', 'r', 'f', 'v'
-];
+/// A key on the host's keyboard, which can be mapped to a key of the CHIP-8 keypad.
+///
+/// Keys are written as the character they type, or as one of `space`, `enter`, `tab`, `up`,
+/// `down`, `left` and `right`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HostKey {
+    Char(char),
+    Enter,
+    Tab,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl FromStr for HostKey {
+    type Err = KeymapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => return Ok(HostKey::Char(c.to_ascii_lowercase())),
+            (None, _) => return Err(KeymapError(String::from("Missing key name"))),
+            _ => {}
+        }
+        match s.to_ascii_lowercase().as_str() {
+            "space" => Ok(HostKey::Char(' ')),
+            "enter" => Ok(HostKey::Enter),
+            "tab" => Ok(HostKey::Tab),
+            "up" => Ok(HostKey::Up),
+            "down" => Ok(HostKey::Down),
+            "left" => Ok(HostKey::Left),
+            "right" => Ok(HostKey::Right),
+            _ => Err(KeymapError(format!("Unknown key {:?}", s))),
+        }
+    }
+}
+
+/// Which host key is mapped to each of the 16 keys on the CHIP-8 keypad, indexed by the CHIP-8
+/// key. A host key is never mapped to more than one CHIP-8 key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap([Option<HostKey>; 16]);
+
+/// Lays the CHIP-8 keypad out over the left of a QWERTY keyboard:
+///
+/// ```text
+/// 1 2 3 C        1 2 3 4
+/// 4 5 6 D   ->   q w e r
+/// 7 8 9 E        a s d f
+/// A 0 B F        z x c v
+/// ```
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keys = [None; 16];
+        for (key, &c) in keys.iter_mut().zip(b"x123qweasdzc4rfv") {
+            *key = Some(HostKey::Char(c as char));
+        }
+        Keymap(keys)
+    }
+}
+
+impl Keymap {
+    /// The CHIP-8 key which `key` is mapped to, if there is one.
+    pub fn lookup(&self, key: HostKey) -> Option<u8> {
+        self.0
+            .iter()
+            .position(|&mapped| mapped == Some(key))
+            .map(|idx| idx as u8)
+    }
+
+    /// Map `key` to the CHIP-8 key `chip8_key`, removing any other mapping it had.
+    pub fn set(&mut self, chip8_key: u8, key: HostKey) {
+        for mapped in self.0.iter_mut().filter(|mapped| **mapped == Some(key)) {
+            *mapped = None;
+        }
+        self.0[chip8_key as usize & 0xF] = Some(key);
+    }
+
+    /// Apply a list of assignments separated by commas or newlines, each of the form
+    /// `<chip8 key> = <host key>`, such as `5 = up, 8 = down`.
+    pub fn apply(&mut self, assignments: &str) -> Result<(), KeymapError> {
+        let assignments = assignments
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|assignment| !assignment.is_empty());
+        for assignment in assignments {
+            let mut parts = assignment.splitn(2, '=').map(str::trim);
+            let (chip8_key, key) = match (parts.next(), parts.next()) {
+                (Some(chip8_key), Some(key)) => (chip8_key, key),
+                _ => {
+                    let msg = format!("Expected `<chip8 key> = <key>`, found {:?}", assignment);
+                    return Err(KeymapError(msg));
+                }
+            };
+            let chip8_key = match u8::from_str_radix(chip8_key, 16) {
+                Ok(k) if k < 16 => k,
+                _ => {
+                    let msg = format!("{:?} is not a CHIP-8 key, from 0 to F", chip8_key);
+                    return Err(KeymapError(msg));
+                }
+            };
+            self.set(chip8_key, key.parse()?);
+        }
+        Ok(())
+    }
+
+    /// Apply a keymap config file to this keymap.
+    ///
+    /// The file is a list of assignments, as taken by `Keymap::apply`, one per line. Lines
+    /// starting with `#` are comments. Assignments under a `[name]` header only apply to the ROM
+    /// whose file name is `name`, and take priority over those before any header, so a file like
+    ///
+    /// ```text
+    /// 5 = up
+    /// 8 = down
+    ///
+    /// [pong.ch8]
+    /// 1 = up
+    /// 4 = down
+    /// ```
+    ///
+    /// moves most games to the arrow keys, but gives Pong its own controls.
+    pub fn apply_config(&mut self, config: &str, rom_name: &str) -> Result<(), KeymapError> {
+        let mut global = String::new();
+        let mut specific = String::new();
+        let mut section: Option<&str> = None;
+        for line in config.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = Some(line[1..line.len() - 1].trim());
+                continue;
+            }
+            let dest = match section {
+                None => &mut global,
+                Some(name) if name == rom_name => &mut specific,
+                Some(_) => continue,
+            };
+            dest.push_str(line);
+            dest.push('\n');
+        }
+        self.apply(&global)?;
+        self.apply(&specific)
+    }
+}
+
+/// Where the keymap config file is looked for when none is given, which is
+/// `$XDG_CONFIG_HOME/chip-again/keymap`, falling back to `~/.config/chip-again/keymap`.
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("chip-again").join("keymap"))
+}
+
+/// Tracks which keys of the CHIP-8 keypad are held down over time.
+///
+/// Terminals only report key presses, not releases, so a key counts as held for `hold` after it
+/// was last pressed. While a key is physically held, the terminal's key repeat keeps pressing it,
+/// which keeps it held here too.
+pub struct Keypad {
+    keymap: Keymap,
+    hold: Duration,
+    /// When each CHIP-8 key was last pressed.
+    pressed_at: [Option<Instant>; 16],
+}
+
+impl Keypad {
+    /// Create a keypad with nothing held, which maps host keys with `keymap`.
+    pub fn new(keymap: Keymap, hold: Duration) -> Self {
+        Keypad {
+            keymap,
+            hold,
+            pressed_at: [None; 16],
+        }
+    }
+
+    /// Record that `key` was pressed at `now`. Returns whether it is mapped to the keypad.
+    pub fn press(&mut self, key: HostKey, now: Instant) -> bool {
+        match self.keymap.lookup(key) {
+            Some(idx) => {
+                self.pressed_at[idx as usize] = Some(now);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Which keys are held at `now`.
+    pub fn held(&self, now: Instant) -> Input {
+        let mut input = Input::default();
+        for (held, pressed_at) in input.iter_mut().zip(self.pressed_at.iter()) {
+            *held = pressed_at.is_some_and(|at| now.saturating_duration_since(at) < self.hold);
+        }
+        input
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keymap() {
+        let mut keymap = Keymap::default();
+        assert_eq!(Some(0x0), keymap.lookup(HostKey::Char('x')));
+        assert_eq!(Some(0xF), keymap.lookup(HostKey::Char('v')));
+
+        // Remapping a key which was already in use takes it away from its old CHIP-8 key
+        keymap.apply("5 = up, 1=W").unwrap();
+        assert_eq!(Some(0x5), keymap.lookup(HostKey::Up));
+        assert_eq!(Some(0x1), keymap.lookup(HostKey::Char('w')));
+        assert_eq!(None, keymap.lookup(HostKey::Char('1')));
+
+        assert!(keymap.apply("g = up").is_err());
+        assert!(keymap.apply("5 = pgup").is_err());
+        assert!(keymap.apply("5").is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        let config = "
+            # Arrow keys for everything
+            5 = up
+            8 = down
+
+            [pong.ch8]
+            1 = up
+
+            [tetris.ch8]
+            4 = left
+        ";
+        let mut keymap = Keymap::default();
+        keymap.apply_config(config, "pong.ch8").unwrap();
+        assert_eq!(Some(0x1), keymap.lookup(HostKey::Up));
+        assert_eq!(Some(0x8), keymap.lookup(HostKey::Down));
+        assert_eq!(None, keymap.lookup(HostKey::Left));
+    }
+
+    #[test]
+    fn test_hold() {
+        let start = Instant::now();
+        let hold = Duration::from_millis(100);
+        let mut keypad = Keypad::new(Keymap::default(), hold);
+        assert!(keypad.press(HostKey::Char('w'), start));
+        assert!(!keypad.press(HostKey::Char('p'), start));
+
+        assert!(keypad.held(start)[0x5]);
+        assert!(keypad.held(start + hold / 2)[0x5]);
+        assert!(!keypad.held(start + hold)[0x5]);
 
+        // Key repeat keeps the key held
+        keypad.press(HostKey::Char('w'), start + hold / 2);
+        assert!(keypad.held(start + hold)[0x5]);
+        assert_eq!(1, keypad.held(start + hold).iter().filter(|&&k| k).count());
+    }
+}
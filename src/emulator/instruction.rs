@@ -0,0 +1,380 @@
+use std::fmt;
+
+/// A single decoded instruction, covering CHIP-8, SUPER-CHIP 1.1 and XO-CHIP.
+///
+/// Register operands (`x` and `y`) are register indexes from 0x0 to 0xF. Mnemonics follow Cowgod's
+/// CHIP-8 technical reference, with additions for the SUPER-CHIP and XO-CHIP instructions, and are
+/// what the `Display` implementation prints.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// 00Cn - Scroll the display down n lines.
+    ScrollDown(u8),
+    /// 00Dn - Scroll the display up n lines.
+    ScrollUp(u8),
+    /// 00E0 - Clear the display.
+    Clear,
+    /// 00EE - Return from a subroutine.
+    Return,
+    /// 00FB - Scroll the display right by 4 pixels.
+    ScrollRight,
+    /// 00FC - Scroll the display left by 4 pixels.
+    ScrollLeft,
+    /// 00FD - Exit the interpreter.
+    Exit,
+    /// 00FE - Disable high resolution mode.
+    LowRes,
+    /// 00FF - Enable high resolution mode.
+    HighRes,
+    /// 1nnn - Jump to location nnn.
+    Jump(u16),
+    /// 2nnn - Call subroutine at nnn.
+    Call(u16),
+    /// 3xkk - Skip next instruction if Vx = kk.
+    SkipEqual(u8, u8),
+    /// 4xkk - Skip next instruction if Vx != kk.
+    SkipNotEqual(u8, u8),
+    /// 5xy0 - Skip next instruction if Vx = Vy.
+    SkipRegEqual(u8, u8),
+    /// 5xy2 - Store registers Vx through Vy in memory starting at location I.
+    SaveRange(u8, u8),
+    /// 5xy3 - Read registers Vx through Vy from memory starting at location I.
+    LoadRange(u8, u8),
+    /// 6xkk - Set Vx = kk.
+    Load(u8, u8),
+    /// 7xkk - Set Vx = Vx + kk.
+    AddByte(u8, u8),
+    /// 8xy0 - Set Vx = Vy.
+    Move(u8, u8),
+    /// 8xy1 - Set Vx = Vx OR Vy.
+    Or(u8, u8),
+    /// 8xy2 - Set Vx = Vx AND Vy.
+    And(u8, u8),
+    /// 8xy3 - Set Vx = Vx XOR Vy.
+    Xor(u8, u8),
+    /// 8xy4 - Set Vx = Vx + Vy, set VF = carry.
+    Add(u8, u8),
+    /// 8xy5 - Set Vx = Vx - Vy, set VF = NOT borrow.
+    Sub(u8, u8),
+    /// 8xy6 - Set Vx = Vx SHR 1.
+    ShiftRight(u8, u8),
+    /// 8xy7 - Set Vx = Vy - Vx, set VF = NOT borrow.
+    SubN(u8, u8),
+    /// 8xyE - Set Vx = Vx SHL 1.
+    ShiftLeft(u8, u8),
+    /// 9xy0 - Skip next instruction if Vx != Vy.
+    SkipRegNotEqual(u8, u8),
+    /// Annn - Set I = nnn.
+    LoadI(u16),
+    /// Bnnn - Jump to location nnn + V0.
+    JumpOffset(u16),
+    /// Cxkk - Set Vx = random byte AND kk.
+    Random(u8, u8),
+    /// Dxyn - Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+    /// When n is 0, a 16x16 sprite is drawn instead.
+    Draw(u8, u8, u8),
+    /// Ex9E - Skip next instruction if key with the value of Vx is pressed.
+    SkipPressed(u8),
+    /// ExA1 - Skip next instruction if key with the value of Vx is not pressed.
+    SkipNotPressed(u8),
+    /// F000 nnnn - Set I = nnnn, the 16-bit address in the two bytes following the instruction.
+    LoadLongI,
+    /// Fn01 - Select the drawing planes n.
+    Plane(u8),
+    /// F002 - Load the audio pattern buffer from memory starting at location I.
+    LoadAudio,
+    /// Fx07 - Set Vx = delay timer value.
+    GetDelay(u8),
+    /// Fx0A - Block and wait for a key press, store the value of the key in Vx.
+    WaitKey(u8),
+    /// Fx15 - Set delay timer = Vx.
+    SetDelay(u8),
+    /// Fx18 - Set sound timer = Vx.
+    SetSound(u8),
+    /// Fx1E - Set I = I + Vx.
+    AddI(u8),
+    /// Fx29 - Set I = location of sprite for digit Vx.
+    Font(u8),
+    /// Fx30 - Set I = location of big sprite for digit Vx.
+    BigFont(u8),
+    /// Fx33 - Store BCD representation of Vx in memory locations I, I+1, and I+2.
+    Bcd(u8),
+    /// Fx3A - Set the audio pattern playback pitch = Vx.
+    Pitch(u8),
+    /// Fx55 - Store registers V0 through Vx in memory starting at location I.
+    Store(u8),
+    /// Fx65 - Read registers V0 through Vx from memory starting at location I.
+    Restore(u8),
+    /// Fx75 - Store registers V0 through Vx in the RPL user flags.
+    SaveFlags(u8),
+    /// Fx85 - Read registers V0 through Vx from the RPL user flags.
+    LoadFlags(u8),
+}
+
+impl Instruction {
+    /// The number of bytes the instruction occupies in memory. This is 2 for everything except
+    /// `F000 nnnn`, which is 4.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LoadLongI => 4,
+            _ => 2,
+        }
+    }
+
+    /// Whether the instruction skips the one after it under some condition.
+    pub fn is_skip(&self) -> bool {
+        use Instruction::*;
+        matches!(
+            self,
+            SkipEqual(..)
+                | SkipNotEqual(..)
+                | SkipRegEqual(..)
+                | SkipRegNotEqual(..)
+                | SkipPressed(_)
+                | SkipNotPressed(_)
+        )
+    }
+}
+
+/// Decode a two-byte opcode, returning `None` if it isn't a known instruction.
+pub fn decode(opcode: u16) -> Option<Instruction> {
+    use Instruction::*;
+
+    // We're interested in each nibble of the opcode, so 4 values
+    let nibbles = [
+        (opcode >> 12) as u8,
+        (opcode >> 8 & 0xF) as u8,
+        (opcode >> 4 & 0xF) as u8,
+        (opcode & 0xF) as u8,
+    ];
+    Some(match nibbles {
+        [0x0, 0x0, 0xC, n] => ScrollDown(n),
+        [0x0, 0x0, 0xD, n] => ScrollUp(n),
+        [0x0, 0x0, 0xE, 0x0] => Clear,
+        [0x0, 0x0, 0xE, 0xE] => Return,
+        [0x0, 0x0, 0xF, 0xB] => ScrollRight,
+        [0x0, 0x0, 0xF, 0xC] => ScrollLeft,
+        [0x0, 0x0, 0xF, 0xD] => Exit,
+        [0x0, 0x0, 0xF, 0xE] => LowRes,
+        [0x0, 0x0, 0xF, 0xF] => HighRes,
+        [0x1, n1, n2, n3] => Jump(addr(n1, n2, n3)),
+        [0x2, n1, n2, n3] => Call(addr(n1, n2, n3)),
+        [0x3, x, k1, k2] => SkipEqual(x, byte(k1, k2)),
+        [0x4, x, k1, k2] => SkipNotEqual(x, byte(k1, k2)),
+        [0x5, x, y, 0x0] => SkipRegEqual(x, y),
+        [0x5, x, y, 0x2] => SaveRange(x, y),
+        [0x5, x, y, 0x3] => LoadRange(x, y),
+        [0x6, x, k1, k2] => Load(x, byte(k1, k2)),
+        [0x7, x, k1, k2] => AddByte(x, byte(k1, k2)),
+        [0x8, x, y, 0x0] => Move(x, y),
+        [0x8, x, y, 0x1] => Or(x, y),
+        [0x8, x, y, 0x2] => And(x, y),
+        [0x8, x, y, 0x3] => Xor(x, y),
+        [0x8, x, y, 0x4] => Add(x, y),
+        [0x8, x, y, 0x5] => Sub(x, y),
+        [0x8, x, y, 0x6] => ShiftRight(x, y),
+        [0x8, x, y, 0x7] => SubN(x, y),
+        [0x8, x, y, 0xE] => ShiftLeft(x, y),
+        [0x9, x, y, 0x0] => SkipRegNotEqual(x, y),
+        [0xA, n1, n2, n3] => LoadI(addr(n1, n2, n3)),
+        [0xB, n1, n2, n3] => JumpOffset(addr(n1, n2, n3)),
+        [0xC, x, k1, k2] => Random(x, byte(k1, k2)),
+        [0xD, x, y, n] => Draw(x, y, n),
+        [0xE, x, 0x9, 0xE] => SkipPressed(x),
+        [0xE, x, 0xA, 0x1] => SkipNotPressed(x),
+        [0xF, 0x0, 0x0, 0x0] => LoadLongI,
+        [0xF, n, 0x0, 0x1] => Plane(n),
+        [0xF, 0x0, 0x0, 0x2] => LoadAudio,
+        [0xF, x, 0x0, 0x7] => GetDelay(x),
+        [0xF, x, 0x0, 0xA] => WaitKey(x),
+        [0xF, x, 0x1, 0x5] => SetDelay(x),
+        [0xF, x, 0x1, 0x8] => SetSound(x),
+        [0xF, x, 0x1, 0xE] => AddI(x),
+        [0xF, x, 0x2, 0x9] => Font(x),
+        [0xF, x, 0x3, 0x0] => BigFont(x),
+        [0xF, x, 0x3, 0x3] => Bcd(x),
+        [0xF, x, 0x3, 0xA] => Pitch(x),
+        [0xF, x, 0x5, 0x5] => Store(x),
+        [0xF, x, 0x6, 0x5] => Restore(x),
+        [0xF, x, 0x7, 0x5] => SaveFlags(x),
+        [0xF, x, 0x8, 0x5] => LoadFlags(x),
+        _ => return None,
+    })
+}
+
+/// Encode an instruction back into its two-byte opcode. This is the inverse of `decode`, so any
+/// operands wider than their field in the opcode are truncated. For `LoadLongI`, the address
+/// which follows the opcode must be written separately.
+pub fn encode(instruction: Instruction) -> u16 {
+    use Instruction::*;
+
+    let xy = |op: u16, x: u8, y: u8| op | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+    let xkk = |op: u16, x: u8, kk: u8| op | (x as u16 & 0xF) << 8 | kk as u16;
+    let x = |op: u16, x: u8| op | (x as u16 & 0xF) << 8;
+    match instruction {
+        ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+        ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+        Clear => 0x00E0,
+        Return => 0x00EE,
+        ScrollRight => 0x00FB,
+        ScrollLeft => 0x00FC,
+        Exit => 0x00FD,
+        LowRes => 0x00FE,
+        HighRes => 0x00FF,
+        Jump(nnn) => 0x1000 | (nnn & 0xFFF),
+        Call(nnn) => 0x2000 | (nnn & 0xFFF),
+        SkipEqual(vx, kk) => xkk(0x3000, vx, kk),
+        SkipNotEqual(vx, kk) => xkk(0x4000, vx, kk),
+        SkipRegEqual(vx, vy) => xy(0x5000, vx, vy),
+        SaveRange(vx, vy) => xy(0x5002, vx, vy),
+        LoadRange(vx, vy) => xy(0x5003, vx, vy),
+        Load(vx, kk) => xkk(0x6000, vx, kk),
+        AddByte(vx, kk) => xkk(0x7000, vx, kk),
+        Move(vx, vy) => xy(0x8000, vx, vy),
+        Or(vx, vy) => xy(0x8001, vx, vy),
+        And(vx, vy) => xy(0x8002, vx, vy),
+        Xor(vx, vy) => xy(0x8003, vx, vy),
+        Add(vx, vy) => xy(0x8004, vx, vy),
+        Sub(vx, vy) => xy(0x8005, vx, vy),
+        ShiftRight(vx, vy) => xy(0x8006, vx, vy),
+        SubN(vx, vy) => xy(0x8007, vx, vy),
+        ShiftLeft(vx, vy) => xy(0x800E, vx, vy),
+        SkipRegNotEqual(vx, vy) => xy(0x9000, vx, vy),
+        LoadI(nnn) => 0xA000 | (nnn & 0xFFF),
+        JumpOffset(nnn) => 0xB000 | (nnn & 0xFFF),
+        Random(vx, kk) => xkk(0xC000, vx, kk),
+        Draw(vx, vy, n) => xy(0xD000, vx, vy) | (n as u16 & 0xF),
+        SkipPressed(vx) => x(0xE09E, vx),
+        SkipNotPressed(vx) => x(0xE0A1, vx),
+        LoadLongI => 0xF000,
+        Plane(n) => x(0xF001, n),
+        LoadAudio => 0xF002,
+        GetDelay(vx) => x(0xF007, vx),
+        WaitKey(vx) => x(0xF00A, vx),
+        SetDelay(vx) => x(0xF015, vx),
+        SetSound(vx) => x(0xF018, vx),
+        AddI(vx) => x(0xF01E, vx),
+        Font(vx) => x(0xF029, vx),
+        BigFont(vx) => x(0xF030, vx),
+        Bcd(vx) => x(0xF033, vx),
+        Pitch(vx) => x(0xF03A, vx),
+        Store(vx) => x(0xF055, vx),
+        Restore(vx) => x(0xF065, vx),
+        SaveFlags(vx) => x(0xF075, vx),
+        LoadFlags(vx) => x(0xF085, vx),
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+        match *self {
+            ScrollDown(n) => write!(f, "SCD {}", n),
+            ScrollUp(n) => write!(f, "SCU {}", n),
+            Clear => write!(f, "CLS"),
+            Return => write!(f, "RET"),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            LowRes => write!(f, "LOW"),
+            HighRes => write!(f, "HIGH"),
+            Jump(nnn) => write!(f, "JP #{:03X}", nnn),
+            Call(nnn) => write!(f, "CALL #{:03X}", nnn),
+            SkipEqual(x, kk) => write!(f, "SE V{:X}, #{:02X}", x, kk),
+            SkipNotEqual(x, kk) => write!(f, "SNE V{:X}, #{:02X}", x, kk),
+            SkipRegEqual(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            SaveRange(x, y) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            LoadRange(x, y) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Load(x, kk) => write!(f, "LD V{:X}, #{:02X}", x, kk),
+            AddByte(x, kk) => write!(f, "ADD V{:X}, #{:02X}", x, kk),
+            Move(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Add(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubN(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SkipRegNotEqual(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LoadI(nnn) => write!(f, "LD I, #{:03X}", nnn),
+            JumpOffset(nnn) => write!(f, "JP V0, #{:03X}", nnn),
+            Random(x, kk) => write!(f, "RND V{:X}, #{:02X}", x, kk),
+            Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipPressed(x) => write!(f, "SKP V{:X}", x),
+            SkipNotPressed(x) => write!(f, "SKNP V{:X}", x),
+            LoadLongI => write!(f, "LD I, LONG"),
+            Plane(n) => write!(f, "PLANE {}", n),
+            LoadAudio => write!(f, "AUDIO"),
+            GetDelay(x) => write!(f, "LD V{:X}, DT", x),
+            WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Font(x) => write!(f, "LD F, V{:X}", x),
+            BigFont(x) => write!(f, "LD HF, V{:X}", x),
+            Bcd(x) => write!(f, "LD B, V{:X}", x),
+            Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Store(x) => write!(f, "LD [I], V{:X}", x),
+            Restore(x) => write!(f, "LD V{:X}, [I]", x),
+            SaveFlags(x) => write!(f, "LD R, V{:X}", x),
+            LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+/// Combine three nibbles into an address.
+fn addr(n1: u8, n2: u8, n3: u8) -> u16 {
+    let (n1, n2, n3) = (n1 as u16, n2 as u16, n3 as u16);
+    (n1 << 8) | (n2 << 4) | n3
+}
+
+/// Combine two nibbles into a byte.
+fn byte(k1: u8, k2: u8) -> u8 {
+    (k1 << 4) | k2
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_addr() {
+        assert_eq!(0x0000, addr(0x0, 0x0, 0x0));
+        assert_eq!(0x0123, addr(0x1, 0x2, 0x3));
+        assert_eq!(0x0FFF, addr(0xF, 0xF, 0xF));
+    }
+
+    #[test]
+    fn test_byte() {
+        assert_eq!(0x00, byte(0x0, 0x0));
+        assert_eq!(0x12, byte(0x1, 0x2));
+        assert_eq!(0xFF, byte(0xF, 0xF));
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(Some(Instruction::Clear), decode(0x00E0));
+        assert_eq!(Some(Instruction::Call(0x2A4)), decode(0x22A4));
+        assert_eq!(Some(Instruction::Draw(0x1, 0x2, 0x0)), decode(0xD120));
+        assert_eq!(Some(Instruction::Plane(0x3)), decode(0xF301));
+        assert_eq!(None, decode(0x5121));
+        assert_eq!(None, decode(0xE1FF));
+    }
+
+    #[test]
+    fn test_encode() {
+        // Every valid opcode should survive a round trip
+        for opcode in 0..=0xFFFF {
+            if let Some(instruction) = decode(opcode) {
+                assert_eq!(opcode, encode(instruction), "{}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!("LD V3, #2A", decode(0x632A).unwrap().to_string());
+        assert_eq!("DRW V0, V1, 5", decode(0xD015).unwrap().to_string());
+        assert_eq!("LD [I], VF", decode(0xFF55).unwrap().to_string());
+    }
+}
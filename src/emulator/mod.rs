@@ -1,10 +1,37 @@
+/// Contains the Emulator type, a simple interface to the whole machine for use from other crates.
+pub mod facade;
+/// Contains the EmulationFault type, raised when the program does something impossible.
+pub mod fault;
 /// Contains the standard font for the CHIP-8 system.
 pub mod fontset;
+/// Contains the Framebuffer type which holds the contents of the screen.
+pub mod framebuffer;
 /// Contains the Input type which provides values for each of the 16 keys as well as input handlers.
 pub mod input;
+/// Contains the Instruction type, and decoding of opcodes into instructions.
+pub mod instruction;
+/// Recording input to a file, so a run can be replayed exactly.
+pub mod movie;
 /// Implementation of some of the more complex opcodes. Called mostly from state.
 pub mod opcodes;
+/// Contains the Quirks type which selects between the behaviours of different CHIP-8 platforms.
+pub mod quirks;
+/// Contains the Rewind type which records past states so execution can be stepped backwards.
+pub mod rewind;
+/// Contains the Rng type, the seedable random number generator used by programs.
+pub mod rng;
+/// Contains the Scheduler type which decides how much work is done in each rendered frame.
+pub mod scheduler;
+/// Loading ROMs from files, in each of the formats they are shared in.
+pub mod rom;
+/// Saving and restoring the State type to and from a versioned binary format.
+pub mod snapshot;
+/// Contains the Script type, which holds keys down on chosen frames of a run without a keyboard.
+pub mod script;
 /// Contains the State type which describes the current state of the interpreter.
 pub mod state;
 
+pub use facade::Emulator;
+pub use framebuffer::Framebuffer;
+pub use quirks::Quirks;
 pub use state::State;
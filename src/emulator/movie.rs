@@ -0,0 +1,312 @@
+//! Movies, which record the input given to a program in every frame, so that the run can be
+//! replayed exactly later, to reproduce a bug or to watch a run back.
+//!
+//! Along with the input, a movie holds everything else a run depends on: the seed of the random
+//! number generator, the quirks and the speed. After the last frame, a hash of the state is
+//! recorded, which a replay must match.
+//!
+//! # Format
+//!
+//! Movies are stored as a flat binary file. All multi-byte integers are little-endian.
+//!
+//! | Size  | Contents                                                            |
+//! |-------|---------------------------------------------------------------------|
+//! | 4     | Magic bytes, `C8MV`                                                 |
+//! | 2     | Format version, currently 1                                         |
+//! | 8     | FNV-1a hash of the ROM the movie was recorded with                  |
+//! | 8     | Seed of the random number generator                                 |
+//! | 1     | Quirks, one bit for each field of `Quirks` in order, lowest first   |
+//! | 8     | Instructions per second                                             |
+//! | 8     | Frames per second                                                   |
+//! | 4     | Number of frames, `n`                                               |
+//! | 2n    | Keys held in each frame, with bit `k` set while key `k` is held     |
+//! | 8     | Hash of the state after the last frame, from `snapshot::hash`       |
+
+use crate::{
+    emulator::{input::Input, snapshot, Quirks, State},
+    error::ErrorKind,
+};
+use std::{
+    fs::File,
+    io::{prelude::*, BufReader, BufWriter},
+    path::Path,
+};
+
+/// Magic bytes at the start of every movie file.
+pub const MAGIC: [u8; 4] = *b"C8MV";
+
+/// The version of the movie format written by this build.
+pub const VERSION: u16 = 1;
+
+/// A recording of the input to a program, frame by frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    /// Hash of the ROM the movie was recorded with.
+    pub rom_hash: u64,
+    /// The seed the random number generator started from.
+    pub seed: u64,
+    pub quirks: Quirks,
+    /// Instructions per second.
+    pub ips: u64,
+    /// Frames per second.
+    pub fps: u64,
+    /// The keys held in each frame.
+    pub inputs: Vec<Input>,
+    /// Hash of the state after the last frame.
+    pub final_hash: u64,
+}
+
+impl Movie {
+    /// Start recording a movie of `state`, which has just been loaded and seeded with `seed`, and
+    /// is run at `ips` instructions and `fps` frames per second.
+    pub fn new(state: &State, seed: u64, ips: u64, fps: u64) -> Self {
+        Movie {
+            rom_hash: state.rom_hash,
+            seed,
+            quirks: state.quirks,
+            ips,
+            fps,
+            inputs: Vec::new(),
+            final_hash: snapshot::hash(state),
+        }
+    }
+
+    /// Record the input for the next frame.
+    pub fn push(&mut self, input: Input) {
+        self.inputs.push(input);
+    }
+
+    /// Finish the movie, with `state` as it is after the last frame.
+    pub fn finish(&mut self, state: &State) {
+        self.final_hash = snapshot::hash(state);
+    }
+
+    /// Prepare `state`, which has just been loaded, to replay this movie, by checking it has the
+    /// same ROM and giving it the same seed and quirks.
+    pub fn prepare(&self, state: &mut State) -> Result<(), ErrorKind> {
+        if state.rom_hash != self.rom_hash {
+            return Err(ErrorKind::MovieRom);
+        }
+        state.seed(self.seed);
+        state.quirks = self.quirks;
+        Ok(())
+    }
+
+    /// Check that a replay of this movie turned out the same as the recording, once it has run
+    /// for `frames` frames and left `state` behind.
+    pub fn verify(&self, state: &State, frames: u64) -> Result<(), ErrorKind> {
+        let expected = self.inputs.len() as u64;
+        if frames != expected {
+            return Err(ErrorKind::MovieLength {
+                found: frames,
+                expected,
+            });
+        }
+        let found = snapshot::hash(state);
+        if found != self.final_hash {
+            return Err(ErrorKind::MovieDesync {
+                found,
+                expected: self.final_hash,
+            });
+        }
+        Ok(())
+    }
+
+    /// Write the movie to `w`.
+    pub fn save(&self, w: &mut impl Write) -> Result<(), ErrorKind> {
+        w.write_all(&MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&self.rom_hash.to_le_bytes())?;
+        w.write_all(&self.seed.to_le_bytes())?;
+        w.write_all(&[quirks_to_bits(self.quirks)])?;
+        w.write_all(&self.ips.to_le_bytes())?;
+        w.write_all(&self.fps.to_le_bytes())?;
+        w.write_all(&(self.inputs.len() as u32).to_le_bytes())?;
+        for input in &self.inputs {
+            w.write_all(&input_to_bits(*input).to_le_bytes())?;
+        }
+        w.write_all(&self.final_hash.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Read a movie from `r`.
+    pub fn load(r: &mut impl Read) -> Result<Movie, ErrorKind> {
+        if read_array::<4>(r)? != MAGIC {
+            return Err(ErrorKind::NotAMovie);
+        }
+        let version = u16::from_le_bytes(read_array(r)?);
+        if version != VERSION {
+            return Err(ErrorKind::MovieVersion {
+                found: version,
+                expected: VERSION,
+            });
+        }
+        let rom_hash = u64::from_le_bytes(read_array(r)?);
+        let seed = u64::from_le_bytes(read_array(r)?);
+        let [quirks] = read_array(r)?;
+        let ips = u64::from_le_bytes(read_array(r)?);
+        let fps = u64::from_le_bytes(read_array(r)?);
+        if fps == 0 {
+            return Err(ErrorKind::NotAMovie);
+        }
+        let frames = u32::from_le_bytes(read_array(r)?);
+        let mut inputs = Vec::new();
+        for _ in 0..frames {
+            inputs.push(input_from_bits(u16::from_le_bytes(read_array(r)?)));
+        }
+        let final_hash = u64::from_le_bytes(read_array(r)?);
+        Ok(Movie {
+            rom_hash,
+            seed,
+            quirks: quirks_from_bits(quirks),
+            ips,
+            fps,
+            inputs,
+            final_hash,
+        })
+    }
+
+    /// Write the movie to the file at `path`, replacing it if it exists.
+    pub fn save_file(&self, path: impl AsRef<Path>) -> Result<(), ErrorKind> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.save(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Read a movie from the file at `path`.
+    pub fn load_file(path: impl AsRef<Path>) -> Result<Movie, ErrorKind> {
+        Movie::load(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+/// Pack the held keys into a number, with bit `k` set if key `k` is held.
+fn input_to_bits(input: Input) -> u16 {
+    input
+        .iter()
+        .enumerate()
+        .filter(|(_, &held)| held)
+        .fold(0, |bits, (key, _)| bits | 1 << key)
+}
+
+/// Unpack the held keys from a number made by `input_to_bits`.
+fn input_from_bits(bits: u16) -> Input {
+    let mut input = Input::default();
+    for (key, held) in input.iter_mut().enumerate() {
+        *held = bits & 1 << key != 0;
+    }
+    input
+}
+
+/// Pack the quirks into a byte, one bit for each field in order.
+fn quirks_to_bits(quirks: Quirks) -> u8 {
+    let flags = [
+        quirks.shift_uses_vy,
+        quirks.load_store_increments_i,
+        quirks.jump_uses_vx,
+        quirks.logic_resets_vf,
+        quirks.clip_sprites,
+    ];
+    flags
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (idx, &flag)| bits | (flag as u8) << idx)
+}
+
+/// Unpack the quirks from a byte made by `quirks_to_bits`.
+fn quirks_from_bits(bits: u8) -> Quirks {
+    let flag = |idx: u8| bits & 1 << idx != 0;
+    Quirks {
+        shift_uses_vy: flag(0),
+        load_store_increments_i: flag(1),
+        jump_uses_vx: flag(2),
+        logic_resets_vf: flag(3),
+        clip_sprites: flag(4),
+    }
+}
+
+/// Read exactly `N` bytes from `r`.
+fn read_array<const N: usize>(r: &mut impl Read) -> Result<[u8; N], ErrorKind> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::scheduler::Scheduler;
+
+    /// Run `rom` for as many frames as `inputs` has, recording a movie.
+    fn record(rom: &[u8], seed: u64, inputs: &[Input]) -> Movie {
+        let mut state = State::new(rom, Quirks::vip());
+        state.seed(seed);
+        let mut movie = Movie::new(&state, seed, 700, 60);
+        let mut scheduler = Scheduler::new(movie.ips, movie.fps);
+        for &input in inputs {
+            movie.push(input);
+            state.run_frame(scheduler.next_frame(), input).unwrap();
+        }
+        movie.finish(&state);
+        movie
+    }
+
+    /// Replay `movie` with `rom`, checking the result.
+    fn replay(rom: &[u8], movie: &Movie) -> Result<(), ErrorKind> {
+        let mut state = State::new(rom, Quirks::default());
+        movie.prepare(&mut state)?;
+        let mut scheduler = Scheduler::new(movie.ips, movie.fps);
+        for &input in &movie.inputs {
+            state.run_frame(scheduler.next_frame(), input)?;
+        }
+        movie.verify(&state, movie.inputs.len() as u64)
+    }
+
+    #[test]
+    fn test_replay() {
+        // C0FF - V0 = random, E19E - skip if key V1 is pressed, 7201 - V2 += 1, 1200 - loop
+        let rom = [0xC0, 0xFF, 0xE1, 0x9E, 0x72, 0x01, 0x12, 0x00];
+        let mut inputs = vec![[false; 16]; 10];
+        inputs[3][0x0] = true;
+        inputs[7][0x0] = true;
+        let movie = record(&rom, 1234, &inputs);
+
+        let mut buf = Vec::new();
+        movie.save(&mut buf).unwrap();
+        let loaded = Movie::load(&mut buf.as_slice()).unwrap();
+        assert_eq!(movie, loaded);
+        assert!(replay(&rom, &loaded).is_ok());
+
+        // A different seed, or different input, ends up somewhere else
+        let mut reseeded = loaded.clone();
+        reseeded.seed = 4321;
+        match replay(&rom, &reseeded) {
+            Err(ErrorKind::MovieDesync { .. }) => {}
+            res => panic!("expected a desync, got {:?}", res),
+        }
+        let mut pressed = loaded.clone();
+        pressed.inputs[5][0x0] = true;
+        assert!(replay(&rom, &pressed).is_err());
+        match replay(&[0x12, 0x00], &loaded) {
+            Err(ErrorKind::MovieRom) => {}
+            res => panic!("expected a ROM mismatch, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_bits() {
+        let mut input = Input::default();
+        input[0x3] = true;
+        input[0xF] = true;
+        assert_eq!(0x8008, input_to_bits(input));
+        assert_eq!(input, input_from_bits(0x8008));
+        for quirks in &[
+            Quirks::default(),
+            Quirks::vip(),
+            Quirks::schip(),
+            Quirks::xochip(),
+        ] {
+            assert_eq!(*quirks, quirks_from_bits(quirks_to_bits(*quirks)));
+        }
+    }
+}
@@ -1,57 +1,87 @@
-use crate::{
-    emulator::{input::Input, State},
-    utils::u8_to_bits,
-};
+use crate::emulator::{fault::Fault, fontset, input::Input, State};
+use std::ops::Range;
 
 // http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
 
 /// Return from a subroutine.
 ///
 /// The interpreter sets the program counter to the address at the top of the stack, then
-/// subtracts 1 from the stack pointer.
-pub fn r#return(s: &mut State) {
+/// subtracts 1 from the stack pointer. Faults if there is no subroutine to return from.
+pub fn r#return(s: &mut State) -> Result<(), Fault> {
+    if s.sp == 0 {
+        return Err(Fault::StackUnderflow);
+    }
     s.sp -= 1;
     // Do not decrement PC: if we did this, we would end up calling the function again!
     // We want to skip the instruction at the address we are branching back to.
-    s.pc = s.stack[s.sp as usize];
+    s.pc = s.stack[s.sp];
+    Ok(())
 }
 
 /// Call subroutine at nnn.
 ///
 /// The interpreter increments the stack pointer, then puts the current PC on the top of the stack.
-/// The PC is then set to nnn.
-pub fn call(s: &mut State, addr: u16) {
-    s.stack[s.sp as usize] = s.pc;
+/// The PC is then set to nnn. Faults if all 16 levels of the stack are already in use.
+pub fn call(s: &mut State, addr: u16) -> Result<(), Fault> {
+    if s.sp >= s.stack.len() {
+        return Err(Fault::StackOverflow);
+    }
+    s.stack[s.sp] = s.pc;
     s.pc = addr;
     s.sp += 1;
+    Ok(())
+}
+
+/// The range of `len` bytes of memory starting at `addr`, or a fault if it runs past the end.
+fn mem_range(addr: u16, len: usize) -> Result<Range<usize>, Fault> {
+    let start = addr as usize;
+    if start + len > State::MEM_SIZE {
+        return Err(Fault::MemoryOutOfRange { addr, len });
+    }
+    Ok(start..start + len)
+}
+
+/// Skip the next instruction.
+///
+/// The program counter is increased by 2, or by 4 if the next instruction is the XO-CHIP
+/// `F000 nnnn`, which is twice as long as any other.
+fn skip_next(s: &mut State) -> Result<(), Fault> {
+    let long = s.opcode_at(s.pc) == 0xF000;
+    s.pc =
+        s.pc.checked_add(if long { 4 } else { 2 })
+            .ok_or(Fault::PcOutOfRange)?;
+    Ok(())
 }
 
 /// Skip next instruction if Vx = kk.
 ///
 /// The interpreter compares register Vx to kk, and if they are equal, increments the program
 /// counter by 2.
-pub fn skip_if_equal(s: &mut State, x: u8, byte: u8) {
+pub fn skip_if_equal(s: &mut State, x: u8, byte: u8) -> Result<(), Fault> {
     if s.reg_v[x as usize] == byte {
-        s.pc += 2;
+        skip_next(s)?;
     }
+    Ok(())
 }
 /// Skip next instruction if Vx != kk.
 ///
 /// The interpreter compares register Vx to kk, and if they are not equal, increments the program
 /// counter by 2.
-pub fn skip_if_not_equal(s: &mut State, x: u8, byte: u8) {
+pub fn skip_if_not_equal(s: &mut State, x: u8, byte: u8) -> Result<(), Fault> {
     if s.reg_v[x as usize] != byte {
-        s.pc += 2;
+        skip_next(s)?;
     }
+    Ok(())
 }
 /// Skip next instruction if Vx = Vy.
 ///
 /// The interpreter compares register Vx to register Vy, and if they are equal, increments the
 /// program counter by 2.
-pub fn skip_reg_equal(s: &mut State, x: u8, y: u8) {
+pub fn skip_reg_equal(s: &mut State, x: u8, y: u8) -> Result<(), Fault> {
     if s.reg_v[x as usize] == s.reg_v[y as usize] {
-        s.pc += 2;
+        skip_next(s)?;
     }
+    Ok(())
 }
 
 /// Set Vx = Vx + Vy, set VF = carry.
@@ -74,32 +104,61 @@ pub fn subtract(s: &mut State, x: u8, y: u8) {
     s.reg_v[0xF] = !flag as u8;
 }
 
+/// Set Vx = Vx OR Vy, Vx AND Vy or Vx XOR Vy, depending on `op`.
+///
+/// The bitwise operation is applied to Vx and Vy, and the result stored in Vx. On the COSMAC VIP,
+/// these instructions also reset VF to 0.
+pub fn logical(s: &mut State, x: u8, y: u8, op: impl Fn(u8, u8) -> u8) {
+    s.reg_v[x as usize] = op(s.reg_v[x as usize], s.reg_v[y as usize]);
+    if s.quirks.logic_resets_vf {
+        s.reg_v[0xF] = 0;
+    }
+}
+
 /// Set Vx = Vx SHR 1.
 ///
 /// If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided
-/// by 2.
-pub fn shift_right(s: &mut State, x: u8) {
-    s.reg_v[0xF] = s.reg_v[x as usize] & 1;
-    s.reg_v[x as usize] >>= 1;
+/// by 2. On the COSMAC VIP, Vy is shifted instead and the result stored in Vx.
+pub fn shift_right(s: &mut State, x: u8, y: u8) {
+    let src = if s.quirks.shift_uses_vy { y } else { x };
+    let val = s.reg_v[src as usize];
+    s.reg_v[x as usize] = val >> 1;
+    s.reg_v[0xF] = val & 1;
 }
 
 /// Set Vx = Vx SHL 1.
 ///
-///If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is
-/// multiplied by 2.
-pub fn shift_left(s: &mut State, x: u8) {
-    s.reg_v[0xF] = ((s.reg_v[x as usize] & 0x80) != 0) as u8;
-    s.reg_v[x as usize] <<= 1;
+/// If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is
+/// multiplied by 2. On the COSMAC VIP, Vy is shifted instead and the result stored in Vx.
+pub fn shift_left(s: &mut State, x: u8, y: u8) {
+    let src = if s.quirks.shift_uses_vy { y } else { x };
+    let val = s.reg_v[src as usize];
+    s.reg_v[x as usize] = val << 1;
+    s.reg_v[0xF] = ((val & 0x80) != 0) as u8;
 }
 
 /// Skip next instruction if Vx != Vy.
 ///
 /// The values of Vx and Vy are compared, and if they are not equal, the program counter is
 /// increased by 2.
-pub fn skip_reg_not_equal(s: &mut State, x: u8, y: u8) {
+pub fn skip_reg_not_equal(s: &mut State, x: u8, y: u8) -> Result<(), Fault> {
     if s.reg_v[x as usize] != s.reg_v[y as usize] {
-        s.pc += 2;
+        skip_next(s)?;
     }
+    Ok(())
+}
+
+/// Jump to location nnn + V0.
+///
+/// The program counter is set to nnn plus the value of V0. On SUPER-CHIP, the instruction is
+/// instead read as Bxnn, and the value of Vx is added, where x is the highest nibble of nnn.
+pub fn jump_offset(s: &mut State, addr: u16) {
+    let x = if s.quirks.jump_uses_vx {
+        (addr >> 8) as usize
+    } else {
+        0
+    };
+    s.pc = addr + s.reg_v[x] as u16;
 }
 
 /// Set Vx = random byte AND kk.
@@ -107,7 +166,7 @@ pub fn skip_reg_not_equal(s: &mut State, x: u8, y: u8) {
 /// The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk.
 /// The results are stored in Vx. See instruction 8xy2 for more information on AND.
 pub fn random(s: &mut State, x: u8, kk: u8) {
-    let rng: u8 = rand::random();
+    let rng = s.rng.next_u8();
     s.reg_v[x as usize] = rng & kk;
 }
 
@@ -118,50 +177,97 @@ pub fn random(s: &mut State, x: u8, kk: u8) {
 /// Sprites are XORed onto the existing screen.
 /// If this causes any pixels to be erased, VF is set to 1, otherwise it is set to 0.
 /// If the sprite is positioned so part of it is outside the coordinates of the display, it wraps
-/// around to the opposite side of the screen.
-pub fn draw_sprite(s: &mut State, x: u8, y: u8, n: u8) {
-    let x = s.reg_v[x as usize] as usize;
-    let y = s.reg_v[y as usize] as usize;
+/// around to the opposite side of the screen, or is clipped if the quirks ask for it.
+pub fn draw_sprite(s: &mut State, x: u8, y: u8, n: u8) -> Result<(), Fault> {
+    draw(s, x, y, n as usize, 1)
+}
+
+/// Display 16x16 sprite starting at memory location I at (Vx, Vy), set VF = collision.
+///
+/// The interpreter reads 32 bytes from memory, starting at the address stored in I, as 16 rows of
+/// two bytes each. The sprite is then drawn in the same way as for Dxyn.
+pub fn draw_large_sprite(s: &mut State, x: u8, y: u8) -> Result<(), Fault> {
+    draw(s, x, y, 16, 2)
+}
+
+/// Draw a sprite of `height` rows, each `row_bytes` wide, to every selected plane. When more than
+/// one plane is selected, the sprite for each plane follows directly after the one before it.
+fn draw(s: &mut State, x: u8, y: u8, height: usize, row_bytes: usize) -> Result<(), Fault> {
+    let size = height * row_bytes;
+    let planes = s.display.planes().count_ones() as usize;
+    let mut addr = mem_range(s.reg_i, size * planes)?.start;
+    let mut collision = false;
+
+    for &plane in &[0b01, 0b10] {
+        if s.display.planes() & plane == 0 {
+            continue;
+        }
+        let rows: Vec<u16> = s.mem[addr..addr + size]
+            .chunks_exact(row_bytes)
+            .map(|row| match *row {
+                [hi] => (hi as u16) << 8,
+                [hi, lo] => (hi as u16) << 8 | lo as u16,
+                _ => unreachable!("sprite rows are one or two bytes wide"),
+            })
+            .collect();
+        collision |= blit(s, x, y, &rows, plane);
+        addr += size;
+    }
+    s.reg_v[0xF] = collision as u8;
+    Ok(())
+}
 
-    s.reg_v[0xF] = 0;
-    // We can write to (x, y) as display[(x % w) + (y % h) * w]
+/// XOR sprite rows onto a plane of the screen at (Vx, Vy), returning whether any lit pixels were
+/// erased. Each row is up to 16 pixels wide, with the leftmost pixel in the most significant bit.
+fn blit(s: &mut State, x: u8, y: u8, rows: &[u16], plane: u8) -> bool {
+    let (w, h) = (s.display.width(), s.display.height());
+    // The starting position always wraps, even when the rest of the sprite is clipped
+    let x = s.reg_v[x as usize] as usize % w;
+    let y = s.reg_v[y as usize] as usize % h;
+    let clip = s.quirks.clip_sprites;
+
+    let mut collision = false;
     // Each time dy increments, we move down to the next row
-    for dy in 0..(n as usize) {
-        let byte = s.mem[s.reg_i as usize + dy];
+    for (dy, &row) in rows.iter().enumerate() {
+        if clip && y + dy >= h {
+            break;
+        }
         // Each time dx increments, we move to the next column
-        for (dx, &bit) in u8_to_bits(byte).iter().enumerate() {
-            let x_idx = (x + dx) % State::WIDTH;
-            let y_idx = ((y + dy) % State::HEIGHT) * State::WIDTH;
-            let pixel = &mut s.display[x_idx + y_idx];
-
-            if bit && *pixel {
-                s.reg_v[0xF] = 1;
+        for dx in 0..16 {
+            if clip && x + dx >= w {
+                break;
+            }
+            let bit = row & (0x8000 >> dx) != 0;
+            if bit && s.display.flip((x + dx) % w, (y + dy) % h, plane) {
+                collision = true;
             }
-            *pixel ^= bit;
         }
     }
+    collision
 }
 
 /// Skip next instruction if key with the value of Vx is pressed.
 ///
 /// Checks the keyboard, and if the key corresponding to the value of Vx is currently in the down
 /// position, PC is increased by 2.
-pub fn skip_if_pressed(s: &mut State, inp: Input, x: u8) {
+pub fn skip_if_pressed(s: &mut State, inp: Input, x: u8) -> Result<(), Fault> {
     let val = s.reg_v[x as usize];
     if inp[val as usize] {
-        s.pc += 2;
+        skip_next(s)?;
     }
+    Ok(())
 }
 
 /// Skip next instruction if key with the value of Vx is not pressed.
 ///
 /// Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up
 /// position, PC is increased by 2.
-pub fn skip_if_unpressed(s: &mut State, inp: Input, x: u8) {
+pub fn skip_if_unpressed(s: &mut State, inp: Input, x: u8) -> Result<(), Fault> {
     let val = s.reg_v[x as usize];
     if !inp[val as usize] {
-        s.pc += 2;
+        skip_next(s)?;
     }
+    Ok(())
 }
 
 /// Wait for a key press, store the value of the key in Vx.
@@ -176,13 +282,8 @@ pub fn block_input(s: &mut State, inp: Input, x: u8) {
     }
 
     // All execution stops. Hence, we should decrement the PC. This will be ran again next frame.
-    // We should also make sure the timers do not increase, or the emulation is not accurate.
+    // The timers carry on counting down while we wait.
     s.pc -= 2;
-    s.delay += 1;
-    // Only increase the sound timer if it's not already finished, otherwise it'll beep.
-    if s.sound != 0 {
-        s.sound += 1;
-    }
 }
 
 /// Set I = location of sprite for digit Vx.
@@ -190,38 +291,127 @@ pub fn block_input(s: &mut State, inp: Input, x: u8) {
 /// The value of I is set to the location for the hexadecimal sprite corresponding to the
 /// value of Vx
 pub fn sprite_location(s: &mut State, x: u8) {
-    debug_assert!(x <= 0xF);
-    s.reg_i = x as u16 * 5;
+    let digit = (s.reg_v[x as usize] & 0xF) as usize;
+    s.reg_i = (fontset::SMALL_ADDR + digit * fontset::BYTES) as u16;
+}
+
+/// Set I = location of big sprite for digit Vx.
+///
+/// The value of I is set to the location for the 8x10 hexadecimal sprite corresponding to the
+/// value of Vx.
+pub fn big_sprite_location(s: &mut State, x: u8) {
+    let digit = (s.reg_v[x as usize] & 0xF) as usize;
+    s.reg_i = (fontset::BIG_ADDR + digit * fontset::BIG_BYTES) as u16;
 }
 
 /// Store BCD representation of Vx in memory locations I, I+1, and I+2.
 ///
 /// The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at
 /// location in I, the tens digit at location I+1, and the ones digit at location I+2.
-pub fn store_bcd(s: &mut State, x: u8) {
-    let i = s.reg_i as usize;
+pub fn store_bcd(s: &mut State, x: u8) -> Result<(), Fault> {
+    let i = mem_range(s.reg_i, 3)?.start;
     let vx = s.reg_v[x as usize];
     // No need to mod 1000 because the range of u8 is below this
-    s.mem[i + 0] = vx /*% 1000*/ / 100;
+    s.mem[i] = vx /*% 1000*/ / 100;
     s.mem[i + 1] = (vx % 100) / 10;
     s.mem[i + 2] = vx % 10;
+    Ok(())
 }
 
 /// Store registers V0 through Vx in memory starting at location I.
 ///
 /// The interpreter copies the values of registers V0 through Vx into memory, starting at the
-/// address in I.
-pub fn copy_registers(s: &mut State, x: u8) {
+/// address in I. On the COSMAC VIP, I is left pointing just past the last address written.
+pub fn copy_registers(s: &mut State, x: u8) -> Result<(), Fault> {
     let x = x as usize;
-    let idx = s.reg_i as usize;
-    s.mem[idx..=(idx + x)].copy_from_slice(&s.reg_v[0..=x]);
+    let range = mem_range(s.reg_i, x + 1)?;
+    s.mem[range].copy_from_slice(&s.reg_v[0..=x]);
+    if s.quirks.load_store_increments_i {
+        s.reg_i = s.reg_i.wrapping_add(x as u16 + 1);
+    }
+    Ok(())
 }
 
 /// Read registers V0 through Vx from memory starting at location I.
 ///
 /// The interpreter reads values from memory starting at location I into registers V0 through Vx.
-pub fn load_registers(s: &mut State, x: u8) {
+/// On the COSMAC VIP, I is left pointing just past the last address read.
+pub fn load_registers(s: &mut State, x: u8) -> Result<(), Fault> {
     let x = x as usize;
-    let idx = s.reg_i as usize;
-    s.reg_v[0..=x].copy_from_slice(&s.mem[idx..=(idx + x)]);
+    let range = mem_range(s.reg_i, x + 1)?;
+    s.reg_v[0..=x].copy_from_slice(&s.mem[range]);
+    if s.quirks.load_store_increments_i {
+        s.reg_i = s.reg_i.wrapping_add(x as u16 + 1);
+    }
+    Ok(())
+}
+
+/// Store registers V0 through Vx in the RPL user flags.
+///
+/// The interpreter copies the values of registers V0 through Vx into the user flags, which are
+/// kept separately from the main memory.
+pub fn save_flags(s: &mut State, x: u8) {
+    let x = x as usize;
+    s.rpl[0..=x].copy_from_slice(&s.reg_v[0..=x]);
+}
+
+/// Read registers V0 through Vx from the RPL user flags.
+///
+/// The interpreter reads values from the user flags into registers V0 through Vx.
+pub fn load_flags(s: &mut State, x: u8) {
+    let x = x as usize;
+    s.reg_v[0..=x].copy_from_slice(&s.rpl[0..=x]);
+}
+
+/// Store registers Vx through Vy in memory starting at location I.
+///
+/// The interpreter copies the values of registers Vx through Vy into memory, starting at the
+/// address in I. If x > y, the registers are copied in reverse order. I is not modified.
+pub fn copy_register_range(s: &mut State, x: u8, y: u8) -> Result<(), Fault> {
+    let idx = mem_range(s.reg_i, register_range(x, y).count())?.start;
+    for (offset, reg) in register_range(x, y).enumerate() {
+        s.mem[idx + offset] = s.reg_v[reg];
+    }
+    Ok(())
+}
+
+/// Read registers Vx through Vy from memory starting at location I.
+///
+/// The interpreter reads values from memory starting at location I into registers Vx through Vy.
+/// If x > y, the registers are read in reverse order. I is not modified.
+pub fn load_register_range(s: &mut State, x: u8, y: u8) -> Result<(), Fault> {
+    let idx = mem_range(s.reg_i, register_range(x, y).count())?.start;
+    for (offset, reg) in register_range(x, y).enumerate() {
+        s.reg_v[reg] = s.mem[idx + offset];
+    }
+    Ok(())
+}
+
+/// The register indexes from x to y inclusive, counting down if x > y.
+fn register_range(x: u8, y: u8) -> impl Iterator<Item = usize> {
+    let (x, y) = (x as usize, y as usize);
+    let forwards = (x..=y).filter(move |_| x <= y);
+    let backwards = (y..=x).rev().filter(move |_| x > y);
+    forwards.chain(backwards)
+}
+
+/// Set I = nnnn.
+///
+/// The interpreter loads the 16-bit address stored in the two bytes following this instruction
+/// into I, then skips over them.
+pub fn load_long_address(s: &mut State) -> Result<(), Fault> {
+    let next = s.pc.checked_add(2).ok_or(Fault::PcOutOfRange)?;
+    s.reg_i = s.opcode_at(s.pc);
+    s.pc = next;
+    Ok(())
+}
+
+/// Load the audio pattern buffer from memory starting at location I.
+///
+/// The interpreter copies 16 bytes from memory, starting at the address in I, into the audio
+/// pattern buffer, which is played as 128 1-bit samples while the sound timer is active.
+pub fn load_audio_pattern(s: &mut State) -> Result<(), Fault> {
+    let range = mem_range(s.reg_i, 16)?;
+    s.audio.copy_from_slice(&s.mem[range]);
+    Ok(())
 }
@@ -0,0 +1,85 @@
+use crate::error::QuirksError;
+use std::str::FromStr;
+
+/// Switches for the instructions whose behaviour differs between CHIP-8 implementations.
+///
+/// Programs written for the original COSMAC VIP interpreter rely on different semantics to those
+/// written for SUPER-CHIP or XO-CHIP, so the interpretation of each ambiguous instruction can be
+/// chosen here. The named presets, `Quirks::vip`, `Quirks::schip` and `Quirks::xochip`, cover the
+/// most common platforms.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8xy6` and `8xyE` shift Vy and store the result in Vx, instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    /// `Fx55` and `Fx65` leave I pointing one past the last register accessed.
+    pub load_store_increments_i: bool,
+    /// `Bnnn` is interpreted as `Bxnn`, jumping to xnn + Vx instead of nnn + V0.
+    pub jump_uses_vx: bool,
+    /// `8xy1`, `8xy2` and `8xy3` reset VF to zero.
+    pub logic_resets_vf: bool,
+    /// Sprites drawn over the edge of the screen are clipped, instead of wrapping around.
+    pub clip_sprites: bool,
+}
+
+impl Quirks {
+    /// The behaviour of the original COSMAC VIP interpreter.
+    pub fn vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+        }
+    }
+
+    /// The behaviour of SUPER-CHIP 1.1 on the HP48 calculators.
+    pub fn schip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+        }
+    }
+
+    /// The behaviour of XO-CHIP, as implemented by Octo.
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+        }
+    }
+}
+
+/// The interpretation most commonly described by CHIP-8 documentation, and the one this
+/// emulator has always used.
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+        }
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = QuirksError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "default" => Quirks::default(),
+            "vip" => Quirks::vip(),
+            "schip" => Quirks::schip(),
+            "xochip" => Quirks::xochip(),
+            _ => return Err(QuirksError(s.to_owned())),
+        })
+    }
+}
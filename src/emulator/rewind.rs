@@ -0,0 +1,121 @@
+use crate::emulator::{snapshot, State};
+use std::collections::VecDeque;
+
+/// A run of bytes which differ between two serialised states, starting at `offset`.
+struct Run {
+    offset: usize,
+    bytes: Vec<u8>,
+}
+
+/// A ring buffer of past states, which lets the emulator step backwards in time.
+///
+/// Only the most recent state is kept in full. Every older state is stored as the list of byte
+/// runs that turn its successor back into it, and since very little changes between frames these
+/// deltas are tiny compared to the 64K of memory in a `State`. Once the buffer is full, the
+/// oldest state is forgotten.
+pub struct Rewind {
+    capacity: usize,
+    /// The most recently pushed state, in the save state format.
+    latest: Option<Vec<u8>>,
+    /// Deltas from each state to the one before it, oldest first.
+    history: VecDeque<Vec<Run>>,
+}
+
+impl Rewind {
+    /// Create an empty rewind buffer, which remembers up to `capacity` states before the latest.
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            capacity,
+            latest: None,
+            history: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// The number of states that can currently be stepped back through.
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Whether there are no states to step back through.
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    /// Record `state` as the newest entry in the buffer.
+    pub fn push(&mut self, state: &State) {
+        let mut current = Vec::new();
+        snapshot::save(state, &mut current).expect("writing to a Vec cannot fail");
+
+        if let Some(latest) = self.latest.take() {
+            if self.capacity > 0 {
+                if self.history.len() == self.capacity {
+                    self.history.pop_front();
+                }
+                self.history.push_back(diff(&current, &latest));
+            }
+        }
+        self.latest = Some(current);
+    }
+
+    /// Step back to the state before the latest one, writing it into `state`. Returns `false`,
+    /// leaving `state` untouched, if there is nothing left to rewind.
+    pub fn pop(&mut self, state: &mut State) -> bool {
+        let (latest, delta) = match (self.latest.as_mut(), self.history.pop_back()) {
+            (Some(latest), Some(delta)) => (latest, delta),
+            _ => return false,
+        };
+        for run in delta {
+            latest[run.offset..run.offset + run.bytes.len()].copy_from_slice(&run.bytes);
+        }
+        snapshot::restore(state, &mut latest.as_slice()).expect("rewind buffer is always valid");
+        true
+    }
+}
+
+/// The runs of bytes which must be written over `from` to turn it into `to`. Both must be the
+/// same length, which is always true for serialised states.
+fn diff(from: &[u8], to: &[u8]) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+    for (offset, (&a, &b)) in from.iter().zip(to).enumerate() {
+        if a == b {
+            continue;
+        }
+        match runs.last_mut() {
+            Some(run) if run.offset + run.bytes.len() == offset => run.bytes.push(b),
+            _ => runs.push(Run {
+                offset,
+                bytes: vec![b],
+            }),
+        }
+    }
+    runs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Quirks;
+
+    #[test]
+    fn test_rewind() {
+        // 7001 - V0 += 1, 1200 - Jump back to the start
+        let mut state = State::new(&[0x70, 0x01, 0x12, 0x00], Quirks::default());
+        let mut rewind = Rewind::new(3);
+        rewind.push(&state);
+        for _ in 0..10 {
+            state.step_forward().unwrap();
+            rewind.push(&state);
+        }
+        assert_eq!(5, state.reg_v[0x0]);
+        assert_eq!(3, rewind.len());
+
+        assert!(rewind.pop(&mut state));
+        assert_eq!((5, 0x202), (state.reg_v[0x0], state.pc));
+        assert!(rewind.pop(&mut state));
+        assert_eq!((4, 0x200), (state.reg_v[0x0], state.pc));
+        assert!(rewind.pop(&mut state));
+        assert_eq!((4, 0x202), (state.reg_v[0x0], state.pc));
+        assert!(!rewind.pop(&mut state));
+        assert_eq!((4, 0x202), (state.reg_v[0x0], state.pc));
+    }
+}
@@ -0,0 +1,63 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+/// The pseudo-random number generator used by `Cxkk`.
+///
+/// Unlike the thread RNG, it is seeded explicitly and its whole state is a single number, so it
+/// is copied along with the rest of `State`, saved in snapshots and rewound, and a run started
+/// from the same seed with the same input always turns out the same. This is SplitMix64.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rng(pub(crate) u64);
+
+impl Rng {
+    /// Create a generator which starts from `seed`.
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    /// A seed which is different every time, for runs which don't need to be repeated.
+    ///
+    /// This comes from the randomly keyed hasher of the standard library, which is seeded by the
+    /// operating system, so the core library needs no random number crate.
+    pub fn random_seed() -> u64 {
+        RandomState::new().build_hasher().finish()
+    }
+
+    /// The next random 64-bit number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// The next random byte.
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        // The first output for seed 0, from the reference implementation
+        assert_eq!(0xE220_A839_7B1D_CDAF, Rng::new(0).next_u64());
+
+        let mut a = Rng::new(42);
+        let mut b = a;
+        let bytes: Vec<u8> = (0..16).map(|_| a.next_u8()).collect();
+        assert_eq!(bytes, (0..16).map(|_| b.next_u8()).collect::<Vec<_>>());
+        assert!(bytes.iter().any(|&byte| byte != bytes[0]));
+    }
+
+    #[test]
+    fn test_random_seed() {
+        assert_ne!(Rng::random_seed(), Rng::random_seed());
+    }
+}
@@ -0,0 +1,184 @@
+//! ROMs are usually shared as raw binaries, but this module also reads them as text hex dumps,
+//! compressed with gzip, or from standard input. The format is recognised from the contents of
+//! the file, so the extension doesn't matter:
+//!
+//! - Files starting with the gzip magic bytes `1F 8B` are decompressed, then read again.
+//! - Files starting with `GIF87a` or `GIF89a` are taken to be Octo cartridges, which hold the
+//!   source code of a program rather than a ROM, so the program is compiled with `octo::compile`.
+//!   Builds without the `cartridge` feature reject them instead.
+//! - Files made up of nothing but hex digits, whitespace, commas and `0x` prefixes are hex dumps,
+//!   such as `12 4E EA AC` or `0x12, 0x4E`, or the output of `xxd -p`.
+//! - Anything else is a raw binary.
+//!
+//! Every ROM must have at least one byte, and fit in memory after the 0x200 bytes reserved for
+//! the interpreter. Memory is 64K whichever quirks profile is chosen, as `MAX_SIZE` explains.
+
+#[cfg(feature = "cartridge")]
+use crate::octo;
+use crate::{emulator::State, error::ErrorKind};
+use flate2::read::GzDecoder;
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+/// The largest ROM which fits in memory, after the 0x200 bytes reserved for the interpreter.
+///
+/// This is the same for every quirks profile, since quirks only change how ambiguous instructions
+/// behave. Memory is always the 64K of XO-CHIP, and `F000 nnnn` reaches all of it in any profile,
+/// so nothing in a ROM this size is lost. A ROM above 3.5K written for a 4K machine such as the
+/// COSMAC VIP can't have been run there, but it still runs here, rather than being turned away.
+pub const MAX_SIZE: usize = State::MEM_SIZE - 0x200;
+
+/// The most bytes read from a compressed file, which is enough for a hex dump of the largest
+/// ROM, and keeps decompression from filling memory.
+const MAX_DECOMPRESSED: u64 = 4 * MAX_SIZE as u64;
+
+/// The ways a ROM can be stored in a file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The bytes of the program, as they're loaded into memory.
+    Binary,
+    /// The bytes of the program written out in hexadecimal.
+    Hex,
+    /// Another format, compressed with gzip.
+    Gzip,
+    /// An Octo cartridge, a GIF image with the program's source code hidden in its pixels.
+    Cartridge,
+}
+
+impl Format {
+    /// Recognise the format of a file from its contents.
+    pub fn detect(data: &[u8]) -> Format {
+        let hex_char =
+            |&c: &u8| c.is_ascii_hexdigit() || c.is_ascii_whitespace() || b",xX".contains(&c);
+        if data.starts_with(&[0x1F, 0x8B]) {
+            Format::Gzip
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            Format::Cartridge
+        } else if data.iter().any(u8::is_ascii_hexdigit) && data.iter().all(hex_char) {
+            Format::Hex
+        } else {
+            Format::Binary
+        }
+    }
+}
+
+/// Read the ROM in the file at `path`, or from standard input if the path is `-`.
+pub fn load_file(path: impl AsRef<Path>) -> Result<Vec<u8>, ErrorKind> {
+    let path = path.as_ref();
+    let data = if path == Path::new("-") {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data)?;
+        data
+    } else {
+        fs::read(path)?
+    };
+    load(&data)
+}
+
+/// The program held in the contents of a ROM file, in any format.
+pub fn load(data: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+    let rom = match Format::detect(data) {
+        Format::Binary => data.to_vec(),
+        Format::Hex => parse_hex(data)?,
+        Format::Gzip => return load(&decompress(data)?),
+        Format::Cartridge => cartridge(data)?,
+    };
+    if rom.is_empty() {
+        Err(ErrorKind::RomEmpty)
+    } else if rom.len() > MAX_SIZE {
+        Err(ErrorKind::RomTooLarge {
+            size: rom.len(),
+            max: MAX_SIZE,
+        })
+    } else {
+        Ok(rom)
+    }
+}
+
+/// The program compiled from the source code in an Octo cartridge.
+#[cfg(feature = "cartridge")]
+fn cartridge(data: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+    Ok(octo::compile(&octo::cartridge_source(data)?)?)
+}
+
+/// Octo cartridges can't be read without a GIF decoder, so this build rejects them.
+#[cfg(not(feature = "cartridge"))]
+fn cartridge(_data: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+    let msg = "This build was made without the `cartridge` feature needed to read it";
+    Err(ErrorKind::CartridgeErr(msg.to_owned()))
+}
+
+/// The bytes written in a hex dump, as pairs of hex digits separated by whitespace or commas,
+/// each optionally starting with `0x`.
+fn parse_hex(data: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+    let text = String::from_utf8_lossy(data);
+    let mut res = Vec::new();
+    for word in text.split(|c: char| c.is_whitespace() || c == ',') {
+        let digits = word.trim_start_matches("0x").trim_start_matches("0X");
+        if digits.len() % 2 != 0 || !digits.bytes().all(|c| c.is_ascii_hexdigit()) {
+            let msg = format!("Invalid hex dump, which contains {:?}", word);
+            return Err(ErrorKind::RomErr(msg));
+        }
+        for pair in digits.as_bytes().chunks(2) {
+            let pair = std::str::from_utf8(pair).expect("hex digits are ASCII");
+            res.push(u8::from_str_radix(pair, 16).expect("pairs are valid hex"));
+        }
+    }
+    Ok(res)
+}
+
+/// The contents of a gzip-compressed file.
+fn decompress(data: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+    let mut res = Vec::new();
+    GzDecoder::new(data)
+        .take(MAX_DECOMPRESSED + 1)
+        .read_to_end(&mut res)
+        .map_err(|e| ErrorKind::RomErr(format!("Could not decompress: {}", e)))?;
+    if res.len() as u64 > MAX_DECOMPRESSED {
+        let msg = "Decompressed ROM is far too large".to_owned();
+        return Err(ErrorKind::RomErr(msg));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    #[test]
+    fn test_load() {
+        let rom = [0x12, 0x4E, 0xEA, 0xAC];
+        assert_eq!(rom.to_vec(), load(&rom).unwrap());
+        assert_eq!(rom.to_vec(), load(b"124e eaac\n").unwrap());
+        assert_eq!(rom.to_vec(), load(b"0x12, 0x4E, 0xEA, 0xAC").unwrap());
+        assert!(load(b"12 4 EA").is_err());
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(b"12 4E EA AC").unwrap();
+        assert_eq!(rom.to_vec(), load(&gz.finish().unwrap()).unwrap());
+
+        assert!(matches!(load(&[]), Err(ErrorKind::RomEmpty)));
+        assert!(matches!(
+            load(&[0; MAX_SIZE + 1]),
+            Err(ErrorKind::RomTooLarge { size, .. }) if size == MAX_SIZE + 1
+        ));
+        assert!(matches!(
+            load(b"GIF89a\x80\x00\x40\x00"),
+            Err(ErrorKind::CartridgeErr(_))
+        ));
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Format::Binary, Format::detect(&[0x00, 0xE0, 0x12, 0x00]));
+        assert_eq!(Format::Hex, Format::detect(b"00E0 1200"));
+        assert_eq!(Format::Binary, Format::detect(b"  \n"));
+        assert_eq!(Format::Gzip, Format::detect(&[0x1F, 0x8B, 0x08]));
+        assert_eq!(Format::Cartridge, Format::detect(b"GIF87a"));
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+/// How many times a second the delay and sound timers count down.
+pub const TIMER_HZ: u64 = 60;
+
+/// The work to do in a single rendered frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Number of instructions to run.
+    pub instructions: u64,
+    /// Number of times to count the delay and sound timers down.
+    pub timer_ticks: u64,
+}
+
+/// Splits every second of emulation into frames, and decides how many instructions to run and
+/// how many timer ticks happen in each of them.
+///
+/// Neither the instruction rate nor the timer rate have to divide evenly by the frame rate, since
+/// the remainders are spread across the frames of each second. Over any one second, exactly `ips`
+/// instructions are run and the timers tick exactly `TIMER_HZ` times.
+#[derive(Clone, Debug)]
+pub struct Scheduler {
+    ips: u64,
+    fps: u64,
+    /// Index of the next frame within the current second.
+    frame: u64,
+}
+
+impl Scheduler {
+    /// Create a scheduler which runs `ips` instructions per second, over `fps` frames per second.
+    pub fn new(ips: u64, fps: u64) -> Self {
+        assert!(fps > 0, "frame rate must be at least 1");
+        Scheduler { ips, fps, frame: 0 }
+    }
+
+    /// How long each frame should last in real time.
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_nanos(1_000_000_000 / self.fps)
+    }
+
+    /// The work for the next frame.
+    pub fn next_frame(&mut self) -> Frame {
+        // The share of `rate` owed by the end of this frame, less the share owed before it
+        let (frame, fps) = (self.frame, self.fps);
+        let share = |rate: u64| rate * (frame + 1) / fps - rate * frame / fps;
+        self.frame = (self.frame + 1) % self.fps;
+        Frame {
+            instructions: share(self.ips),
+            timer_ticks: share(TIMER_HZ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scheduler() {
+        let mut scheduler = Scheduler::new(700, 60);
+        let frames: Vec<_> = (0..60).map(|_| scheduler.next_frame()).collect();
+        assert_eq!(700, frames.iter().map(|f| f.instructions).sum::<u64>());
+        assert!(frames
+            .iter()
+            .all(|f| f.instructions == 11 || f.instructions == 12));
+        assert!(frames.iter().all(|f| f.timer_ticks == 1));
+
+        // Timers still tick 60 times a second when frames are rarer or more frequent
+        let mut scheduler = Scheduler::new(500, 25);
+        let ticks: u64 = (0..25).map(|_| scheduler.next_frame().timer_ticks).sum();
+        assert_eq!(60, ticks);
+        let mut scheduler = Scheduler::new(500, 144);
+        let ticks: u64 = (0..144).map(|_| scheduler.next_frame().timer_ticks).sum();
+        assert_eq!(60, ticks);
+    }
+}
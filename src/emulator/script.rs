@@ -0,0 +1,95 @@
+use crate::{emulator::input::Input, error::ScriptError};
+use std::str::FromStr;
+
+/// The keys to hold down on each frame of a run without a keyboard.
+///
+/// A script is written one line per change, giving the number of the frame, counting from 0,
+/// followed by the keys held from that frame onwards as hex digits, or `-` for none at all.
+/// Lines must be in order of frame, and anything after a `#` is a comment:
+///
+/// ```text
+/// # Hold 5 for ten frames, then 4 and 6 together for another ten
+/// 60 5
+/// 70 46
+/// 80 -
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Script(Vec<(u64, Input)>);
+
+impl Script {
+    /// The keys held down on frame `frame`.
+    pub fn keys(&self, frame: u64) -> Input {
+        match self.0.iter().rev().find(|(start, _)| *start <= frame) {
+            Some(&(_, keys)) => keys,
+            None => [false; 16],
+        }
+    }
+}
+
+impl FromStr for Script {
+    type Err = ScriptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut res = Vec::new();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |msg: String| ScriptError(format!("Line {}: {}", number + 1, msg));
+
+            let mut words = line.split_whitespace();
+            let (frame, keys) = match (words.next(), words.next(), words.next()) {
+                (Some(frame), Some(keys), None) => (frame, keys),
+                _ => {
+                    return Err(error(format!(
+                        "Expected a frame and keys, found {:?}",
+                        line
+                    )))
+                }
+            };
+            let frame: u64 = frame
+                .parse()
+                .map_err(|_| error(format!("Invalid frame {:?}", frame)))?;
+            if res.last().is_some_and(|&(last, _)| last >= frame) {
+                return Err(error(format!("Frame {} is out of order", frame)));
+            }
+            let mut input = [false; 16];
+            if keys != "-" {
+                for c in keys.chars() {
+                    let key = c
+                        .to_digit(16)
+                        .ok_or_else(|| error(format!("Invalid key {:?}", c)))?;
+                    input[key as usize] = true;
+                }
+            }
+            res.push((frame, input));
+        }
+        Ok(Script(res))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_script() {
+        let script: Script = "# Comment\n10 5\n20 4f # Both\n\n30 -\n".parse().unwrap();
+        assert_eq!([false; 16], script.keys(0));
+        assert_eq!(
+            vec![0x5],
+            (0..16).filter(|&k| script.keys(19)[k]).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![0x4, 0xF],
+            (0..16).filter(|&k| script.keys(25)[k]).collect::<Vec<_>>()
+        );
+        assert_eq!([false; 16], script.keys(1000));
+
+        assert!("10".parse::<Script>().is_err());
+        assert!("10 5\n5 4".parse::<Script>().is_err());
+        assert!("10 g".parse::<Script>().is_err());
+        assert!("x 5".parse::<Script>().is_err());
+    }
+}
@@ -0,0 +1,245 @@
+//! Save states, which capture the entire state of the interpreter so it can be restored later.
+//!
+//! # Format
+//!
+//! Snapshots are stored as a flat binary file. All multi-byte integers are little-endian.
+//!
+//! | Size  | Contents                                                            |
+//! |-------|---------------------------------------------------------------------|
+//! | 4     | Magic bytes, `C8SS`                                                 |
+//! | 2     | Format version, currently 2                                         |
+//! | 8     | FNV-1a hash of the ROM the snapshot was taken from                  |
+//! | 65536 | `mem`                                                               |
+//! | 16    | `reg_v`                                                             |
+//! | 2     | `reg_i`                                                             |
+//! | 1     | `delay`                                                             |
+//! | 1     | `sound`                                                             |
+//! | 2     | `pc`                                                                |
+//! | 32    | `stack`, 16 addresses of 2 bytes each                               |
+//! | 1     | `sp`                                                                |
+//! | 1     | Whether `display` is in high resolution mode, 0 or 1                |
+//! | 1     | Bit-mask of the selected `display` planes                           |
+//! | 8192  | `display` pixels, in rows of 128, one byte per pixel                |
+//! | 16    | `rpl` user flags                                                    |
+//! | 1     | Whether the program has `halted`, 0 or 1                            |
+//! | 16    | `audio` pattern buffer                                              |
+//! | 1     | `pitch`                                                             |
+//! | 8     | State of the random number generator, `rng`                         |
+//!
+//! The quirks are not part of a snapshot, since they describe how the emulator is configured
+//! rather than the state of the program. Any change to the layout above must increment
+//! `VERSION`, so that older snapshots are rejected rather than misread.
+
+use crate::{
+    emulator::{rng::Rng, Framebuffer, State},
+    error::ErrorKind,
+    utils::fnv1a,
+};
+use std::{
+    fs::File,
+    io::{prelude::*, BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+/// Magic bytes at the start of every snapshot file.
+pub const MAGIC: [u8; 4] = *b"C8SS";
+
+/// The version of the snapshot format written by this build.
+pub const VERSION: u16 = 2;
+
+/// Write a snapshot of `state` to `w`.
+pub fn save(state: &State, w: &mut impl Write) -> Result<(), ErrorKind> {
+    w.write_all(&MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    w.write_all(&state.rom_hash.to_le_bytes())?;
+    w.write_all(&state.mem)?;
+    w.write_all(&state.reg_v)?;
+    w.write_all(&state.reg_i.to_le_bytes())?;
+    w.write_all(&[state.delay, state.sound])?;
+    w.write_all(&state.pc.to_le_bytes())?;
+    for addr in state.stack.iter() {
+        w.write_all(&addr.to_le_bytes())?;
+    }
+    w.write_all(&[state.sp as u8])?;
+    w.write_all(&[state.display.hires as u8, state.display.planes])?;
+    w.write_all(&state.display.pixels)?;
+    w.write_all(&state.rpl)?;
+    w.write_all(&[state.halted as u8])?;
+    w.write_all(&state.audio)?;
+    w.write_all(&[state.pitch])?;
+    w.write_all(&state.rng.0.to_le_bytes())?;
+    Ok(())
+}
+
+/// Restore a snapshot read from `r` into `state`.
+///
+/// The snapshot must have been taken from the same ROM that `state` was loaded with, and written
+/// with the current format version, and hold a display the renderers can show. If it was not,
+/// `state` is left untouched.
+pub fn restore(state: &mut State, r: &mut impl Read) -> Result<(), ErrorKind> {
+    if read_array::<4>(r)? != MAGIC {
+        return Err(ErrorKind::NotASnapshot);
+    }
+    let version = u16::from_le_bytes(read_array(r)?);
+    if version != VERSION {
+        return Err(ErrorKind::SnapshotVersion {
+            found: version,
+            expected: VERSION,
+        });
+    }
+    let rom_hash = u64::from_le_bytes(read_array(r)?);
+    if rom_hash != state.rom_hash {
+        return Err(ErrorKind::SnapshotRom);
+    }
+
+    // Fill in a copy, so a truncated snapshot can't leave `state` half-restored
+    let mut res = *state;
+    r.read_exact(&mut res.mem)?;
+    r.read_exact(&mut res.reg_v)?;
+    res.reg_i = u16::from_le_bytes(read_array(r)?);
+    let [delay, sound] = read_array(r)?;
+    res.delay = delay;
+    res.sound = sound;
+    res.pc = u16::from_le_bytes(read_array(r)?);
+    for addr in res.stack.iter_mut() {
+        *addr = u16::from_le_bytes(read_array(r)?);
+    }
+    let [sp] = read_array(r)?;
+    if sp as usize > res.stack.len() {
+        return Err(ErrorKind::NotASnapshot);
+    }
+    res.sp = sp as usize;
+    let [hires, planes] = read_array(r)?;
+    let pixels: [u8; Framebuffer::HIRES_WIDTH * Framebuffer::HIRES_HEIGHT] = read_array(r)?;
+    // Every pixel is a colour made of one bit per plane, which the renderers index palettes with
+    let colours = 1 << Framebuffer::PLANES;
+    if planes >= colours || pixels.iter().any(|&px| px >= colours) {
+        return Err(ErrorKind::NotASnapshot);
+    }
+    res.display = Framebuffer {
+        hires: hires != 0,
+        planes,
+        pixels,
+    };
+    r.read_exact(&mut res.rpl)?;
+    let [halted] = read_array(r)?;
+    res.halted = halted != 0;
+    r.read_exact(&mut res.audio)?;
+    let [pitch] = read_array(r)?;
+    res.pitch = pitch;
+    res.rng = Rng(u64::from_le_bytes(read_array(r)?));
+
+    *state = res;
+    Ok(())
+}
+
+/// A hash of everything in a snapshot of `state`, which two states only share if a program would
+/// carry on exactly the same way from either of them.
+pub fn hash(state: &State) -> u64 {
+    let mut buf = Vec::new();
+    save(state, &mut buf).expect("writing to a Vec can't fail");
+    fnv1a(&buf)
+}
+
+/// Write a snapshot of `state` to the file at `path`, replacing it if it exists.
+pub fn save_file(state: &State, path: impl AsRef<Path>) -> Result<(), ErrorKind> {
+    let mut w = BufWriter::new(File::create(path)?);
+    save(state, &mut w)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Restore a snapshot from the file at `path` into `state`.
+pub fn restore_file(state: &mut State, path: impl AsRef<Path>) -> Result<(), ErrorKind> {
+    restore(state, &mut BufReader::new(File::open(path)?))
+}
+
+/// The path of a numbered save slot for the ROM at `rom_path`, which sits beside the ROM.
+/// For example, slot 2 for `pong.ch8` is `pong.ch8.state2`.
+pub fn slot_path(rom_path: &Path, slot: u8) -> PathBuf {
+    let mut name = rom_path.as_os_str().to_owned();
+    name.push(format!(".state{}", slot));
+    PathBuf::from(name)
+}
+
+/// Read exactly `N` bytes from `r`.
+fn read_array<const N: usize>(r: &mut impl Read) -> Result<[u8; N], ErrorKind> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Quirks;
+
+    #[test]
+    fn test_round_trip() {
+        let mut state = State::new(&[0x00, 0xFF, 0x12, 0x00], Quirks::default());
+        state.step_forward().unwrap();
+        state.reg_v[0x3] = 0x42;
+        state.stack[0] = 0x234;
+        state.sp = 1;
+        state.display.flip(100, 50, 0b10);
+
+        let mut buf = Vec::new();
+        save(&state, &mut buf).unwrap();
+        let mut restored = State::new(&[0x00, 0xFF, 0x12, 0x00], Quirks::default());
+        restore(&mut restored, &mut buf.as_slice()).unwrap();
+
+        assert_eq!(state.mem[..], restored.mem[..]);
+        assert_eq!(state.reg_v, restored.reg_v);
+        assert_eq!(state.pc, restored.pc);
+        assert_eq!(state.stack, restored.stack);
+        assert_eq!(state.sp, restored.sp);
+        assert!(state.display == restored.display);
+        assert_eq!(state.rng, restored.rng);
+        assert_eq!(hash(&state), hash(&restored));
+    }
+
+    #[test]
+    fn test_rejects_mismatch() {
+        let state = State::new(&[0x12, 0x00], Quirks::default());
+        let mut buf = Vec::new();
+        save(&state, &mut buf).unwrap();
+
+        let mut other = State::new(&[0x13, 0x00], Quirks::default());
+        match restore(&mut other, &mut buf.as_slice()) {
+            Err(ErrorKind::SnapshotRom) => {}
+            res => panic!("expected a ROM mismatch, got {:?}", res),
+        }
+
+        buf[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let mut same = State::new(&[0x12, 0x00], Quirks::default());
+        match restore(&mut same, &mut buf.as_slice()) {
+            Err(ErrorKind::SnapshotVersion { found, expected }) => {
+                assert_eq!((VERSION + 1, VERSION), (found, expected))
+            }
+            res => panic!("expected a version mismatch, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_display() {
+        let state = State::new(&[0x12, 0x00], Quirks::default());
+        let mut buf = Vec::new();
+        save(&state, &mut buf).unwrap();
+        // The display follows the header, memory, registers, timers, program counter and stack
+        let planes = 4 + 2 + 8 + State::MEM_SIZE + 16 + 2 + 2 + 2 + 32 + 1 + 1;
+
+        let mut bad_planes = buf.clone();
+        bad_planes[planes] = 0b100;
+        let mut bad_pixel = buf.clone();
+        bad_pixel[planes + 1 + 10] = 4;
+        for bad in [bad_planes, bad_pixel].iter() {
+            let mut restored = State::new(&[0x12, 0x00], Quirks::default());
+            match restore(&mut restored, &mut bad.as_slice()) {
+                Err(ErrorKind::NotASnapshot) => {}
+                res => panic!("expected an invalid snapshot, got {:?}", res),
+            }
+        }
+        let mut restored = State::new(&[0x12, 0x00], Quirks::default());
+        restore(&mut restored, &mut buf.as_slice()).unwrap();
+    }
+}
@@ -1,23 +1,36 @@
-use crate::emulator::{fontset, input::Input, opcodes};
+use crate::{
+    emulator::{
+        fault::{EmulationFault, Fault},
+        fontset,
+        input::Input,
+        instruction::{decode, Instruction},
+        opcodes,
+        rng::Rng,
+        scheduler::Frame,
+        Framebuffer, Quirks,
+    },
+    utils::fnv1a,
+};
 use std::default::Default;
 
 #[derive(Copy, Clone)]
 /// The state in memory of the emulator at any given time.
 pub struct State {
-    /// The system's 4K of RAM.
-    pub(crate) mem: [u8; 4096],
+    /// The system's RAM. Only the first 4K are addressable by plain CHIP-8 programs, but XO-CHIP
+    /// extends this to 64K.
+    pub(crate) mem: [u8; State::MEM_SIZE],
     /// The system's 16 general-purpose registers.
     pub(crate) reg_v: [u8; 16],
     /// The system's single 16-bit register.
     pub(crate) reg_i: u16,
     /// The system's sound timer.
     ///
-    /// This is decremented every frame. Furthermore, when it reaches zero, a beep is emitted
-    /// by the system.
+    /// This is decremented 60 times a second. Furthermore, while it is above zero, a beep is
+    /// emitted by the system.
     pub(crate) sound: u8,
     /// The system's delay timer.
     ///
-    /// This is decremented every frame.
+    /// This is decremented 60 times a second.
     pub(crate) delay: u8,
     /// The address of the next instruction.
     pub(crate) pc: u16,
@@ -28,24 +41,44 @@ pub struct State {
     /// Pointer to the current value in the stack.
     pub(crate) sp: usize,
     /// Current contents of the screen.
-    pub(crate) display: [bool; 2048],
+    pub(crate) display: Framebuffer,
+    /// The SUPER-CHIP RPL user flags, saved and restored by `Fx75` and `Fx85`.
+    pub(crate) rpl: [u8; 16],
+    /// Set once the program has exited with `00FD`, after which no more instructions are run.
+    pub(crate) halted: bool,
+    /// The XO-CHIP audio pattern buffer, 128 1-bit samples loaded by `F002`, which the audio
+    /// backends play in place of the tone while the sound timer runs, once it has been loaded.
+    pub(crate) audio: [u8; 16],
+    /// The XO-CHIP playback pitch of the audio pattern buffer, set by `Fx3A`. The default of 64
+    /// plays the pattern at 4000 samples per second.
+    pub(crate) pitch: u8,
+    /// The interpretation used for ambiguous instructions.
+    pub(crate) quirks: Quirks,
+    /// Hash of the ROM the program was loaded from, used to match save states to their ROM.
+    pub(crate) rom_hash: u64,
+    /// The random number generator used by `Cxkk`.
+    pub(crate) rng: Rng,
 }
 
 impl State {
-    /// Width of the display.
-    pub const WIDTH: usize = 64;
+    /// Size of the system's RAM, the full XO-CHIP address space.
+    pub const MEM_SIZE: usize = 0x10000;
 
-    /// Height of the display.
-    pub const HEIGHT: usize = 32;
-
-    /// Create a new emulator with the given program ROM.
+    /// Create a new emulator with the given program ROM, interpreting ambiguous instructions
+    /// according to `quirks`.
     /// In this case, the ROM is loaded into memory at the address 0x200, which is where the
     /// majority of CHIP-8 programs start. The program counter is also initialised to point to
-    /// this location.
-    pub fn new(mem: &[u8]) -> Self {
-        let mut res = Self::default();
+    /// this location. The random number generator is given a random seed, which can be replaced
+    /// with `State::seed`.
+    pub fn new(mem: &[u8], quirks: Quirks) -> Self {
+        let mut res = Self {
+            quirks,
+            rom_hash: fnv1a(mem),
+            rng: Rng::new(Rng::random_seed()),
+            ..Self::default()
+        };
         // Load into emulator's memory starting at 0x200
-        // Silently truncates extra bytes (maybe worth changing?)
+        // Silently truncates extra bytes, so ROMs should be checked by `rom::load` first
         for (dst, &src) in res.mem[0x200..].iter_mut().zip(mem) {
             *dst = src;
         }
@@ -54,99 +87,210 @@ impl State {
 
     /// Step forward one instruction in the logical simulation. This is provided keys are currently
     /// being pressed, to assume they are not, see `State::step_forward`.
-    pub fn step(&mut self, input: Input) {
-        // We want to get the opcode at the program counter and simply match against it to call
-        // a function from the `opcodes` module. The opcode consists of two bytes,
-        // we're interested in each nibble, so 4 values
-        let mut opcode = [0u8; 4];
-        opcode[0] = (self.mem[self.pc as usize + 0] & 0xF0) >> 4;
-        opcode[1] = self.mem[self.pc as usize + 0] & 0x0F;
-        opcode[2] = (self.mem[self.pc as usize + 1] & 0xF0) >> 4;
-        opcode[3] = self.mem[self.pc as usize + 1] & 0x0F;
+    ///
+    /// If the instruction can't be carried out, it is skipped and the fault returned, with the
+    /// program counter pointing past it.
+    pub fn step(&mut self, input: Input) -> Result<(), EmulationFault> {
+        if self.halted {
+            return Ok(());
+        }
 
+        // We want to get the opcode at the program counter, decode it and simply match against
+        // it to call a function from the `opcodes` module. The opcode consists of two bytes.
+        let pc = self.pc;
+        let opcode = self.opcode_at(pc);
+        // There must be room for this instruction and the program counter to move past it
+        if pc as usize + 2 >= State::MEM_SIZE {
+            self.pc = pc.wrapping_add(2);
+            return Err(Fault::PcOutOfRange.at(pc, opcode));
+        }
         self.pc += 2;
-        self.delay = self.delay.saturating_sub(1);
-        self.sound = self.sound.saturating_sub(1);
 
-        match opcode {
+        let instruction = decode(opcode).ok_or_else(|| Fault::UnknownOpcode.at(pc, opcode))?;
+        self.execute(instruction, input)
+            .map_err(|fault| fault.at(pc, opcode))
+    }
+
+    /// Carry out a decoded instruction, with the program counter already pointing past it.
+    fn execute(&mut self, instruction: Instruction, input: Input) -> Result<(), Fault> {
+        use Instruction::*;
+
+        match instruction {
+            // 00Cn - Scroll the display down n lines.
+            ScrollDown(n) => self.display.scroll_down(n as usize),
+            // 00Dn - Scroll the display up n lines.
+            ScrollUp(n) => self.display.scroll_up(n as usize),
             // 00E0 - Clear the display.
-            [0x0, 0x0, 0xE, 0x0] => self.display = [false; 2048],
+            Clear => self.display.clear(),
             // 00EE - Return from a subroutine.
-            [0x0, 0x0, 0xE, 0xE] => opcodes::r#return(self),
+            Return => opcodes::r#return(self)?,
+            // 00FB - Scroll the display right by 4 pixels.
+            ScrollRight => self.display.scroll_right(4),
+            // 00FC - Scroll the display left by 4 pixels.
+            ScrollLeft => self.display.scroll_left(4),
+            // 00FD - Exit the interpreter.
+            Exit => self.halted = true,
+            // 00FE - Disable high resolution mode.
+            LowRes => self.display.set_hires(false),
+            // 00FF - Enable high resolution mode.
+            HighRes => self.display.set_hires(true),
             // 1nnn - Jump to location *nnn*.
-            [0x1, n1, n2, n3] => self.pc = addr(n1, n2, n3),
+            Jump(nnn) => self.pc = nnn,
             // 2nnn - Call subroutine at nnn.
-            [0x2, n1, n2, n3] => opcodes::call(self, addr(n1, n2, n3)),
+            Call(nnn) => opcodes::call(self, nnn)?,
             // 3xkk - Skip next instruction if Vx = kk.
-            [0x3, x, k1, k2] => opcodes::skip_if_equal(self, x, byte(k1, k2)),
+            SkipEqual(x, kk) => opcodes::skip_if_equal(self, x, kk)?,
             // 4xkk - Skip next instruction if Vx != kk.
-            [0x4, x, k1, k2] => opcodes::skip_if_not_equal(self, x, byte(k1, k2)),
+            SkipNotEqual(x, kk) => opcodes::skip_if_not_equal(self, x, kk)?,
             // 5xy0 - Skip next instruction if Vx = Vy.
-            [0x5, x, y, 0x0] => opcodes::skip_reg_equal(self, x, y),
+            SkipRegEqual(x, y) => opcodes::skip_reg_equal(self, x, y)?,
+            // 5xy2 - Store registers Vx through Vy in memory starting at location I.
+            SaveRange(x, y) => opcodes::copy_register_range(self, x, y)?,
+            // 5xy3 - Read registers Vx through Vy from memory starting at location I.
+            LoadRange(x, y) => opcodes::load_register_range(self, x, y)?,
             // 6xkk - Set Vx = kk.
-            [0x6, x, k1, k2] => self.reg_v[x as usize] = byte(k1, k2),
+            Load(x, kk) => self.reg_v[x as usize] = kk,
             // 7xkk - Set Vx = Vx + kk.
-            [0x7, x, k1, k2] => {
-                self.reg_v[x as usize] = self.reg_v[x as usize].wrapping_add(byte(k1, k2))
-            }
+            AddByte(x, kk) => self.reg_v[x as usize] = self.reg_v[x as usize].wrapping_add(kk),
             // 8xy0 - Set Vx = Vy.
-            [0x8, x, y, 0x0] => self.reg_v[x as usize] = self.reg_v[y as usize],
+            Move(x, y) => self.reg_v[x as usize] = self.reg_v[y as usize],
             // 8xy1 - Set Vx = Vx OR Vy.
-            [0x8, x, y, 0x1] => self.reg_v[x as usize] |= self.reg_v[y as usize],
+            Or(x, y) => opcodes::logical(self, x, y, |vx, vy| vx | vy),
             // 8xy2 - Set Vx = Vx AND Vy.
-            [0x8, x, y, 0x2] => self.reg_v[x as usize] &= self.reg_v[y as usize],
+            And(x, y) => opcodes::logical(self, x, y, |vx, vy| vx & vy),
             // 8xy3 - Set Vx = Vx XOR Vy.
-            [0x8, x, y, 0x3] => self.reg_v[x as usize] ^= self.reg_v[y as usize],
+            Xor(x, y) => opcodes::logical(self, x, y, |vx, vy| vx ^ vy),
             // 8xy4 - Set Vx = Vx + Vy, set VF = carry.
-            [0x8, x, y, 0x4] => opcodes::add(self, x, y),
+            Add(x, y) => opcodes::add(self, x, y),
             // 8xy5 - Set Vx = Vx - Vy, set VF = NOT borrow.
-            [0x8, x, y, 0x5] => opcodes::subtract(self, x, y),
+            Sub(x, y) => opcodes::subtract(self, x, y),
             // 8xy6 - Set Vx = Vx SHR 1.
-            [0x8, x, _, 0x6] => opcodes::shift_right(self, x),
+            ShiftRight(x, y) => opcodes::shift_right(self, x, y),
             // 8xy7 - Set Vx = Vy - Vx, set VF = NOT borrow.
-            [0x8, x, y, 0x7] => opcodes::subtract(self, y, x),
+            SubN(x, y) => opcodes::subtract(self, y, x),
             // 8xyE - Set Vx = Vx SHL 1.
-            [0x8, x, _, 0xE] => opcodes::shift_left(self, x),
+            ShiftLeft(x, y) => opcodes::shift_left(self, x, y),
             // 9xy0 - Skip next instruction if Vx != Vy.
-            [0x9, x, y, 0x0] => opcodes::skip_reg_not_equal(self, x, y),
+            SkipRegNotEqual(x, y) => opcodes::skip_reg_not_equal(self, x, y)?,
             // Annn - Set I = nnn.
-            [0xA, n1, n2, n3] => self.reg_i = addr(n1, n2, n3),
+            LoadI(nnn) => self.reg_i = nnn,
             // Bnnn - Jump to location nnn + V0.
-            [0xB, n1, n2, n3] => self.pc = addr(n1, n2, n3) + dbg!(self.reg_v[0x0] as u16),
+            JumpOffset(nnn) => opcodes::jump_offset(self, nnn),
             // Cxkk - Set Vx = random byte AND kk.
-            [0xC, x, k1, k2] => opcodes::random(self, x, byte(k1, k2)),
+            Random(x, kk) => opcodes::random(self, x, kk),
+            // Dxy0 - Display 16x16 sprite starting at memory location I at (Vx, Vy)
+            // Set VF = collision.
+            Draw(x, y, 0x0) => opcodes::draw_large_sprite(self, x, y)?,
             // Dxyn - Display n-byte sprite starting at memory location I at (Vx, Vy)
             // Set VF = collision.
-            [0xD, x, y, n] => opcodes::draw_sprite(self, x, y, n),
+            Draw(x, y, n) => opcodes::draw_sprite(self, x, y, n)?,
             // Ex9E - Skip next instruction if key with the value of Vx is pressed.
-            [0xE, x, 0x9, 0xE] => opcodes::skip_if_pressed(self, input, x),
+            SkipPressed(x) => opcodes::skip_if_pressed(self, input, x)?,
             // ExA1 - Skip next instruction if key with the value of Vx is not pressed.
-            [0xE, x, 0xA, 0x1] => opcodes::skip_if_unpressed(self, input, x),
+            SkipNotPressed(x) => opcodes::skip_if_unpressed(self, input, x)?,
+            // F000 nnnn - Set I = nnnn, the 16-bit address following this instruction.
+            LoadLongI => opcodes::load_long_address(self)?,
+            // Fn01 - Select the drawing planes n.
+            Plane(n) => self.display.select_planes(n),
+            // F002 - Load the audio pattern buffer from memory starting at location I.
+            LoadAudio => opcodes::load_audio_pattern(self)?,
             // Fx07 - Set Vx = delay timer value.
-            [0xF, x, 0x0, 0x7] => self.reg_v[x as usize] = self.delay,
+            GetDelay(x) => self.reg_v[x as usize] = self.delay,
             // Fx0A - Block and wait for a key press, store the value of the key in Vx.
-            [0xF, x, 0x0, 0xA] => opcodes::block_input(self, input, x),
+            WaitKey(x) => opcodes::block_input(self, input, x),
             // Fx15 - Set delay timer = Vx.
-            [0xF, x, 0x1, 0x5] => self.delay = self.reg_v[x as usize],
+            SetDelay(x) => self.delay = self.reg_v[x as usize],
             // Fx18 - Set sound timer = Vx.
-            [0xF, x, 0x1, 0x8] => self.sound = self.reg_v[x as usize],
+            SetSound(x) => self.sound = self.reg_v[x as usize],
             // Fx1E - Set I = I + Vx.
-            [0xF, x, 0x1, 0xE] => self.reg_i += self.reg_v[x as usize] as u16,
+            AddI(x) => self.reg_i = self.reg_i.wrapping_add(self.reg_v[x as usize] as u16),
             // Fx29 - Set I = location of sprite for digit Vx.
-            [0xF, x, 0x2, 0x9] => opcodes::sprite_location(self, x),
+            Font(x) => opcodes::sprite_location(self, x),
+            // Fx30 - Set I = location of big sprite for digit Vx.
+            BigFont(x) => opcodes::big_sprite_location(self, x),
             // Fx33 - Store BCD representation of Vx in memory locations I, I+1, and I+2.
-            [0xF, x, 0x3, 0x3] => opcodes::store_bcd(self, x),
+            Bcd(x) => opcodes::store_bcd(self, x)?,
+            // Fx3A - Set the audio pattern playback pitch = Vx.
+            Pitch(x) => self.pitch = self.reg_v[x as usize],
             // Fx55 - Store registers V0 through Vx in memory starting at location I.
-            [0xF, x, 0x5, 0x5] => opcodes::copy_registers(self, x),
+            Store(x) => opcodes::copy_registers(self, x)?,
             // Fx65 - Read registers V0 through Vx from memory starting at location I.
-            [0xF, x, 0x6, 0x5] => opcodes::load_registers(self, x),
-
-            _ => panic!("Interpreter encountered an unknown opcode: {:?}", opcode),
+            Restore(x) => opcodes::load_registers(self, x)?,
+            // Fx75 - Store registers V0 through Vx in the RPL user flags.
+            SaveFlags(x) => opcodes::save_flags(self, x),
+            // Fx85 - Read registers V0 through Vx from the RPL user flags.
+            LoadFlags(x) => opcodes::load_flags(self, x),
         }
+        Ok(())
+    }
+
+    /// Restart the random number generator from `seed`, so that the program makes the same
+    /// random choices every time it is given the same input.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// The two-byte opcode stored at `addr`, wrapping around the end of memory.
+    pub fn opcode_at(&self, addr: u16) -> u16 {
+        let next = addr.wrapping_add(1);
+        u16::from_be_bytes([self.mem[addr as usize], self.mem[next as usize]])
+    }
+
+    /// The current contents of the screen.
+    pub fn display(&self) -> &Framebuffer {
+        &self.display
+    }
+
+    /// The value of the sound timer, which plays a tone while it is above zero.
+    pub fn sound(&self) -> u8 {
+        self.sound
     }
 
-    pub fn step_forward(&mut self) {
-        self.step([false; 16]);
+    /// Whether the program has exited with `00FD`.
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn step_forward(&mut self) -> Result<(), EmulationFault> {
+        self.step([false; 16])
+    }
+
+    /// Count the delay and sound timers down by one, which should happen 60 times a second
+    /// regardless of how quickly instructions are run.
+    pub fn tick_timers(&mut self) {
+        self.delay = self.delay.saturating_sub(1);
+        self.sound = self.sound.saturating_sub(1);
+    }
+
+    /// Run the instructions in `frame`, with `input` held down throughout, then tick the timers
+    /// as many times as it asks for. The rest of the frame's instructions are skipped if one
+    /// faults, but the timers still tick.
+    pub fn run_frame(&mut self, frame: Frame, input: Input) -> Result<(), EmulationFault> {
+        self.run_frame_until(frame, input, |_| false).map(|_| ())
+    }
+
+    /// Run the instructions in `frame` like `State::run_frame`, but skip the rest of them as soon
+    /// as `done` is true after an instruction, returning whether it was.
+    pub fn run_frame_until(
+        &mut self,
+        frame: Frame,
+        input: Input,
+        mut done: impl FnMut(&State) -> bool,
+    ) -> Result<bool, EmulationFault> {
+        let mut res = Ok(false);
+        for _ in 0..frame.instructions {
+            if self.halted {
+                break;
+            }
+            res = self.step(input).map(|_| done(self));
+            if res != Ok(false) {
+                break;
+            }
+        }
+        for _ in 0..frame.timer_ticks {
+            self.tick_timers();
+        }
+        res
     }
 }
 
@@ -155,7 +299,7 @@ impl State {
 impl Default for State {
     fn default() -> Self {
         let mut res = Self {
-            mem: [0; 4096],
+            mem: [0; State::MEM_SIZE],
             reg_v: [0; 16],
             reg_i: 0,
             sound: 0,
@@ -163,54 +307,267 @@ impl Default for State {
             pc: 0x200,
             stack: [0; 16],
             sp: 0,
-            display: [false; 2048],
+            display: Framebuffer::new(),
+            rpl: [0; 16],
+            halted: false,
+            audio: [0; 16],
+            pitch: 64,
+            quirks: Quirks::default(),
+            rom_hash: fnv1a(&[]),
+            rng: Rng::new(0),
         };
-        // Copy fontset into memory
-        res.mem[00..05].copy_from_slice(&fontset::NUM_1);
-        res.mem[05..10].copy_from_slice(&fontset::NUM_2);
-        res.mem[10..15].copy_from_slice(&fontset::NUM_3);
-        res.mem[15..20].copy_from_slice(&fontset::NUM_4);
-        res.mem[20..25].copy_from_slice(&fontset::NUM_5);
-        res.mem[25..30].copy_from_slice(&fontset::NUM_6);
-        res.mem[30..35].copy_from_slice(&fontset::NUM_7);
-        res.mem[35..40].copy_from_slice(&fontset::NUM_8);
-        res.mem[40..45].copy_from_slice(&fontset::NUM_9);
-        res.mem[45..50].copy_from_slice(&fontset::DIG_A);
-        res.mem[50..55].copy_from_slice(&fontset::DIG_B);
-        res.mem[55..60].copy_from_slice(&fontset::DIG_B);
-        res.mem[60..65].copy_from_slice(&fontset::DIG_C);
-        res.mem[65..70].copy_from_slice(&fontset::DIG_D);
-        res.mem[70..75].copy_from_slice(&fontset::DIG_E);
+        // Copy both fontsets into memory
+        let small = res.mem[fontset::SMALL_ADDR..].chunks_exact_mut(fontset::BYTES);
+        for (dst, src) in small.zip(&fontset::SMALL) {
+            dst.copy_from_slice(src);
+        }
+        let big = res.mem[fontset::BIG_ADDR..].chunks_exact_mut(fontset::BIG_BYTES);
+        for (dst, src) in big.zip(&fontset::BIG) {
+            dst.copy_from_slice(src);
+        }
         res
     }
 }
 
-/// Combine three nibbles into an address.
-fn addr(n1: u8, n2: u8, n3: u8) -> u16 {
-    let (n1, n2, n3) = (n1 as u16, n2 as u16, n3 as u16);
-    (n1 << 8) | (n2 << 4) | n3
-}
-
-/// Combine two nibbles into a byte.
-fn byte(k1: u8, k2: u8) -> u8 {
-    (k1 << 4) | k2
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
-    fn test_addr() {
-        assert_eq!(0x0000, addr(0x0, 0x0, 0x0));
-        assert_eq!(0x0123, addr(0x1, 0x2, 0x3));
-        assert_eq!(0x0FFF, addr(0xF, 0xF, 0xF));
+    fn test_shift_quirk() {
+        // 8016 - V0 = V1 SHR 1
+        let rom = [0x80, 0x16];
+        let mut cpu = State::new(&rom, Quirks::default());
+        cpu.reg_v[0x0] = 0b0110;
+        cpu.reg_v[0x1] = 0b0011;
+        cpu.step_forward().unwrap();
+        assert_eq!(0b0011, cpu.reg_v[0x0]);
+        assert_eq!(0, cpu.reg_v[0xF]);
+
+        let mut cpu = State::new(&rom, Quirks::vip());
+        cpu.reg_v[0x0] = 0b0110;
+        cpu.reg_v[0x1] = 0b0011;
+        cpu.step_forward().unwrap();
+        assert_eq!(0b0001, cpu.reg_v[0x0]);
+        assert_eq!(1, cpu.reg_v[0xF]);
     }
 
     #[test]
-    fn test_byte() {
-        assert_eq!(0x00, byte(0x0, 0x0));
-        assert_eq!(0x12, byte(0x1, 0x2));
-        assert_eq!(0xFF, byte(0xF, 0xF));
+    fn test_load_store_quirk() {
+        // F255 - Store V0 through V2 at I
+        let rom = [0xF2, 0x55];
+        let mut cpu = State::new(&rom, Quirks::default());
+        cpu.reg_i = 0x300;
+        cpu.step_forward().unwrap();
+        assert_eq!(0x300, cpu.reg_i);
+
+        let mut cpu = State::new(&rom, Quirks::vip());
+        cpu.reg_i = 0x300;
+        cpu.step_forward().unwrap();
+        assert_eq!(0x303, cpu.reg_i);
+    }
+
+    #[test]
+    fn test_jump_quirk() {
+        // B234 - Jump to 0x234 + V0 (or V2 on SUPER-CHIP)
+        let rom = [0xB2, 0x34];
+        let mut cpu = State::new(&rom, Quirks::default());
+        cpu.reg_v[0x0] = 0x1;
+        cpu.reg_v[0x2] = 0x2;
+        cpu.step_forward().unwrap();
+        assert_eq!(0x235, cpu.pc);
+
+        let mut cpu = State::new(&rom, Quirks::schip());
+        cpu.reg_v[0x0] = 0x1;
+        cpu.reg_v[0x2] = 0x2;
+        cpu.step_forward().unwrap();
+        assert_eq!(0x236, cpu.pc);
+    }
+
+    #[test]
+    fn test_clip_quirk() {
+        // D011 - Draw the 1-byte sprite at I at (V0, V1)
+        let rom = [0xD0, 0x11, 0xFF];
+        let mut cpu = State::new(&rom, Quirks::default());
+        cpu.reg_i = 0x202;
+        cpu.reg_v[0x0] = 60;
+        cpu.step_forward().unwrap();
+        assert!((0..4).all(|x| cpu.display.lit(x, 0)));
+
+        let mut cpu = State::new(&rom, Quirks::schip());
+        cpu.reg_i = 0x202;
+        cpu.reg_v[0x0] = 60;
+        cpu.step_forward().unwrap();
+        assert!((0..4).all(|x| !cpu.display.lit(x, 0)));
+        assert!((60..64).all(|x| cpu.display.lit(x, 0)));
+    }
+
+    #[test]
+    fn test_timers() {
+        // F015 - Set the delay timer to V0, then spin until it runs out
+        // F107 - V1 = delay timer, 3100 - skip if V1 = 0, 1202 - loop, 00FD - exit
+        let rom = [0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x02, 0x00, 0xFD];
+        let mut cpu = State::new(&rom, Quirks::default());
+        cpu.reg_v[0x0] = 2;
+        let frame = Frame {
+            instructions: 100,
+            timer_ticks: 1,
+        };
+        cpu.run_frame(frame, [false; 16]).unwrap();
+        // Running many instructions only ticks the timer once
+        assert_eq!((1, 0x202), (cpu.delay, cpu.pc));
+        cpu.run_frame(frame, [false; 16]).unwrap();
+        cpu.run_frame(frame, [false; 16]).unwrap();
+        assert_eq!((0, 0x20A), (cpu.delay, cpu.pc));
+        assert!(cpu.halted);
+    }
+
+    #[test]
+    fn test_fontset() {
+        // F029 - Set I to the sprite for V0, then F130 - Set I to the big sprite for V1
+        let rom = [0xF0, 0x29, 0xF1, 0x30];
+        let mut cpu = State::new(&rom, Quirks::default());
+        cpu.reg_v[0x0] = 0xB;
+        cpu.reg_v[0x1] = 0x7;
+        cpu.step_forward().unwrap();
+        let i = cpu.reg_i as usize;
+        assert_eq!(fontset::DIG_B, cpu.mem[i..i + fontset::BYTES]);
+        cpu.step_forward().unwrap();
+        let i = cpu.reg_i as usize;
+        assert_eq!(fontset::BIG[0x7], cpu.mem[i..i + fontset::BIG_BYTES]);
+    }
+
+    #[test]
+    fn test_font_glyphs() {
+        // The sprites for 0 to F, from Cowgod's Chip-8 Technical Reference
+        let glyphs: [[u8; 5]; 16] = [
+            [0xF0, 0x90, 0x90, 0x90, 0xF0],
+            [0x20, 0x60, 0x20, 0x20, 0x70],
+            [0xF0, 0x10, 0xF0, 0x80, 0xF0],
+            [0xF0, 0x10, 0xF0, 0x10, 0xF0],
+            [0x90, 0x90, 0xF0, 0x10, 0x10],
+            [0xF0, 0x80, 0xF0, 0x10, 0xF0],
+            [0xF0, 0x80, 0xF0, 0x90, 0xF0],
+            [0xF0, 0x10, 0x20, 0x40, 0x40],
+            [0xF0, 0x90, 0xF0, 0x90, 0xF0],
+            [0xF0, 0x90, 0xF0, 0x10, 0xF0],
+            [0xF0, 0x90, 0xF0, 0x90, 0x90],
+            [0xE0, 0x90, 0xE0, 0x90, 0xE0],
+            [0xF0, 0x80, 0x80, 0x80, 0xF0],
+            [0xE0, 0x90, 0x90, 0x90, 0xE0],
+            [0xF0, 0x80, 0xF0, 0x80, 0xF0],
+            [0xF0, 0x80, 0xF0, 0x80, 0x80],
+        ];
+        // F029 - Set I to the sprite for V0
+        let rom = [0xF0, 0x29];
+        for (digit, glyph) in glyphs.iter().enumerate() {
+            let mut cpu = State::new(&rom, Quirks::default());
+            cpu.reg_v[0x0] = digit as u8;
+            cpu.step_forward().unwrap();
+            let i = cpu.reg_i as usize;
+            assert_eq!(glyph, &cpu.mem[i..i + 5], "glyph for {:X}", digit);
+        }
+    }
+
+    #[test]
+    fn test_hires_sprite() {
+        // 00FF - Enable hi-res, then D010 - Draw a 16x16 sprite at (V0, V1)
+        let mut rom = vec![0x00, 0xFF, 0xD0, 0x10];
+        rom.extend_from_slice(&[0xFF; 32]);
+        let mut cpu = State::new(&rom, Quirks::schip());
+        cpu.reg_i = 0x204;
+        cpu.reg_v[0x0] = 120;
+        cpu.step_forward().unwrap();
+        cpu.step_forward().unwrap();
+        assert_eq!(128, cpu.display.width());
+        assert!((120..128).all(|x| (0..16).all(|y| cpu.display.lit(x, y))));
+        assert!(!cpu.display.lit(0, 0));
+        assert!(!cpu.display.lit(120, 16));
+    }
+
+    #[test]
+    fn test_long_address() {
+        // 3000 - Skip if V0 = 0, over F000 1234, then F000 4321 - Set I = 0x4321
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0xF0, 0x00, 0x43, 0x21];
+        let mut cpu = State::new(&rom, Quirks::xochip());
+        cpu.step_forward().unwrap();
+        assert_eq!(0x206, cpu.pc);
+        cpu.step_forward().unwrap();
+        assert_eq!(0x20A, cpu.pc);
+        assert_eq!(0x4321, cpu.reg_i);
+    }
+
+    #[test]
+    fn test_register_range() {
+        // 5312 - Store V3 through V1 at I, then 5013 - Read V0 through V1 from I
+        let rom = [0x53, 0x12, 0x50, 0x13];
+        let mut cpu = State::new(&rom, Quirks::xochip());
+        cpu.reg_i = 0x300;
+        cpu.reg_v[0x1..=0x3].copy_from_slice(&[1, 2, 3]);
+        cpu.step_forward().unwrap();
+        assert_eq!([3, 2, 1], cpu.mem[0x300..0x303]);
+        cpu.step_forward().unwrap();
+        assert_eq!([3, 2], cpu.reg_v[0x0..=0x1]);
+        assert_eq!(0x300, cpu.reg_i);
+    }
+
+    #[test]
+    fn test_planes() {
+        // F301 - Select both planes, then D011 - Draw a 1-byte sprite to each plane at (V0, V1)
+        let rom = [0xF3, 0x01, 0xD0, 0x11, 0xF0, 0x3C];
+        let mut cpu = State::new(&rom, Quirks::xochip());
+        cpu.reg_i = 0x204;
+        cpu.step_forward().unwrap();
+        cpu.step_forward().unwrap();
+        assert_eq!(0b01, cpu.display.get(0, 0));
+        assert_eq!(0b11, cpu.display.get(2, 0));
+        assert_eq!(0b10, cpu.display.get(5, 0));
+        assert_eq!(0b00, cpu.display.get(6, 0));
+        assert_eq!(0, cpu.reg_v[0xF]);
+    }
+
+    #[test]
+    fn test_faults() {
+        // FFFF - Unknown, then 00EE - Return with nothing on the stack
+        let mut cpu = State::new(&[0xFF, 0xFF, 0x00, 0xEE], Quirks::default());
+        let fault = EmulationFault::UnknownOpcode {
+            pc: 0x200,
+            opcode: 0xFFFF,
+        };
+        assert_eq!(Err(fault), cpu.step_forward());
+        assert_eq!(0x202, cpu.pc);
+        assert_eq!(0x202, cpu.step_forward().unwrap_err().pc());
+
+        // 2200 - Call itself until the stack runs out
+        let mut cpu = State::new(&[0x22, 0x00], Quirks::default());
+        for _ in 0..16 {
+            cpu.step_forward().unwrap();
+        }
+        let fault = cpu.step_forward().unwrap_err();
+        assert_eq!(
+            EmulationFault::StackOverflow {
+                pc: 0x200,
+                opcode: 0x2200
+            },
+            fault
+        );
+        assert_eq!(16, cpu.sp);
+
+        // F255 - Store V0 through V2 at I, past the end of memory
+        let mut cpu = State::new(&[0xF2, 0x55], Quirks::vip());
+        cpu.reg_i = 0xFFFE;
+        let fault = EmulationFault::MemoryOutOfRange {
+            pc: 0x200,
+            opcode: 0xF255,
+            addr: 0xFFFE,
+            len: 3,
+        };
+        assert_eq!(Err(fault), cpu.step_forward());
+        assert_eq!(0xFFFE, cpu.reg_i);
+
+        // Running off the end of memory
+        let mut cpu = State::new(&[], Quirks::default());
+        cpu.pc = 0xFFFE;
+        assert_eq!(0xFFFE, cpu.step_forward().unwrap_err().pc());
     }
 }
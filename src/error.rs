@@ -1,3 +1,5 @@
+use crate::emulator::fault::EmulationFault;
+#[cfg(feature = "frontend")]
 use crossterm::ErrorKind as TermErrorKind;
 use std::io::Error as IoError;
 use thiserror::*;
@@ -5,12 +7,85 @@ use thiserror::*;
 /// They should be bubbled up and then handled in main.rs
 #[derive(Debug, Error)]
 pub enum ErrorKind {
+    #[cfg(feature = "frontend")]
     #[error("Error in terminal: {0:?}")]
     TermErr(#[from] TermErrorKind),
     #[error("Error with files: {0:?}")]
     IoErr(#[from] IoError),
+    #[error("ROM is empty")]
+    RomEmpty,
+    #[error("ROM is {size} bytes, but only {max} fit in memory")]
+    RomTooLarge { size: usize, max: usize },
+    #[error("Could not read ROM: {0}")]
+    RomErr(String),
+    #[error("Invalid Octo cartridge: {0}")]
+    CartridgeErr(String),
+    #[error("Could not compile the Octo cartridge's program, on line {0}")]
+    OctoErr(#[from] AsmError),
+    #[error("File is not a save state, or is corrupt")]
+    NotASnapshot,
+    #[error("Save state is format version {found}, but this build only reads version {expected}")]
+    SnapshotVersion { found: u16, expected: u16 },
+    #[error("Save state was made with a different ROM")]
+    SnapshotRom,
+    #[error("File is not a movie, or is corrupt")]
+    NotAMovie,
+    #[error("Movie is format version {found}, but this build only reads version {expected}")]
+    MovieVersion { found: u16, expected: u16 },
+    #[error("Movie was recorded with a different ROM")]
+    MovieRom,
+    #[error("Replay ran for {found} frames, but the movie has {expected}")]
+    MovieLength { found: u64, expected: u64 },
+    #[error("Replay did not match the movie, ending with state hash {found:016x} instead of {expected:016x}")]
+    MovieDesync { found: u64, expected: u64 },
+    #[error("The program faulted: {0}")]
+    Fault(#[from] EmulationFault),
+    #[error("Assembly failed with {} errors", .0.len())]
+    AsmErr(Vec<AsmError>),
+    #[error("Invalid input script: {0}")]
+    ScriptErr(#[from] ScriptError),
+    #[error("Invalid keymap: {0}")]
+    KeymapErr(#[from] KeymapError),
+    #[error("Error with audio output: {0}")]
+    AudioErr(String),
+    #[error("Invalid display: {0}")]
+    DisplayErr(#[from] DisplayError),
+    #[error("Error writing image: {0}")]
+    ImageErr(String),
 }
 
+/// A problem with one line of assembly source.
+#[derive(Debug, Error)]
+#[error("{line}: {message}")]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Error)]
+#[error("{:?}", _0)]
+pub struct DisplayError(pub String);
+
+#[derive(Debug, Error)]
+#[error("{:?}", _0)]
+pub struct QuirksError(pub String);
+
+#[derive(Debug, Error)]
+#[error("{:?}", _0)]
+pub struct FaultPolicyError(pub String);
+
+#[derive(Debug, Error)]
+#[error("{:?}", _0)]
+pub struct ScriptError(pub String);
+
+#[derive(Debug, Error)]
+#[error("{:?}", _0)]
+pub struct KeymapError(pub String);
+
+#[derive(Debug, Error)]
+#[error("{:?}", _0)]
+pub struct AudioKindError(pub String);
+
 #[derive(Debug, Error)]
 #[error("{:?}", _0)]
-pub struct DisplayKindError(pub String);
+pub struct WaveformError(pub String);
@@ -1,47 +1,364 @@
-use crate::{
-    display::{self, Render},
-    emulator::{input::*, State},
+use crate::Opt;
+use chip_again::{
+    audio::{Sound, Tone},
+    debugger::{self, Debugger},
+    display::{record, screenshot, DisplayOptions, Recorder, Registry, Render},
+    emulator::{
+        fault::FaultPolicy,
+        input::{self, HostKey, Keymap, Keypad},
+        movie::Movie,
+        rewind::Rewind,
+        rom,
+        scheduler::Scheduler,
+        snapshot, State,
+    },
     error::ErrorKind,
-    Opt,
 };
-use crossterm::event::{self, Event::Key, KeyCode};
-use std::{fs::File, io::prelude::*, time::Duration};
+use crossterm::{
+    event::{self, Event, KeyCode},
+    terminal,
+};
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
 
 /// Main loop, we want to take parsed command line input from main and run the emulator in a loop.
 /// It is also our responsibility to handle input, and pass the display state to an instance of
-/// `Render`, which we do here.
-pub fn main_loop(options: Opt) -> Result<(), ErrorKind> {
-    // ROM size, 2048 bytes of memory, 0x200 of which reserved for interpreter.
-    // const ROM_SIZE: usize = 2048 - 0x200;
-
-    let mut buf = Vec::with_capacity(2048 - 0x200);
-    let mut handle = File::open(options.rom_path)?;
-    handle.read_to_end(&mut buf)?;
-
-    let mut cpu = State::new(&buf);
-    // TODO: Allow changing, choosing renderer.
-    let mut disp = Box::new(display::TerminalRenderer::new()?);
-    // let mut disp = Box::new(display::debug::DebugRenderer([true; 2048]));
-
-    loop {
-        let mut input: Input = Default::default();
-        // TODO: Refactor this code out into emulator/input.rs
-        if event::poll(Duration::from_millis(1000 / options.fps))? {
-            if let Key(key_event) = event::read()? {
-                for (idx, &key) in KEYMAP.iter().enumerate() {
-                    if let KeyCode::Char(pressed) = key_event.code {
-                        if key == pressed {
-                            input[idx] = true;
+/// `Render`, which we do here. The renderer is looked up by name in `registry`.
+///
+/// Each frame runs as many instructions as the `Scheduler` asks for, so the speed of the program
+/// depends on the instructions-per-second option rather than the frame rate. Key presses are
+/// collected until the frame is due, and the keys held at that point apply to every instruction
+/// in it.
+///
+/// The function keys F1 to F4 save the emulator's state to the numbered slots 1 to 4, and F5 to
+/// F8 load the state back from those slots. Holding backspace rewinds the emulator, one frame at
+/// a time, through the last few seconds of play. Like the keypad, backspace counts as held for
+/// `--hold` after the terminal last reported it, so rewinding carries on smoothly between the
+/// terminal's key repeats. F9 saves a screenshot, as does reaching the
+/// frame given by `--screenshot-at-frame`. F10 starts and stops recording a video of everything
+/// the renderer draws, which `--record` starts from the beginning. Escape quits. In debugger
+/// mode, keys used by the `Debugger` are not passed on to the program, and the renderer is kept
+/// clear of the debugger's panel.
+///
+/// When the program faults, `--on-fault` chooses whether to stop and report it, skip the faulting
+/// instruction, or pause in the debugger. Pausing would spoil a movie, so while one is recording
+/// or replaying, `debug` stops the emulator just as `halt` does. `ignore` still skips the faulting
+/// instruction, which happens the same way on every run.
+///
+/// With `--record-movie`, the input of every frame is recorded to a `Movie`, which `--replay`
+/// plays back in place of the keyboard. Rewinding and loading save states would make the run
+/// impossible to repeat, so they are disabled while recording or replaying.
+pub fn main_loop(options: Opt, registry: &Registry) -> Result<(), ErrorKind> {
+    let rom_path = options
+        .rom_path
+        .as_ref()
+        .expect("a ROM is required when there is no subcommand");
+    let rom = rom::load_file(rom_path)?;
+
+    let mut cpu = State::new(&rom, options.quirks);
+    if let Some(path) = &options.load_state {
+        snapshot::restore_file(&mut cpu, path)?;
+    }
+    let replay = options.replay.as_ref().map(Movie::load_file).transpose()?;
+    let (ips, fps) = match &replay {
+        Some(movie) => (movie.ips, movie.fps),
+        None => (options.ips, options.fps),
+    };
+    if let Some(movie) = &replay {
+        movie.prepare(&mut cpu)?;
+    } else if let Some(seed) = options.seed {
+        cpu.seed(seed);
+    }
+    let mut movie = match &options.record_movie {
+        Some(_) => {
+            let seed = options.seed.unwrap_or_else(rand::random);
+            cpu.seed(seed);
+            Some(Movie::new(&cpu, seed, ips, fps))
+        }
+        None => None,
+    };
+    // Whether the run must be repeatable, for a movie
+    let repeatable = replay.is_some() || movie.is_some();
+    let display_options = DisplayOptions::new(&options.display_options);
+    let disp = registry.build(&options.display, &display_options)?;
+    let mut disp = Recorder::new(disp, fps, options.record_scale, options.record_theme);
+    if let Some(path) = &options.record {
+        disp.start(path)?;
+    }
+    let mut history = Rewind::new((options.rewind * fps) as usize);
+    history.push(&cpu);
+    let mut debugger = None;
+    if options.debug {
+        open_debugger(&mut debugger, &mut disp)?;
+    }
+    let keymap = load_keymap(&options, rom_path)?;
+    let hold = Duration::from_millis(options.hold);
+    let mut keypad = Keypad::new(keymap, hold);
+    // When backspace was last pressed, to rewind while it's held
+    let mut rewind_pressed_at: Option<Instant> = None;
+    let tone = Tone {
+        frequency: options.frequency,
+        waveform: options.waveform,
+        volume: options.volume,
+    };
+    let wav_path = match &options.wav_path {
+        Some(path) => path.clone(),
+        None => rom_path.with_extension("wav"),
+    };
+    let mut audio = options.audio.to_backend(tone, &wav_path)?;
+    let mut scheduler = Scheduler::new(ips, fps);
+    let mut deadline = Instant::now();
+    // Number of frames shown so far
+    let mut frames = 0;
+    // The fault which stopped the emulator, if any
+    let mut halted_by = None;
+
+    // Whether the user quit, rather than the program or the movie coming to an end
+    let quit = loop {
+        let mut quitting = false;
+        deadline += scheduler.frame_duration();
+        // If we've fallen far behind, for example while suspended, don't try to catch up
+        let now = Instant::now();
+        if deadline + Duration::from_secs(1) < now {
+            deadline = now;
+        }
+
+        while event::poll(deadline.saturating_duration_since(Instant::now()))? {
+            match event::read()? {
+                Event::Resize(columns, rows) => {
+                    disp.resize(display_columns(columns, debugger.is_some()), rows)?
+                }
+                Event::Key(key_event)
+                    if debugger
+                        .as_mut()
+                        .is_some_and(|dbg| dbg.handle_key(key_event, &cpu)) => {}
+                Event::Key(key_event) => {
+                    if let Some(key) = host_key(key_event.code) {
+                        keypad.press(key, Instant::now());
+                    }
+                    match key_event.code {
+                        KeyCode::Esc => quitting = true,
+                        KeyCode::Backspace | KeyCode::F(5..=8) if repeatable => {
+                            log::warn!("Rewinding and loading are disabled for movies");
                         }
+                        KeyCode::Backspace => rewind_pressed_at = Some(Instant::now()),
+                        KeyCode::F(n @ 1..=4) => {
+                            let path = snapshot::slot_path(rom_path, n);
+                            if let Err(e) = snapshot::save_file(&cpu, path) {
+                                log::error!("Could not save to slot {}: {}", n, e);
+                            }
+                        }
+                        KeyCode::F(n @ 5..=8) => {
+                            let path = snapshot::slot_path(rom_path, n - 4);
+                            if let Err(e) = snapshot::restore_file(&mut cpu, path) {
+                                log::error!("Could not load from slot {}: {}", n - 4, e);
+                            }
+                        }
+                        KeyCode::F(9) => {
+                            if let Err(e) = save_screenshot(&options, rom_path, &cpu, frames) {
+                                log::error!("Could not save a screenshot: {}", e);
+                            }
+                        }
+                        KeyCode::F(10) if disp.recording() => {
+                            if let Err(e) = disp.stop() {
+                                log::error!("Could not finish the recording: {}", e);
+                            }
+                        }
+                        KeyCode::F(10) => {
+                            let path = match &options.record {
+                                Some(path) => path.clone(),
+                                None => record::default_path(rom_path, frames),
+                            };
+                            if let Err(e) = disp.start(&path) {
+                                log::error!("Could not start recording: {}", e);
+                            }
+                        }
+                        _ => {}
                     }
                 }
-                if let KeyCode::Char('0') = key_event.code {
-                    unimplemented!()
+                _ => {}
+            }
+        }
+
+        if quitting {
+            break true;
+        }
+        let input = match &replay {
+            Some(replay) => match replay.inputs.get(frames as usize) {
+                Some(&input) => input,
+                None => break false,
+            },
+            None => keypad.held(Instant::now()),
+        };
+        if let Some(movie) = movie.as_mut() {
+            movie.push(input);
+        }
+        let frame = scheduler.next_frame();
+        let rewinding = rewind_pressed_at
+            .is_some_and(|at| Instant::now().saturating_duration_since(at) < hold);
+        // Whether the program moved forwards this frame, which it must for the tone to play
+        let mut advanced = true;
+        let result = if rewinding {
+            history.pop(&mut cpu);
+            advanced = false;
+            Ok(())
+        } else if let Some(dbg) = debugger.as_mut() {
+            // Timers only run while the debugger does, so single-stepping leaves them alone
+            let running = !dbg.paused();
+            let mut ran = false;
+            let mut result = Ok(());
+            for _ in 0..frame.instructions {
+                match dbg.step(&mut cpu, input) {
+                    Ok(true) => ran = true,
+                    Ok(false) => break,
+                    Err(fault) => {
+                        ran = true;
+                        result = Err(fault);
+                        break;
+                    }
+                }
+                if dbg.paused() {
+                    break;
+                }
+            }
+            if running {
+                for _ in 0..frame.timer_ticks {
+                    cpu.tick_timers();
                 }
             }
+            if ran {
+                history.push(&cpu);
+            }
+            advanced = ran;
+            result
+        } else {
+            let result = cpu.run_frame(frame, input);
+            history.push(&cpu);
+            result
+        };
+        if let Err(fault) = result {
+            match options.on_fault {
+                FaultPolicy::Ignore => log::warn!("Ignoring a fault: {}", fault),
+                FaultPolicy::Debug if !repeatable => {
+                    open_debugger(&mut debugger, &mut disp)?.fault(fault)
+                }
+                _ => {
+                    halted_by = Some(fault);
+                    break true;
+                }
+            }
+        }
+        let sound = if advanced {
+            Sound::of(&cpu)
+        } else {
+            Sound::Silent
+        };
+        audio.update(sound, scheduler.frame_duration())?;
+        disp.render(cpu.display())?;
+        frames += 1;
+        if options.screenshot_at_frame == Some(frames) {
+            save_screenshot(&options, rom_path, &cpu, frames)?;
         }
+        if let Some(dbg) = debugger.as_ref() {
+            dbg.draw(&cpu)?;
+            if dbg.quit_requested() {
+                break true;
+            }
+        }
+
+        if cpu.halted() {
+            break false;
+        }
+    };
+
+    if let (Some(replay), false) = (&replay, quit) {
+        replay.verify(&cpu, frames)?;
+    }
+    if let (Some(movie), Some(path)) = (movie.as_mut(), &options.record_movie) {
+        movie.finish(&cpu);
+        movie.save_file(path)?;
+    }
+    match halted_by {
+        Some(fault) => Err(fault.into()),
+        None => Ok(()),
+    }
+}
 
-        cpu.step(input);
-        disp.render(cpu.display)?;
+/// Open the debugger beside the display, if it isn't open already.
+fn open_debugger<'a>(
+    debugger: &'a mut Option<Debugger>,
+    disp: &mut impl Render,
+) -> Result<&'a mut Debugger, ErrorKind> {
+    if debugger.is_none() {
+        let (columns, rows) = terminal::size()?;
+        disp.resize(display_columns(columns, true), rows)?;
     }
+    Ok(debugger.get_or_insert_with(Debugger::new))
+}
+
+/// Build the keymap from the defaults, then the keymap config file, then the `--keymap` option.
+fn load_keymap(options: &Opt, rom_path: &Path) -> Result<Keymap, ErrorKind> {
+    let mut keymap = Keymap::default();
+    let config_path = match &options.keymap_file {
+        Some(path) => Some(path.clone()),
+        // The default config file is optional
+        None => input::default_config_path().filter(|path| path.exists()),
+    };
+    if let Some(path) = config_path {
+        let rom_name = rom_path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        keymap.apply_config(&fs::read_to_string(path)?, &rom_name)?;
+    }
+    if let Some(assignments) = &options.keymap {
+        keymap.apply(assignments)?;
+    }
+    Ok(keymap)
+}
+
+/// Save a screenshot of the display on frame `frame`, as the screenshot options say.
+fn save_screenshot(
+    options: &Opt,
+    rom_path: &Path,
+    cpu: &State,
+    frame: u64,
+) -> Result<(), ErrorKind> {
+    let path = match &options.screenshot_path {
+        Some(path) => path.clone(),
+        None => screenshot::default_path(rom_path, frame),
+    };
+    screenshot::save(
+        &path,
+        cpu.display(),
+        options.screenshot_scale,
+        options.screenshot_theme,
+    )
+}
+
+/// How many columns of a terminal `columns` wide the renderer may draw to, leaving room for the
+/// debugger's panel if `debugging`.
+fn display_columns(columns: u16, debugging: bool) -> u16 {
+    if debugging {
+        columns.saturating_sub(debugger::PANEL_WIDTH)
+    } else {
+        columns
+    }
+}
+
+/// The key which a terminal key code can be mapped from, if any.
+fn host_key(code: KeyCode) -> Option<HostKey> {
+    Some(match code {
+        KeyCode::Char(c) => HostKey::Char(c.to_ascii_lowercase()),
+        KeyCode::Enter => HostKey::Enter,
+        KeyCode::Tab => HostKey::Tab,
+        KeyCode::Up => HostKey::Up,
+        KeyCode::Down => HostKey::Down,
+        KeyCode::Left => HostKey::Left,
+        KeyCode::Right => HostKey::Right,
+        _ => return None,
+    })
 }
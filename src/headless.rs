@@ -0,0 +1,124 @@
+use crate::Opt;
+use chip_again::{
+    display::screenshot,
+    emulator::{fault::FaultPolicy, rom, script::Script, snapshot, State},
+    error::ErrorKind,
+    Emulator,
+};
+use itertools::Itertools;
+use std::{
+    fs::{self, File},
+    io::{self, stdout, BufWriter, Write},
+    path::Path,
+};
+
+/// Run the ROM without a terminal, for scripts and CI, as `--headless` asks.
+///
+/// The program runs as fast as it can until `--frames` frames have been run, the program counter
+/// reaches `--until-pc`, or the program exits, with the keys held on each frame taken from the
+/// `--input` script. Command line parsing makes sure at least one of `--frames` and `--until-pc`
+/// is given, so the run ends even if the program never exits. Nothing is run if the program
+/// counter starts at `--until-pc`, such as when a save state was made there. Then the final
+/// screen is written as text, and a report of the registers and a hash of the whole state follows
+/// it. Each goes to stdout unless `--screen-out` or `--report-out` give a file instead, and the
+/// screen can also be saved as an image.
+///
+/// Unless `--on-fault ignore` is given, a fault stops the run, and is returned after the results
+/// are written so the process exits unsuccessfully.
+pub fn run(options: &Opt, rom_path: &Path) -> Result<(), ErrorKind> {
+    let mut state = State::new(&rom::load_file(rom_path)?, options.quirks);
+    if let Some(path) = &options.load_state {
+        snapshot::restore_file(&mut state, path)?;
+    }
+    let mut emulator = Emulator::from_state(state);
+    emulator.set_speed(options.ips, options.fps);
+    if let Some(seed) = options.seed {
+        emulator.seed(seed);
+    }
+    let script: Script = match &options.input {
+        Some(path) => fs::read_to_string(path)?.parse()?,
+        None => Script::default(),
+    };
+
+    let mut fault = None;
+    while !emulator.halted()
+        && options.frames.is_none_or(|n| emulator.frames() < n)
+        && options.until_pc != Some(emulator.pc())
+    {
+        emulator.set_keys(script.keys(emulator.frames()));
+        let result = match options.until_pc {
+            Some(pc) => emulator.run_frame_until_pc(pc),
+            None => emulator.run_frame().map(|_| false),
+        };
+        match result {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(e) if options.on_fault == FaultPolicy::Ignore => {
+                log::warn!("Ignoring a fault: {}", e)
+            }
+            Err(e) => {
+                fault = Some(e);
+                break;
+            }
+        }
+    }
+
+    let stdout = stdout();
+    let mut out = stdout.lock();
+    match &options.screen_out {
+        Some(path) if is_image(path) => screenshot::save(
+            path,
+            emulator.framebuffer(),
+            options.screenshot_scale,
+            options.screenshot_theme,
+        )?,
+        Some(path) => fs::write(path, emulator.framebuffer().to_string())?,
+        None => writeln!(out, "{}", emulator.framebuffer())?,
+    }
+    let fault_text = fault.map_or_else(|| String::from("none"), |e| e.to_string());
+    match &options.report_out {
+        Some(path) => {
+            let mut w = BufWriter::new(File::create(path)?);
+            write_report(&mut w, &emulator, &fault_text)?;
+            w.flush()?;
+        }
+        None => write_report(&mut out, &emulator, &fault_text)?,
+    }
+
+    match fault {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// Whether the screen should be saved to `path` as an image, rather than as text.
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png") || ext.eq_ignore_ascii_case("ppm"))
+}
+
+/// Write the registers, timers and state hash of `emulator` as `key: value` lines, along with
+/// `fault`, a description of the fault which stopped it.
+fn write_report(w: &mut impl Write, emulator: &Emulator, fault: &str) -> io::Result<()> {
+    writeln!(w, "frames: {}", emulator.frames())?;
+    writeln!(w, "pc: {:04X}", emulator.pc())?;
+    writeln!(w, "i: {:04X}", emulator.i())?;
+    let registers = emulator.registers();
+    writeln!(
+        w,
+        "v: {}",
+        registers.iter().map(|v| format!("{:02X}", v)).join(" ")
+    )?;
+    let stack: String = emulator
+        .stack()
+        .iter()
+        .map(|addr| format!(" {:04X}", addr))
+        .collect();
+    writeln!(w, "stack:{}", stack)?;
+    writeln!(w, "delay: {:02X}", emulator.delay_timer())?;
+    writeln!(w, "sound: {:02X}", emulator.sound_timer())?;
+    writeln!(w, "halted: {}", emulator.halted())?;
+    writeln!(w, "fault: {}", fault)?;
+    writeln!(w, "hash: {:016x}", emulator.hash())?;
+    Ok(())
+}
@@ -0,0 +1,28 @@
+//! The core of a CHIP-8, SUPER-CHIP and XO-CHIP emulator, along with an assembler and
+//! disassembler. `Emulator` runs a program without any opinion on how it is shown or played.
+//!
+//! The terminal frontend, with its renderers and debugger, is only built with the `frontend`
+//! feature, which is on by default.
+
+/// Assembler, which turns mnemonics into ROMs.
+pub mod asm;
+/// Includes the `Audio` trait and several backends.
+pub mod audio;
+/// Interactive debugger with breakpoints and single-stepping.
+#[cfg(feature = "frontend")]
+pub mod debugger;
+/// Disassembler, which turns ROMs back into readable mnemonics.
+pub mod disasm;
+/// Includes the `Render` trait and several implementors.
+#[cfg(feature = "frontend")]
+pub mod display;
+/// Main emulator logic, includes emulated opcodes.
+pub mod emulator;
+/// Error handling and ErrorKind enum
+pub mod error;
+/// Compiler for Octo programs, and reader for the Octo cartridges they're shared in.
+pub mod octo;
+/// Utility and helpful functions.
+pub mod utils;
+
+pub use emulator::Emulator;
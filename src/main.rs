@@ -1,43 +1,298 @@
-/// Includes the `Render` trait and several implementors.
-pub mod display;
-/// Main emulator logic, includes emulated opcodes.
-pub mod emulator;
-/// Error handling and ErrorKind enum
-pub mod error;
 /// Main program loop and input handling.
 pub mod exec;
-/// Utility and helpful functions.
-pub mod utils;
+/// Running without a terminal, for scripts and CI.
+pub mod headless;
 
-use display::DisplayKind;
-use env_logger;
-use std::path::PathBuf;
-use structopt::StructOpt;
+use chip_again::{
+    asm,
+    audio::{AudioKind, Waveform},
+    disasm,
+    display::{DisplayOption, Registry, Theme},
+    emulator::{fault::FaultPolicy, Quirks},
+    error::ErrorKind,
+};
+use std::{io::stdout, path::PathBuf};
+use structopt::{clap, StructOpt};
 
-// TODO: Add a display selector?
 #[derive(StructOpt, Debug)]
 #[structopt(
     name = "chip-again",
-    about = "Another CHIP-8 emulator, for the terminal, written with Rust."
+    about = "Another CHIP-8 emulator, for the terminal, written with Rust.",
+    // What stops a --headless run, as a program which never exits would otherwise run forever
+    group = clap::ArgGroup::with_name("headless-limit").multiple(true)
 )]
 pub struct Opt {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+    // Always present unless a subcommand was given.
     #[structopt(name = "rom", help = "Path to a chip8 compatible ROM file.")]
-    rom_path: PathBuf,
+    rom_path: Option<PathBuf>,
     #[structopt(
         short = "f",
         default_value = "60",
         help = "Frames-per-second of the emulator."
     )]
     fps: u64,
-    #[structopt(name = "display", default_value = "Terminal", help = "Display mode.")]
-    display: DisplayKind,
+    #[structopt(
+        long = "ips",
+        default_value = "700",
+        help = "Instructions-per-second, the speed of the emulated CPU."
+    )]
+    ips: u64,
+    #[structopt(
+        name = "display",
+        default_value = "terminal",
+        help = "Display mode, see --list-displays for the choices."
+    )]
+    display: String,
+    #[structopt(
+        long = "display-opt",
+        number_of_values = 1,
+        help = "An option for the display, as key=value. May be given more than once."
+    )]
+    display_options: Vec<DisplayOption>,
+    #[structopt(long = "list-displays", help = "List the display modes and their options.")]
+    list_displays: bool,
+    #[structopt(
+        long = "quirks",
+        default_value = "default",
+        help = "Interpretation of ambiguous opcodes: one of default, vip, schip or xochip."
+    )]
+    quirks: Quirks,
+    #[structopt(
+        long = "load-state",
+        help = "Path to a save state to restore before starting the emulator."
+    )]
+    load_state: Option<PathBuf>,
+    #[structopt(
+        long = "rewind",
+        default_value = "10",
+        help = "Seconds of play kept for rewinding with backspace, or 0 to disable rewinding."
+    )]
+    rewind: u64,
+    #[structopt(
+        long = "keymap",
+        help = "Keys to remap, such as \"5=up,8=down\", applied after the keymap file."
+    )]
+    keymap: Option<String>,
+    #[structopt(
+        long = "keymap-file",
+        help = "Keymap config file, by default ~/.config/chip-again/keymap if it exists."
+    )]
+    keymap_file: Option<PathBuf>,
+    #[structopt(
+        long = "hold",
+        default_value = "600",
+        help = "Milliseconds a key stays held after being pressed, since terminals can't report releases. Keep this above the terminal's key repeat delay, usually about 500ms, or held keys will seem to be released before the repeats arrive."
+    )]
+    hold: u64,
+    #[structopt(
+        long = "audio",
+        default_value = "bell",
+        help = "Audio backend: one of none, bell, device or wav."
+    )]
+    audio: AudioKind,
+    #[structopt(
+        long = "wav-path",
+        help = "File written by the wav audio backend, by default the ROM path with a .wav extension."
+    )]
+    wav_path: Option<PathBuf>,
+    #[structopt(
+        long = "frequency",
+        default_value = "440",
+        help = "Pitch of the tone, in hertz."
+    )]
+    frequency: f32,
+    #[structopt(
+        long = "waveform",
+        default_value = "square",
+        help = "Shape of the tone: one of square, triangle, sawtooth or sine."
+    )]
+    waveform: Waveform,
+    #[structopt(
+        long = "volume",
+        default_value = "0.25",
+        help = "Loudness of the tone, from 0 to 1."
+    )]
+    volume: f32,
+    #[structopt(
+        long = "screenshot-at-frame",
+        help = "Save a screenshot once this many frames have been shown."
+    )]
+    screenshot_at_frame: Option<u64>,
+    #[structopt(
+        long = "screenshot-path",
+        help = "Where to save screenshots, as PNG or with a .ppm extension PPM. By default beside the ROM."
+    )]
+    screenshot_path: Option<PathBuf>,
+    #[structopt(
+        long = "screenshot-scale",
+        default_value = "8",
+        help = "Width and height in image pixels of each pixel of a screenshot."
+    )]
+    screenshot_scale: usize,
+    #[structopt(
+        long = "screenshot-theme",
+        default_value = "classic",
+        help = "Colours of screenshots, as taken by the theme display option."
+    )]
+    screenshot_theme: Theme,
+    #[structopt(
+        long = "record",
+        help = "Record a video from the start, as GIF or with a .y4m extension Y4M."
+    )]
+    record: Option<PathBuf>,
+    #[structopt(
+        long = "record-scale",
+        default_value = "4",
+        help = "Width and height in video pixels of each high resolution pixel of a recording."
+    )]
+    record_scale: usize,
+    #[structopt(
+        long = "record-theme",
+        default_value = "classic",
+        help = "Colours of recordings, as taken by the theme display option."
+    )]
+    record_theme: Theme,
+    #[structopt(
+        long = "seed",
+        help = "Seed for the random number generator, so runs given the same input turn out the same."
+    )]
+    seed: Option<u64>,
+    #[structopt(
+        long = "record-movie",
+        conflicts_with_all = &["replay", "load-state", "debug"],
+        help = "Record the input of every frame to a movie file, to replay later."
+    )]
+    record_movie: Option<PathBuf>,
+    #[structopt(
+        long = "replay",
+        conflicts_with_all = &["load-state", "debug"],
+        help = "Replay a movie file, checking that the run ends exactly as it was recorded."
+    )]
+    replay: Option<PathBuf>,
+    #[structopt(
+        long = "debug",
+        help = "Start paused in the interactive debugger, shown beside the display."
+    )]
+    debug: bool,
+    #[structopt(
+        long = "on-fault",
+        default_value = "halt",
+        help = "What to do when the program faults: one of halt, ignore or debug."
+    )]
+    on_fault: FaultPolicy,
+    #[structopt(
+        long = "headless",
+        conflicts_with_all = &["debug", "record", "record-movie", "replay"],
+        requires = "headless-limit",
+        help = "Run without a terminal until --frames or --until-pc stops it, then print the final screen, registers and state hash."
+    )]
+    headless: bool,
+    #[structopt(
+        long = "frames",
+        requires = "headless",
+        group = "headless-limit",
+        help = "Number of frames to run for with --headless."
+    )]
+    frames: Option<u64>,
+    #[structopt(
+        long = "until-pc",
+        requires = "headless",
+        group = "headless-limit",
+        parse(try_from_str = parse_address),
+        help = "Stop --headless runs when the program counter reaches this hexadecimal address, including where it starts."
+    )]
+    until_pc: Option<u16>,
+    #[structopt(
+        long = "input",
+        requires = "headless",
+        help = "Script of the keys held on each frame of a --headless run."
+    )]
+    input: Option<PathBuf>,
+    #[structopt(
+        long = "screen-out",
+        requires = "headless",
+        help = "Write the final screen here instead of stdout, as an image if it ends in .png or .ppm."
+    )]
+    screen_out: Option<PathBuf>,
+    #[structopt(
+        long = "report-out",
+        requires = "headless",
+        help = "Write the registers and state hash here instead of stdout."
+    )]
+    report_out: Option<PathBuf>,
+}
+
+/// Parse a hexadecimal address, with or without a `0x` prefix.
+fn parse_address(s: &str) -> Result<u16, std::num::ParseIntError> {
+    u16::from_str_radix(s.trim_start_matches("0x").trim_start_matches("0X"), 16)
+}
+
+// Tools which are run instead of the emulator.
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Print the addresses, raw bytes and mnemonics of a ROM's instructions.
+    #[structopt(name = "disasm")]
+    Disasm {
+        #[structopt(name = "rom", help = "Path to a chip8 compatible ROM file.")]
+        rom_path: PathBuf,
+    },
+    /// Assemble a source file of mnemonics, labels and data into a ROM.
+    #[structopt(name = "assemble")]
+    Assemble {
+        #[structopt(name = "source", help = "Path to the assembly source file.")]
+        source_path: PathBuf,
+        #[structopt(
+            short = "o",
+            long = "output",
+            help = "Path to write the ROM to, by default the source path with a .ch8 extension."
+        )]
+        output: Option<PathBuf>,
+    },
 }
 
 fn main() {
+    env_logger::init();
     let opt = Opt::from_args();
+    let registry = Registry::default();
 
-    if let Err(e) = exec::main_loop(opt) {
+    let res = match &opt.command {
+        Some(Command::Disasm { rom_path }) => disasm::run(rom_path),
+        Some(Command::Assemble {
+            source_path,
+            output,
+        }) => asm::run(source_path, output.as_deref()).inspect_err(|e| {
+            if let ErrorKind::AsmErr(errors) = e {
+                for error in errors {
+                    eprintln!("{}:{}", source_path.display(), error);
+                }
+            }
+        }),
+        None if opt.list_displays => registry.list(&mut stdout()).map_err(Into::into),
+        None if opt.rom_path.is_none() => clap::Error::with_description(
+            "A ROM must be given, unless running a subcommand",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+        None if opt.fps == 0 => clap::Error::with_description(
+            "The frame rate must be at least 1",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit(),
+        None if opt.headless => {
+            let rom_path = opt.rom_path.as_deref().expect("a ROM was checked for");
+            headless::run(&opt, rom_path)
+        }
+        None => exec::main_loop(opt, &registry),
+    };
+    if let Err(e) = res {
         eprintln!("An error occurred in execution.");
-        eprintln!("{:?}", e);
+        match e {
+            // Each assembly error has already been printed beside its path, so only count them
+            ErrorKind::AsmErr(_) => eprintln!("{}", e),
+            _ => eprintln!("{:?}", e),
+        }
+        std::process::exit(1);
     }
 }
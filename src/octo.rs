@@ -0,0 +1,1253 @@
+#[cfg(feature = "cartridge")]
+use crate::error::ErrorKind;
+use crate::{
+    emulator::instruction::{encode, Instruction},
+    error::AsmError,
+    utils,
+};
+use std::collections::{HashMap, VecDeque};
+
+/// Address the ROM is loaded at. Octo always starts a program with a jump to `main` here.
+const START: u16 = 0x200;
+/// How many macro and string mode expansions one program may make, which catches macros that
+/// expand themselves forever.
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// Read the source code of the program hidden in an Octo cartridge.
+///
+/// A cartridge is a GIF whose palette repeats every colour four times, so the lowest two bits of
+/// each pixel's palette index can hold data without changing the picture. Taken four pixels at a
+/// time, most significant bits first, through every frame in turn, those bits spell out a 32-bit
+/// big-endian length, then that many bytes of JSON, whose `program` field is the source code.
+///
+/// This needs the `cartridge` feature, which is on by default.
+#[cfg(feature = "cartridge")]
+pub fn cartridge_source(gif: &[u8]) -> Result<String, ErrorKind> {
+    let invalid = |message: String| ErrorKind::CartridgeErr(message);
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options
+        .read_info(gif)
+        .map_err(|e| invalid(format!("Could not decode the GIF: {}", e)))?;
+    let mut pixels = Vec::new();
+    while let Some(frame) = decoder
+        .read_next_frame()
+        .map_err(|e| invalid(format!("Could not decode the GIF: {}", e)))?
+    {
+        pixels.extend_from_slice(&frame.buffer);
+    }
+
+    let bytes: Vec<u8> = pixels
+        .chunks_exact(4)
+        .map(|px| (px[0] & 3) << 6 | (px[1] & 3) << 4 | (px[2] & 3) << 2 | (px[3] & 3))
+        .collect();
+    if bytes.len() < 4 {
+        return Err(invalid(String::from(
+            "The image is too small to hold a program",
+        )));
+    }
+    let size = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let payload = bytes
+        .get(4..4 + size)
+        .ok_or_else(|| invalid(format!("The image is too small to hold {} bytes", size)))?;
+    let json: serde_json::Value = serde_json::from_slice(payload)
+        .map_err(|e| invalid(format!("The hidden data isn't valid JSON: {}", e)))?;
+    match json.get("program").and_then(|program| program.as_str()) {
+        Some(program) => Ok(program.to_owned()),
+        None => Err(invalid(String::from("The hidden data has no program"))),
+    }
+}
+
+/// A word of source, along with the line it came from.
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+/// Split `source` into words separated by whitespace, dropping `#` comments. Strings in double
+/// quotes are kept whole, quotes included, so they can be told apart from other words.
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (idx, line) in source.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '#' {
+                break;
+            } else {
+                let mut text = String::new();
+                if c == '"' {
+                    text.push(c);
+                    chars.next();
+                    for c in chars.by_ref() {
+                        text.push(c);
+                        if c == '"' {
+                            break;
+                        }
+                    }
+                } else {
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() {
+                            break;
+                        }
+                        text.push(c);
+                        chars.next();
+                    }
+                }
+                tokens.push(Token {
+                    text,
+                    line: idx + 1,
+                });
+            }
+        }
+    }
+    tokens
+}
+
+/// A macro, which is replaced by its body wherever it is used, with each of its arguments replaced
+/// by the words which follow its name.
+#[derive(Clone, Debug)]
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+    /// Number of times the macro has been used so far, which the body can read as `CALLS`.
+    calls: usize,
+}
+
+/// A string mode, which is replaced by its body once for each character of the string which
+/// follows its name, with `CHAR`, `INDEX` and `VALUE` replaced by the character's code, its
+/// position in the string and its position in `alphabet`.
+#[derive(Clone, Debug)]
+struct StringMode {
+    alphabet: Vec<char>,
+    body: Vec<Token>,
+}
+
+/// A place where an address will be written once the label it refers to is defined.
+#[derive(Clone, Debug)]
+struct Fixup {
+    /// Address of the first byte to write.
+    addr: u16,
+    label: String,
+    kind: FixupKind,
+    line: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum FixupKind {
+    /// The lowest 12 bits of an instruction, as in `jump` or `i :=`.
+    Nnn,
+    /// A whole 16-bit address, following `i := long`.
+    Long,
+    /// The immediate byte of `v0 :=` in `:unpack`, with the nibble above the address's high bits.
+    UnpackHigh(u8),
+    /// The immediate byte of `v1 :=` in `:unpack`, which is the low byte of the address.
+    UnpackLow,
+}
+
+/// A comparison tested by `if` and `while`.
+#[derive(Copy, Clone, Debug)]
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+    Less(u8, Operand),
+    Greater(u8, Operand),
+    LessEqual(u8, Operand),
+    GreaterEqual(u8, Operand),
+}
+
+/// The right hand side of an operation or comparison.
+#[derive(Copy, Clone, Debug)]
+enum Operand {
+    Reg(u8),
+    Byte(u8),
+}
+
+/// The state of a program being compiled.
+struct Compiler {
+    tokens: VecDeque<Token>,
+    /// The bytes of the ROM, starting from 0x200.
+    rom: Vec<u8>,
+    /// Address the next byte is written to.
+    here: u32,
+    /// Line of the last token read, for errors.
+    line: usize,
+    labels: HashMap<String, u16>,
+    consts: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    string_modes: HashMap<String, Vec<StringMode>>,
+    fixups: Vec<Fixup>,
+    /// The start of each `loop` being compiled, with the addresses of the jumps made by its
+    /// `while`s, which go to the end of the loop.
+    loops: Vec<(u16, Vec<u16>)>,
+    /// The address of the jump made by each `begin` or `else` being compiled, which goes to the
+    /// matching `else` or `end`.
+    branches: Vec<u16>,
+    expansions: usize,
+}
+
+/// Compile `source`, written in Octo, into a ROM to be loaded at 0x200.
+///
+/// Octo is the high-level CHIP-8 assembly language of the Octo IDE, which has structured `if`,
+/// `loop` and `while` statements, operators such as `v0 += 1` and `i := label`, macros, and
+/// compile-time arithmetic with `:calc`. Everything in the Octo reference manual which produces
+/// code or data is supported; the debugging directives `:breakpoint` and `:monitor` are accepted
+/// and ignored. Compilation stops at the first error.
+pub fn compile(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut compiler = Compiler {
+        tokens: tokenize(source).into(),
+        // Room for the jump to `main`, which is filled in at the end
+        rom: vec![0, 0],
+        here: START as u32 + 2,
+        line: 1,
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        string_modes: HashMap::new(),
+        fixups: Vec::new(),
+        loops: Vec::new(),
+        branches: Vec::new(),
+        expansions: 0,
+    };
+    compiler.compile().map_err(|message| AsmError {
+        line: compiler.line,
+        message,
+    })?;
+    Ok(compiler.rom)
+}
+
+impl Compiler {
+    fn compile(&mut self) -> Result<(), String> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if !self.loops.is_empty() {
+            return Err(String::from("A `loop` has no matching `again`"));
+        }
+        if !self.branches.is_empty() {
+            return Err(String::from("A `begin` has no matching `end`"));
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let addr = *self
+                .labels
+                .get(&fixup.label)
+                .ok_or_else(|| format!("`{}` is never defined", fixup.label))?;
+            self.fix(&fixup, addr)?;
+        }
+        let main = *self
+            .labels
+            .get("main")
+            .ok_or_else(|| String::from("The program has no `: main` to start from"))?;
+        let jump = encode(Instruction::Jump(jump_target(main)?));
+        self.write_at(START, &jump.to_be_bytes())
+    }
+
+    /// The next token, or an error if there are none left.
+    fn next(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .pop_front()
+            .ok_or_else(|| String::from("Unexpected end of the program"))?;
+        self.line = token.line;
+        Ok(token.text)
+    }
+
+    /// The next token, which must be `expected`.
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token == expected {
+            Ok(())
+        } else {
+            Err(format!("Expected `{}`, found `{}`", expected, token))
+        }
+    }
+
+    /// Whether the next token is `text`, without taking it.
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|token| token.text == text)
+    }
+
+    /// The next token, which must be a name which can be defined.
+    fn name(&mut self) -> Result<String, String> {
+        let name = self.next()?;
+        let reserved = self.register(&name).is_some() || parse_number(&name).is_some();
+        if reserved || name.starts_with('"') {
+            Err(format!("`{}` can't be used as a name", name))
+        } else {
+            Ok(name)
+        }
+    }
+
+    /// The register written as `text`, either `v0` to `vf` or an alias for one.
+    fn register(&self, text: &str) -> Option<u8> {
+        if let Some(&reg) = self.aliases.get(text) {
+            return Some(reg);
+        }
+        let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+        match digit.len() {
+            1 => u8::from_str_radix(digit, 16).ok(),
+            _ => None,
+        }
+    }
+
+    /// The next token, which must be a register.
+    fn next_register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.register(&token)
+            .ok_or_else(|| format!("Expected a register, found `{}`", token))
+    }
+
+    /// The value of `text` if it is a number or constant, a label which has been defined, or a
+    /// `{ ... }` expression, which is read from the tokens which follow.
+    fn value(&mut self, text: &str) -> Result<Option<f64>, String> {
+        if text == "{" {
+            return self.calc().map(Some);
+        }
+        if let Some(number) = parse_number(text) {
+            return Ok(Some(number));
+        }
+        if let Some(&value) = self.consts.get(text) {
+            return Ok(Some(value));
+        }
+        Ok(self.labels.get(text).map(|&addr| addr as f64))
+    }
+
+    /// The next token as a number from `min` to `max`, where labels must already be defined.
+    fn next_number(&mut self, min: i64, max: i64) -> Result<i64, String> {
+        let token = self.next()?;
+        let value = self
+            .value(&token)?
+            .ok_or_else(|| format!("`{}` is not a number or constant", token))?;
+        let value = value.floor() as i64;
+        if value < min || value > max {
+            Err(format!(
+                "{} is out of range, from {} to {}",
+                value, min, max
+            ))
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// The next token as a byte, which may be written as a negative number.
+    fn next_byte(&mut self) -> Result<u8, String> {
+        Ok(self.next_number(-128, 255)? as u8)
+    }
+
+    /// The next token as an address, which may be a label defined later, in which case `kind`
+    /// says where it will be written in the bytes at `addr`.
+    fn next_address(&mut self, addr: u16, kind: FixupKind, max: i64) -> Result<u16, String> {
+        let token = self.next()?;
+        match self.value(&token)? {
+            Some(value) if value >= 0.0 && value as i64 <= max => Ok(value as u16),
+            Some(value) => Err(format!("{} is out of range, from 0 to {}", value, max)),
+            None if self.register(&token).is_some() || token.starts_with('"') => {
+                Err(format!("Expected an address, found `{}`", token))
+            }
+            None => {
+                self.fixups.push(Fixup {
+                    addr,
+                    label: token,
+                    kind,
+                    line: self.line,
+                });
+                Ok(0)
+            }
+        }
+    }
+
+    /// The address the next byte is written to, which must be in memory.
+    fn here(&self) -> Result<u16, String> {
+        if self.here > u16::MAX as u32 {
+            Err(String::from("The program doesn't fit in memory"))
+        } else {
+            Ok(self.here as u16)
+        }
+    }
+
+    /// Write `bytes` at `addr`, growing the ROM with zeroes to make room.
+    fn write_at(&mut self, addr: u16, bytes: &[u8]) -> Result<(), String> {
+        let start =
+            addr.checked_sub(START)
+                .ok_or_else(|| format!("Can't write below {:#05X}", START))? as usize;
+        if start + bytes.len() > 0x10000 - START as usize {
+            return Err(String::from("The program doesn't fit in memory"));
+        }
+        if self.rom.len() < start + bytes.len() {
+            self.rom.resize(start + bytes.len(), 0);
+        }
+        self.rom[start..start + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Write `bytes` at the current address, and move past them.
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let here = self.here()?;
+        self.write_at(here, bytes)?;
+        self.here += bytes.len() as u32;
+        Ok(())
+    }
+
+    fn inst(&mut self, instruction: Instruction) -> Result<(), String> {
+        self.emit(&encode(instruction).to_be_bytes())
+    }
+
+    /// Fill in the address `addr` where `fixup` needs it.
+    fn fix(&mut self, fixup: &Fixup, addr: u16) -> Result<(), String> {
+        let start = (fixup.addr - START) as usize;
+        match fixup.kind {
+            FixupKind::Nnn => {
+                if addr > 0xFFF {
+                    return Err(format!("`{}` is above {:#05X}", fixup.label, 0xFFF));
+                }
+                self.rom[start] = (self.rom[start] & 0xF0) | (addr >> 8) as u8;
+                self.rom[start + 1] = addr as u8;
+            }
+            FixupKind::Long => self.rom[start..start + 2].copy_from_slice(&addr.to_be_bytes()),
+            FixupKind::UnpackHigh(nibble) => self.rom[start + 1] = nibble << 4 | (addr >> 8) as u8,
+            FixupKind::UnpackLow => self.rom[start + 1] = addr as u8,
+        }
+        Ok(())
+    }
+
+    /// Make `name` refer to the current address.
+    fn define_label(&mut self, name: String) -> Result<(), String> {
+        let here = self.here()?;
+        if self.labels.insert(name.clone(), here).is_some() {
+            return Err(format!("`{}` is defined more than once", name));
+        }
+        Ok(())
+    }
+
+    /// Take the tokens up to the `}` matching a `{` which has already been read.
+    fn block(&mut self) -> Result<Vec<Token>, String> {
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self
+                .tokens
+                .pop_front()
+                .ok_or_else(|| String::from("A `{` has no matching `}`"))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Ok(body);
+            }
+            body.push(token);
+        }
+    }
+
+    /// Put `tokens` back at the front of the queue, to be compiled next.
+    fn expand(&mut self, tokens: Vec<Token>) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(String::from(
+                "Too many macro expansions, which may never end",
+            ));
+        }
+        for token in tokens.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    /// Evaluate a `{ ... }` expression, whose `{` has already been read.
+    fn calc(&mut self) -> Result<f64, String> {
+        let tokens: Vec<String> = self.block()?.into_iter().map(|t| t.text).collect();
+        let mut pos = 0;
+        let value = self.calc_expr(&tokens, &mut pos)?;
+        match tokens.get(pos) {
+            None => Ok(value),
+            Some(token) => Err(format!("Unexpected `{}` in expression", token)),
+        }
+    }
+
+    /// Evaluate the expression starting at `tokens[*pos]`. Like Octo, operators have no
+    /// precedence, and are applied from right to left, so `2 * 3 + 1` is 8.
+    fn calc_expr(&self, tokens: &[String], pos: &mut usize) -> Result<f64, String> {
+        let lhs = self.calc_term(tokens, pos)?;
+        let op = match tokens.get(*pos) {
+            Some(op) if op != ")" => op.clone(),
+            _ => return Ok(lhs),
+        };
+        *pos += 1;
+        let rhs = self.calc_expr(tokens, pos)?;
+        let (a, b) = (lhs, rhs);
+        let (ia, ib) = (a.floor() as i64, b.floor() as i64);
+        let bool = |cond: bool| if cond { 1.0 } else { 0.0 };
+        Ok(match op.as_str() {
+            "+" => a + b,
+            "-" => a - b,
+            "*" => a * b,
+            "/" if b == 0.0 => return Err(String::from("Division by zero")),
+            "/" => a / b,
+            "%" if ib == 0 => return Err(String::from("Division by zero")),
+            "%" => ia.rem_euclid(ib) as f64,
+            "&" => (ia & ib) as f64,
+            "|" => (ia | ib) as f64,
+            "^" => (ia ^ ib) as f64,
+            "<<" => (ia << ib.clamp(0, 63)) as f64,
+            ">>" => (ia >> ib.clamp(0, 63)) as f64,
+            "pow" => a.powf(b),
+            "min" => a.min(b),
+            "max" => a.max(b),
+            "<" => bool(a < b),
+            "<=" => bool(a <= b),
+            "==" => bool(a == b),
+            "!=" => bool(a != b),
+            ">=" => bool(a >= b),
+            ">" => bool(a > b),
+            _ => return Err(format!("`{}` is not an operator", op)),
+        })
+    }
+
+    /// Evaluate a single value, with any unary operators in front of it.
+    fn calc_term(&self, tokens: &[String], pos: &mut usize) -> Result<f64, String> {
+        let token = tokens
+            .get(*pos)
+            .ok_or_else(|| String::from("Expression ends too soon"))?;
+        *pos += 1;
+        let unary: Option<fn(f64) -> f64> = match token.as_str() {
+            "-" => Some(|x| -x),
+            "~" => Some(|x| !(x.floor() as i64) as f64),
+            "!" => Some(|x| if x == 0.0 { 1.0 } else { 0.0 }),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(|x| if x == 0.0 { 0.0 } else { x.signum() }),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(op) = unary {
+            return Ok(op(self.calc_term(tokens, pos)?));
+        }
+        match token.as_str() {
+            "(" => {
+                let value = self.calc_expr(tokens, pos)?;
+                match tokens.get(*pos) {
+                    Some(close) if close == ")" => {
+                        *pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err(String::from("A `(` has no matching `)`")),
+                }
+            }
+            "@" => {
+                // Bytes which haven't been written yet read as zero
+                let addr = self.calc_term(tokens, pos)?.floor() as i64 - START as i64;
+                let byte = match addr {
+                    addr if addr < 0 => 0,
+                    addr => *self.rom.get(addr as usize).unwrap_or(&0),
+                };
+                Ok(byte as f64)
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            text => {
+                if let Some(number) = parse_number(text) {
+                    Ok(number)
+                } else if let Some(&value) = self.consts.get(text) {
+                    Ok(value)
+                } else if let Some(&addr) = self.labels.get(text) {
+                    Ok(addr as f64)
+                } else {
+                    Err(format!("`{}` is not defined yet", text))
+                }
+            }
+        }
+    }
+
+    /// Read a condition, as in `v0 == 3`, `v1 != v2` or `v3 key`.
+    fn condition(&mut self) -> Result<Condition, String> {
+        let x = self.next_register()?;
+        let op = self.next()?;
+        if op == "key" {
+            return Ok(Condition::Key(x));
+        } else if op == "-key" {
+            return Ok(Condition::NotKey(x));
+        }
+        let rhs = self.next()?;
+        let rhs = match self.register(&rhs) {
+            Some(y) => Operand::Reg(y),
+            None => {
+                self.tokens.push_front(Token {
+                    text: rhs,
+                    line: self.line,
+                });
+                Operand::Byte(self.next_byte()?)
+            }
+        };
+        Ok(match op.as_str() {
+            "==" => Condition::Equal(x, rhs),
+            "!=" => Condition::NotEqual(x, rhs),
+            "<" => Condition::Less(x, rhs),
+            ">" => Condition::Greater(x, rhs),
+            "<=" => Condition::LessEqual(x, rhs),
+            ">=" => Condition::GreaterEqual(x, rhs),
+            _ => return Err(format!("`{}` is not a comparison", op)),
+        })
+    }
+
+    /// Emit instructions which skip the next one exactly when `condition` is `skip_when`.
+    fn skip(&mut self, condition: Condition, skip_when: bool) -> Result<(), String> {
+        use Instruction::*;
+        let equal = |x, rhs, skip_if_equal| match (rhs, skip_if_equal) {
+            (Operand::Reg(y), true) => SkipRegEqual(x, y),
+            (Operand::Reg(y), false) => SkipRegNotEqual(x, y),
+            (Operand::Byte(kk), true) => SkipEqual(x, kk),
+            (Operand::Byte(kk), false) => SkipNotEqual(x, kk),
+        };
+        let (x, rhs, swap, true_flag) = match condition {
+            Condition::Equal(x, rhs) => return self.inst(equal(x, rhs, skip_when)),
+            Condition::NotEqual(x, rhs) => return self.inst(equal(x, rhs, !skip_when)),
+            Condition::Key(x) if skip_when => return self.inst(SkipPressed(x)),
+            Condition::Key(x) => return self.inst(SkipNotPressed(x)),
+            Condition::NotKey(x) if skip_when => return self.inst(SkipNotPressed(x)),
+            Condition::NotKey(x) => return self.inst(SkipPressed(x)),
+            // VF is left as whether x >= rhs, or rhs >= x when swapped, and the condition holds
+            // when it equals the flag given here
+            Condition::Less(x, rhs) => (x, rhs, false, 0),
+            Condition::GreaterEqual(x, rhs) => (x, rhs, false, 1),
+            Condition::Greater(x, rhs) => (x, rhs, true, 0),
+            Condition::LessEqual(x, rhs) => (x, rhs, true, 1),
+        };
+        match (rhs, swap) {
+            (Operand::Reg(y), false) => {
+                self.inst(Move(0xF, x))?;
+                self.inst(Sub(0xF, y))?;
+            }
+            (Operand::Reg(y), true) => {
+                self.inst(Move(0xF, y))?;
+                self.inst(Sub(0xF, x))?;
+            }
+            (Operand::Byte(kk), false) => {
+                self.inst(Load(0xF, kk))?;
+                self.inst(SubN(0xF, x))?;
+            }
+            (Operand::Byte(kk), true) => {
+                self.inst(Load(0xF, kk))?;
+                self.inst(Sub(0xF, x))?;
+            }
+        }
+        if skip_when {
+            self.inst(SkipEqual(0xF, true_flag))
+        } else {
+            self.inst(SkipNotEqual(0xF, true_flag))
+        }
+    }
+
+    /// Emit a jump whose target is filled in later by `patch_jump`, returning its address.
+    fn jump_placeholder(&mut self) -> Result<u16, String> {
+        let addr = self.here()?;
+        self.inst(Instruction::Jump(0))?;
+        Ok(addr)
+    }
+
+    /// Make the jump at `addr` go to the current address.
+    fn patch_jump(&mut self, addr: u16) -> Result<(), String> {
+        let here = jump_target(self.here()?)?;
+        self.write_at(addr, &encode(Instruction::Jump(here)).to_be_bytes())
+    }
+
+    /// Compile one statement.
+    fn statement(&mut self) -> Result<(), String> {
+        use Instruction::*;
+        let token = self.next()?;
+        match token.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.define_label(name)
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let reg = match self.next()? {
+                    ref brace if brace == "{" => self.calc()? as i64,
+                    reg => self
+                        .register(&reg)
+                        .ok_or_else(|| format!("Expected a register, found `{}`", reg))?
+                        as i64,
+                };
+                if !(0..16).contains(&reg) {
+                    return Err(format!("{} is not a register", reg));
+                }
+                self.aliases.insert(name, reg as u8);
+                Ok(())
+            }
+            ":const" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                let value = self
+                    .value(&token)?
+                    .ok_or_else(|| format!("`{}` is not a number or constant", token))?;
+                self.consts.insert(name, value);
+                Ok(())
+            }
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.consts.insert(name, value);
+                Ok(())
+            }
+            ":unpack" => {
+                // `:unpack long` has no nibble above the address, so it may use all 16 bits
+                let (nibble, max) = match self.next()? {
+                    ref long if long == "long" => (0, 0xFFFF),
+                    nibble => {
+                        self.tokens.push_front(Token {
+                            text: nibble,
+                            line: self.line,
+                        });
+                        (self.next_number(0, 15)? as u8, 0xFFF)
+                    }
+                };
+                let here = self.here()?;
+                let fixups = self.fixups.len();
+                let addr = self.next_address(here, FixupKind::UnpackHigh(nibble), max)?;
+                if self.fixups.len() > fixups {
+                    // The address is split across both instructions, so each needs filling in
+                    let mut low = self.fixups[fixups].clone();
+                    low.addr += 2;
+                    low.kind = FixupKind::UnpackLow;
+                    self.fixups.push(low);
+                }
+                self.inst(Load(0x0, nibble << 4 | (addr >> 8) as u8))?;
+                self.inst(Load(0x1, addr as u8))
+            }
+            ":next" => {
+                let name = self.name()?;
+                let here = self.here()? + 1;
+                if self.labels.insert(name.clone(), here).is_some() {
+                    return Err(format!("`{}` is defined more than once", name));
+                }
+                Ok(())
+            }
+            ":org" => {
+                self.here = self.next_number(START as i64, 0xFFFF)? as u32;
+                Ok(())
+            }
+            ":byte" => {
+                let byte = self.next_byte()?;
+                self.emit(&[byte])
+            }
+            ":pointer" => {
+                let here = self.here()?;
+                let addr = self.next_address(here, FixupKind::Long, 0xFFFF)?;
+                self.emit(&addr.to_be_bytes())
+            }
+            ":call" => {
+                let here = self.here()?;
+                let addr = self.next_address(here, FixupKind::Nnn, 0xFFF)?;
+                self.inst(Call(addr))
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut args = Vec::new();
+                loop {
+                    match self.next()? {
+                        ref brace if brace == "{" => break,
+                        arg => args.push(arg),
+                    }
+                }
+                let body = self.block()?;
+                self.macros.insert(
+                    name,
+                    Macro {
+                        args,
+                        body,
+                        calls: 0,
+                    },
+                );
+                Ok(())
+            }
+            ":stringmode" => {
+                let name = self.name()?;
+                let alphabet = self.next()?;
+                let alphabet = string_contents(&alphabet)
+                    .ok_or_else(|| format!("Expected a string, found `{}`", alphabet))?;
+                self.expect("{")?;
+                let body = self.block()?;
+                self.string_modes.entry(name).or_default().push(StringMode {
+                    alphabet: alphabet.chars().collect(),
+                    body,
+                });
+                Ok(())
+            }
+            ":assert" => {
+                let message = if self.tokens.front().is_some_and(|t| t.text.starts_with('"')) {
+                    let message = self.next()?;
+                    string_contents(&message).unwrap_or_default().to_owned()
+                } else {
+                    String::from("Assertion failed")
+                };
+                self.expect("{")?;
+                if self.calc()? == 0.0 {
+                    return Err(message);
+                }
+                Ok(())
+            }
+            ":breakpoint" => self.next().map(|_| ()),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(|_| ())
+            }
+            ";" | "return" => self.inst(Return),
+            "clear" => self.inst(Clear),
+            "hires" => self.inst(HighRes),
+            "lores" => self.inst(LowRes),
+            "exit" => self.inst(Exit),
+            "scroll-down" => {
+                let n = self.next_number(0, 15)? as u8;
+                self.inst(ScrollDown(n))
+            }
+            "scroll-up" => {
+                let n = self.next_number(0, 15)? as u8;
+                self.inst(ScrollUp(n))
+            }
+            "scroll-right" => self.inst(ScrollRight),
+            "scroll-left" => self.inst(ScrollLeft),
+            "bcd" => {
+                let x = self.next_register()?;
+                self.inst(Bcd(x))
+            }
+            "save" | "load" => {
+                let x = self.next_register()?;
+                if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.next_register()?;
+                    match token.as_str() {
+                        "save" => self.inst(SaveRange(x, y)),
+                        _ => self.inst(LoadRange(x, y)),
+                    }
+                } else {
+                    match token.as_str() {
+                        "save" => self.inst(Store(x)),
+                        _ => self.inst(Restore(x)),
+                    }
+                }
+            }
+            "saveflags" => {
+                let x = self.next_register()?;
+                self.inst(SaveFlags(x))
+            }
+            "loadflags" => {
+                let x = self.next_register()?;
+                self.inst(LoadFlags(x))
+            }
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let n = self.next_number(0, 15)? as u8;
+                self.inst(Draw(x, y, n))
+            }
+            "plane" => {
+                let n = self.next_number(0, 3)? as u8;
+                self.inst(Plane(n))
+            }
+            "audio" => self.inst(LoadAudio),
+            "jump" | "jump0" | "native" => {
+                let here = self.here()?;
+                let addr = self.next_address(here, FixupKind::Nnn, 0xFFF)?;
+                match token.as_str() {
+                    "jump" => self.inst(Jump(addr)),
+                    "jump0" => self.inst(JumpOffset(addr)),
+                    _ => self.emit(&(addr & 0xFFF).to_be_bytes()),
+                }
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.next_register()?;
+                match token.as_str() {
+                    "delay" => self.inst(SetDelay(x)),
+                    "buzzer" => self.inst(SetSound(x)),
+                    _ => self.inst(Pitch(x)),
+                }
+            }
+            "i" => self.i_statement(),
+            "if" => {
+                let condition = self.condition()?;
+                match self.next()?.as_str() {
+                    "then" => self.skip(condition, false),
+                    "begin" => {
+                        self.skip(condition, true)?;
+                        let jump = self.jump_placeholder()?;
+                        self.branches.push(jump);
+                        Ok(())
+                    }
+                    other => Err(format!("Expected `then` or `begin`, found `{}`", other)),
+                }
+            }
+            "else" => {
+                let jump = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| String::from("`else` without `begin`"))?;
+                let end = self.jump_placeholder()?;
+                self.patch_jump(jump)?;
+                self.branches.push(end);
+                Ok(())
+            }
+            "end" => {
+                let jump = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| String::from("`end` without `begin`"))?;
+                self.patch_jump(jump)
+            }
+            "loop" => {
+                let here = self.here()?;
+                self.loops.push((here, Vec::new()));
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition()?;
+                self.skip(condition, true)?;
+                let jump = self.jump_placeholder()?;
+                self.loops
+                    .last_mut()
+                    .ok_or_else(|| String::from("`while` outside a loop"))?
+                    .1
+                    .push(jump);
+                Ok(())
+            }
+            "again" => {
+                let (start, whiles) = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| String::from("`again` without `loop`"))?;
+                self.inst(Jump(jump_target(start)?))?;
+                for jump in whiles {
+                    self.patch_jump(jump)?;
+                }
+                Ok(())
+            }
+            _ => self.other_statement(token),
+        }
+    }
+
+    /// Compile a statement starting with `i`.
+    fn i_statement(&mut self) -> Result<(), String> {
+        use Instruction::*;
+        let op = self.next()?;
+        match op.as_str() {
+            "+=" => {
+                let x = self.next_register()?;
+                self.inst(AddI(x))
+            }
+            ":=" => {
+                if self.peek_is("hex") || self.peek_is("bighex") {
+                    let kind = self.next()?;
+                    let x = self.next_register()?;
+                    return match kind.as_str() {
+                        "hex" => self.inst(Font(x)),
+                        _ => self.inst(BigFont(x)),
+                    };
+                }
+                let here = self.here()?;
+                if self.peek_is("long") {
+                    self.next()?;
+                    let addr = self.next_address(here + 2, FixupKind::Long, 0xFFFF)?;
+                    self.inst(LoadLongI)?;
+                    self.emit(&addr.to_be_bytes())
+                } else {
+                    let addr = self.next_address(here, FixupKind::Nnn, 0xFFF)?;
+                    self.inst(LoadI(addr))
+                }
+            }
+            _ => Err(format!("Expected `:=` or `+=` after `i`, found `{}`", op)),
+        }
+    }
+
+    /// Compile a statement which starts with a register, macro, string mode, number or label.
+    fn other_statement(&mut self, token: String) -> Result<(), String> {
+        use Instruction::*;
+        if let Some(x) = self.register(&token) {
+            let op = self.next()?;
+            let rhs = self.next()?;
+            let y = self.register(&rhs);
+            return match (op.as_str(), y) {
+                (":=", Some(y)) => self.inst(Move(x, y)),
+                (":=", None) if rhs == "random" => {
+                    let kk = self.next_byte()?;
+                    self.inst(Random(x, kk))
+                }
+                (":=", None) if rhs == "key" => self.inst(WaitKey(x)),
+                (":=", None) if rhs == "delay" => self.inst(GetDelay(x)),
+                ("+=", Some(y)) => self.inst(Add(x, y)),
+                ("-=", Some(y)) => self.inst(Sub(x, y)),
+                ("=-", Some(y)) => self.inst(SubN(x, y)),
+                ("|=", Some(y)) => self.inst(Or(x, y)),
+                ("&=", Some(y)) => self.inst(And(x, y)),
+                ("^=", Some(y)) => self.inst(Xor(x, y)),
+                (">>=", Some(y)) => self.inst(ShiftRight(x, y)),
+                ("<<=", Some(y)) => self.inst(ShiftLeft(x, y)),
+                (":=", None) | ("+=", None) | ("-=", None) => {
+                    self.tokens.push_front(Token {
+                        text: rhs,
+                        line: self.line,
+                    });
+                    let kk = self.next_byte()?;
+                    match op.as_str() {
+                        ":=" => self.inst(Load(x, kk)),
+                        "+=" => self.inst(AddByte(x, kk)),
+                        _ => self.inst(AddByte(x, kk.wrapping_neg())),
+                    }
+                }
+                _ => Err(format!("Can't compile `{} {} {}`", token, op, rhs)),
+            };
+        }
+
+        if let Some(mac) = self.macros.get_mut(&token) {
+            let mac_args = mac.args.clone();
+            let mac_body = mac.body.clone();
+            let calls = mac.calls;
+            mac.calls += 1;
+            let mut values = HashMap::new();
+            for arg in mac_args {
+                let value = self.next()?;
+                values.insert(arg, value);
+            }
+            values.insert(String::from("CALLS"), calls.to_string());
+            let line = self.line;
+            let body = substitute(&mac_body, &values, line);
+            return self.expand(body);
+        }
+
+        if let Some(modes) = self.string_modes.get(&token).cloned() {
+            let text = self.next()?;
+            let text = string_contents(&text)
+                .ok_or_else(|| format!("Expected a string after `{}`, found `{}`", token, text))?
+                .to_owned();
+            let line = self.line;
+            let mut body = Vec::new();
+            for (index, c) in text.chars().enumerate() {
+                let (mode, value) = modes
+                    .iter()
+                    .find_map(|mode| Some((mode, mode.alphabet.iter().position(|&a| a == c)?)))
+                    .ok_or_else(|| format!("`{}` has no character {:?}", token, c))?;
+                let mut values = HashMap::new();
+                values.insert(String::from("CHAR"), (c as u32).to_string());
+                values.insert(String::from("INDEX"), index.to_string());
+                values.insert(String::from("VALUE"), value.to_string());
+                body.extend(substitute(&mode.body, &values, line));
+            }
+            return self.expand(body);
+        }
+
+        if token == "{" || parse_number(&token).is_some() || self.consts.contains_key(&token) {
+            // Numbers and constants on their own are bytes of data
+            self.tokens.push_front(Token {
+                text: token,
+                line: self.line,
+            });
+            let byte = self.next_byte()?;
+            return self.emit(&[byte]);
+        }
+
+        if token.starts_with(':') || token.starts_with('"') || !is_name(&token) {
+            return Err(format!("Unknown statement `{}`", token));
+        }
+        // Anything else is a subroutine to call, which may be defined later
+        let here = self.here()?;
+        self.tokens.push_front(Token {
+            text: token,
+            line: self.line,
+        });
+        let addr = self.next_address(here, FixupKind::Nnn, 0xFFF)?;
+        self.inst(Call(addr))
+    }
+}
+
+/// `addr` if a jump can reach it, as jumps only have 12 bits for their target.
+fn jump_target(addr: u16) -> Result<u16, String> {
+    if addr > 0xFFF {
+        Err(format!("Can't jump to {:#06X}, above 0xFFF", addr))
+    } else {
+        Ok(addr)
+    }
+}
+
+/// Copy `body`, replacing every token named in `values`, and giving each the line `line`.
+fn substitute(body: &[Token], values: &HashMap<String, String>, line: usize) -> Vec<Token> {
+    body.iter()
+        .map(|token| Token {
+            text: values
+                .get(&token.text)
+                .cloned()
+                .unwrap_or_else(|| token.text.clone()),
+            line,
+        })
+        .collect()
+}
+
+/// The text inside a string token, without its quotes.
+fn string_contents(token: &str) -> Option<&str> {
+    token.strip_prefix('"')?.strip_suffix('"')
+}
+
+/// Whether `token` could be the name of a label.
+fn is_name(token: &str) -> bool {
+    token
+        .chars()
+        .all(|c| c.is_alphanumeric() || "_-.".contains(c))
+}
+
+/// Parse a number written in decimal, hex with `0x` or binary with `0b`, which may be negative.
+fn parse_number(text: &str) -> Option<f64> {
+    match text.strip_prefix('-') {
+        Some(digits) => utils::parse_number(digits).map(|value| -value as f64),
+        None => utils::parse_number(text).map(|value| value as f64),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::{Quirks, State};
+
+    #[test]
+    fn test_compile() {
+        let source = "
+            # Count v0 up to 3, calling a subroutine each time
+            : main
+                v0 := 0
+                loop
+                    count
+                    if v0 == 3 then jump done
+                again
+            : done
+                i := sprite
+                exit
+            : count v0 += 1 ;
+            : sprite 0xF0 0b1001 -1
+        ";
+        let rom = compile(source).unwrap();
+        assert_eq!(
+            vec![
+                0x12, 0x02, 0x60, 0x00, 0x22, 0x10, 0x40, 0x03, 0x12, 0x0C, 0x12, 0x04, 0xA2, 0x14,
+                0x00, 0xFD, 0x70, 0x01, 0x00, 0xEE, 0xF0, 0x09, 0xFF
+            ],
+            rom
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let source = "
+            :alias counter v3
+            :alias limit v9
+            :const LIMIT 5
+            :calc DOUBLE { LIMIT * 2 }
+            :macro add-twice reg { reg += 1 reg += 1 }
+            : main
+                counter := 0
+                v4 := 0
+                loop
+                    limit := DOUBLE
+                    while counter < limit
+                    add-twice counter
+                    limit := LIMIT
+                    if counter >= limit begin
+                        v4 += 1
+                    else
+                        v4 += 2
+                    end
+                again
+                v5 := 200
+                if v5 > 100 then v6 := 1
+                if v5 <= 100 then v7 := 1
+                if v5 != v5 then v8 := 1
+                exit
+        ";
+        let rom = compile(source).unwrap();
+        let mut state = State::new(&rom, Quirks::default());
+        for _ in 0..200 {
+            state.step_forward().unwrap();
+        }
+        assert!(state.halted);
+        // The loop runs for counter = 2, 4, 6, 8 and 10
+        assert_eq!(10, state.reg_v[0x3]);
+        assert_eq!(2 + 2 + 1 + 1 + 1, state.reg_v[0x4]);
+        assert_eq!([1, 0, 0], state.reg_v[0x6..0x9]);
+    }
+
+    #[test]
+    fn test_data() {
+        let source = "
+            :stringmode text \"ABC\" { :byte { VALUE + 1 } }
+            : main
+                :unpack 0xA data
+                i := long data
+                jump main
+            : data
+                text \"CAB\"
+                :byte { @ data + 1 }
+        ";
+        let rom = compile(source).unwrap();
+        assert_eq!(
+            vec![
+                0x12, 0x02, 0x60, 0xA2, 0x61, 0x0C, 0xF0, 0x00, 0x02, 0x0C, 0x12, 0x02, 0x03, 0x01,
+                0x02, 0x04
+            ],
+            rom[..0x10]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let line = |source| compile(source).unwrap_err().line;
+        assert_eq!(1, line(": start clear"));
+        assert_eq!(2, line(": main\n  v0 := 256"));
+        assert_eq!(3, line(": main\n  loop\n  jump nowhere"));
+        assert_eq!(2, line(": main\n  if v0 == 1 go"));
+        assert_eq!(1, line(": main : main"));
+        // Jumps can't reach code above 0xFFF
+        assert_eq!(1, line(":org 0x1000 : main clear"));
+        assert_eq!(2, line(": main\n:org 0x1000 loop clear again"));
+    }
+
+    #[test]
+    #[cfg(feature = "cartridge")]
+    fn test_cartridge() {
+        let json = br#"{"options":{},"program":": main\n  jump main"}"#;
+        let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(json);
+        // Four pixels for each byte, two bits at a time, on top of a picture in colour 1
+        let (width, height) = (32, 16);
+        let mut pixels = vec![1 << 2; width * height];
+        for (idx, byte) in payload.iter().enumerate() {
+            for bit in 0..4 {
+                pixels[idx * 4 + bit] |= (byte >> (6 - bit * 2)) & 3;
+            }
+        }
+        let palette: Vec<u8> = (0..16).flat_map(|idx| [idx * 16; 3]).collect();
+        let mut gif = Vec::new();
+        {
+            let mut encoder =
+                gif::Encoder::new(&mut gif, width as u16, height as u16, &palette).unwrap();
+            let frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, pixels, None);
+            encoder.write_frame(&frame).unwrap();
+        }
+
+        let source = cartridge_source(&gif).unwrap();
+        assert_eq!(": main\n  jump main", source);
+        assert_eq!(vec![0x12, 0x02, 0x12, 0x02], compile(&source).unwrap());
+        assert!(cartridge_source(b"GIF89a").is_err());
+    }
+}
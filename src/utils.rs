@@ -27,6 +27,40 @@ pub fn bcd(x: u8) -> [u8; 3] {
     [hundreds, tens, ones]
 }
 
+/// Hashes a slice of bytes with the 64-bit FNV-1a algorithm.
+///
+/// Unlike the hashers in the standard library, the result is guaranteed to stay the same between
+/// runs and versions of the program, so it is safe to write to disk.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    bytes
+        .iter()
+        .fold(OFFSET, |hash, &b| (hash ^ b as u64).wrapping_mul(PRIME))
+}
+
+/// Parses a number written in decimal, in hexadecimal with a `0x` prefix or in binary with a
+/// `0b` prefix, ignoring case.
+///
+/// This is shared by the assembler and the Octo compiler, which each add their own prefixes and
+/// signs on top.
+pub fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        (bin, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+    if digits.starts_with(|c: char| c.is_digit(radix)) {
+        i64::from_str_radix(digits, radix).ok()
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -42,8 +76,26 @@ mod test {
 
     #[test]
     fn test_bcd() {
-        assert_eq!([0, 0, 0], bcd(000));
+        assert_eq!([0, 0, 0], bcd(0));
         assert_eq!([1, 2, 3], bcd(123));
-        assert_eq!([0, 2, 1], bcd(021));
+        assert_eq!([0, 2, 1], bcd(21));
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(0xcbf2_9ce4_8422_2325, fnv1a(b""));
+        assert_eq!(0xaf63_dc4c_8601_ec8c, fnv1a(b"a"));
+        assert_eq!(0x8594_4171_f739_67e8, fnv1a(b"foobar"));
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(Some(42), parse_number("42"));
+        assert_eq!(Some(0xAB), parse_number("0xab"));
+        assert_eq!(Some(0xAB), parse_number("0XAB"));
+        assert_eq!(Some(5), parse_number("0b101"));
+        assert_eq!(None, parse_number("0x"));
+        assert_eq!(None, parse_number("-1"));
+        assert_eq!(None, parse_number("main"));
     }
 }
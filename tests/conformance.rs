@@ -0,0 +1,143 @@
+//! Runs every ROM in `res/`, along with any dropped into `tests/roms/`, for a fixed number of
+//! frames under each quirk profile, and compares the final screen with the golden image committed
+//! in `tests/golden/`, named after the ROM and profile, as in `pong.ch8.vip.txt`.
+//!
+//! Golden images are the screen as text, as shown by `Framebuffer`'s `Display` implementation,
+//! after a line saying how the run ended. After a change which is meant to alter what programs
+//! draw, they can be regenerated with
+//!
+//! ```text
+//! REGENERATE_GOLDENS=1 cargo test --test conformance
+//! ```
+//!
+//! and the differences reviewed before committing them. ROMs in `tests/roms/` are ignored by git,
+//! so that well-known test ROMs which can't be redistributed can still be checked locally.
+
+use chip_again::{emulator::Quirks, Emulator};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Frames each ROM runs for, 5 seconds of play at the default speed.
+const FRAMES: u64 = 300;
+
+/// The quirk profiles every ROM is run under.
+const PROFILES: &[&str] = &["default", "vip", "schip", "xochip"];
+
+/// Every ROM to test, in order of path.
+fn roms() -> Vec<PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut res = Vec::new();
+    for dir in &["res", "tests/roms"] {
+        let entries = match fs::read_dir(root.join(dir)) {
+            Ok(entries) => entries,
+            // The local ROM directory is optional
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let path = entry.expect("ROM directories should be readable").path();
+            if path.is_file() {
+                res.push(path);
+            }
+        }
+    }
+    res.sort();
+    res
+}
+
+/// Run `rom` under the quirk profile `profile`, returning the golden image of its final screen.
+fn run(rom: &[u8], profile: &str) -> String {
+    let quirks: Quirks = profile.parse().expect("profiles should be valid");
+    let mut emulator = match Emulator::new(rom, quirks) {
+        Ok(emulator) => emulator,
+        Err(e) => return format!("# Not loaded: {}\n", e),
+    };
+    emulator.seed(0);
+    let mut outcome = format!("# Ran for {} frames", FRAMES);
+    while emulator.frames() < FRAMES && !emulator.halted() {
+        if let Err(fault) = emulator.run_frame() {
+            outcome = format!("# Faulted on frame {}: {}", emulator.frames(), fault);
+            break;
+        }
+    }
+    if emulator.halted() {
+        outcome = format!("# Exited on frame {}", emulator.frames());
+    }
+    format!("{}\n{}", outcome, emulator.framebuffer())
+}
+
+/// A description of the lines which differ between the expected and actual golden images, with
+/// the row of the screen each is from.
+fn diff(expected: &str, actual: &str) -> String {
+    let (expected, actual): (Vec<_>, Vec<_>) =
+        (expected.lines().collect(), actual.lines().collect());
+    let mut res = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        let (old, new) = (expected.get(i), actual.get(i));
+        if old == new {
+            continue;
+        }
+        // The first line describes the run, and every line after it is a row of the screen
+        let label = match i {
+            0 => String::from("run"),
+            _ => format!("row {}", i - 1),
+        };
+        res += &format!("  {:>7} - {}\n", label, old.unwrap_or(&""));
+        res += &format!("  {:>7} + {}\n", "", new.unwrap_or(&""));
+    }
+    res
+}
+
+#[test]
+fn test_conformance() {
+    let regenerate = env::var_os("REGENERATE_GOLDENS").is_some();
+    let golden_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut failures = Vec::new();
+
+    for path in roms() {
+        let rom = fs::read(&path).expect("ROMs should be readable");
+        let name = path.file_name().unwrap().to_string_lossy();
+        for profile in PROFILES {
+            let actual = run(&rom, profile);
+            let golden = golden_dir.join(format!("{}.{}.txt", name, profile));
+            if regenerate {
+                fs::create_dir_all(&golden_dir).unwrap();
+                fs::write(&golden, &actual).unwrap();
+                continue;
+            }
+            match fs::read_to_string(&golden) {
+                Ok(expected) if expected == actual => {}
+                Ok(expected) => failures.push(format!(
+                    "{} under {} quirks differs from {}:\n{}",
+                    name,
+                    profile,
+                    golden.display(),
+                    diff(&expected, &actual)
+                )),
+                Err(_) => failures.push(format!(
+                    "{} under {} quirks has no golden image at {}",
+                    name,
+                    profile,
+                    golden.display()
+                )),
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}\nRun `REGENERATE_GOLDENS=1 cargo test --test conformance` to accept the changes.",
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn test_diff() {
+    let expected = "# Ran for 300 frames\n..##\n....\n";
+    let actual = "# Ran for 300 frames\n..#.\n....\n";
+    assert_eq!(
+        "    row 0 - ..##\n          + ..#.\n",
+        diff(expected, actual)
+    );
+}